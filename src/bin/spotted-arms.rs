@@ -1,41 +1,8 @@
-use clap::Parser;
-use std::net::{IpAddr, Ipv6Addr};
-use tokio::net::TcpListener;
+use clap::{CommandFactory, FromArgMatches};
+use spotted_arms::cli::{Cli, Command};
+use std::net::SocketAddr;
 use tracing::info;
 
-#[derive(Debug, Parser)]
-#[command(
-    name = "spotted-arms",
-    version,
-    about = "Spotted Arms",
-    long_about = "Spotted Arms — an ephemeral GitHub Actions runner on Google Compute Engine"
-)]
-struct Cli {
-    /// 🚪 TCP port for the HTTP server
-    #[arg(long, short = 'p', env = "PORT", default_value_t = 3000)]
-    port: u16,
-
-    /// 🔑 GitHub credentials JSON: {"token":"...","secret":"..."}
-    #[arg(long, env = "GITHUB_CREDENTIALS")]
-    github_credentials: Option<String>,
-
-    /// 🧩 GCE region instance template name
-    #[arg(long, env = "INSTANCE_TEMPLATE")]
-    instance_template: Option<String>,
-
-    /// 🏷️ Google Cloud project ID (sets GOOGLE_CLOUD_PROJECT and GCP_PROJECT)
-    #[arg(long = "project-id", env = "GOOGLE_CLOUD_PROJECT")]
-    project_id: Option<String>,
-
-    /// 📍 Google Cloud zone (e.g., us-central1-f)
-    #[arg(long = "zone", env = "GOOGLE_CLOUD_ZONE")]
-    zone: Option<String>,
-
-    /// 📊 Cloud Trace project override for telemetry
-    #[arg(long = "telemetry-project-id", env = "PROJECT_ID")]
-    telemetry_project_id: Option<String>,
-}
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     pid1::relaunch_if_pid1()?;
@@ -43,59 +10,85 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
 
     // Parse CLI (supports environment via clap's env feature)
-    let cli = Cli::parse();
-
-    // Resolve project/region using CLI values when provided; otherwise discover
-    let (project_id, region) = if cli.project_id.is_some() || cli.zone.is_some() {
-        let discovered = if cli.project_id.is_none() || cli.zone.is_none() {
-            Some(spotted_arms::server::AppState::discover_project_region().await?)
-        } else {
-            None
-        };
-        let project_id = cli
-            .project_id
-            .clone()
-            .or_else(|| discovered.as_ref().map(|(p, _)| p.clone()))
-            .expect("project id resolution");
-        let region = if let Some(zone) = &cli.zone {
-            zone.rsplit_once('-')
-                .map(|(r, _)| r.to_string())
-                .unwrap_or_else(|| zone.clone())
-        } else {
-            discovered.unwrap().1
-        };
-        (project_id, region)
-    } else {
-        spotted_arms::server::AppState::discover_project_region().await?
-    };
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches)?;
+
+    match cli.command {
+        Some(Command::PrintConfig) => {
+            let effective = cli.effective_config(&matches).await?;
+            println!("{}", serde_json::to_string_pretty(&effective)?);
+            return Ok(());
+        }
+        Some(Command::Check) => {
+            let report = cli.check(&matches).await;
+            for outcome in &report {
+                println!("{outcome}");
+            }
+            let failed = report.iter().filter(|o| o.result.is_err()).count();
+            if failed > 0 {
+                return Err(format!("{failed} check(s) failed").into());
+            }
+            return Ok(());
+        }
+        Some(Command::Serve) | None => {}
+    }
+
+    let config = cli.resolve_config(&matches)?;
+    let (project_id, region) = cli.resolve_project_region().await?;
 
     // Initialize telemetry with optional override
-    spotted_arms::telemetry::init_tracing(cli.telemetry_project_id.clone()).await?;
-
-    // Build application state from CLI-sourced configuration
-    let creds = cli
-        .github_credentials
-        .as_deref()
-        .ok_or("Missing required --github-credentials or GITHUB_CREDENTIALS env")?;
-    let instance_template = cli
-        .instance_template
-        .as_deref()
-        .ok_or("Missing required --instance-template or INSTANCE_TEMPLATE env")?;
-
-    let state = spotted_arms::server::AppState::new_with(
-        creds,
-        project_id,
-        region,
-        instance_template.to_string(),
+    let tracing_guard = spotted_arms::telemetry::init_tracing(
+        cli.telemetry_project_id.clone(),
+        &cli.default_log_filter,
     )
     .await?;
 
+    // Build application state from CLI-sourced configuration
+    let state = cli.app_state(config, project_id, region).await?;
+    state.validate().await?;
+
+    let zones: Vec<String> = spotted_arms::instance::effective_zones(&state.region, &state.zones)
+        .ok_or_else(|| format!("Unsupported region: {}", state.region))?
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+
+    // Keep per-repo counts in line with instances GCE actually has
+    if state.repo_quotas.is_enabled() {
+        tokio::spawn(spotted_arms::quota::run_reconciler(
+            state.compute_client.clone(),
+            state.repo_quotas.clone(),
+            state.config.clone(),
+            state.project_id.clone(),
+            zones.clone(),
+        ));
+    }
+
+    // Delete runners whose completed webhook never arrived
+    if let Some(ttl) = state.config.reaper_ttl {
+        tokio::spawn(spotted_arms::reaper::run_reaper(
+            state.compute_client.clone(),
+            state.config.clone(),
+            state.project_id.clone(),
+            zones,
+            state.clock.clone(),
+            ttl,
+        ));
+    }
+
+    // dependencies are warmed in the background while `/ready` reports 503
+    if !state.readiness.is_ready() {
+        let state = state.clone();
+        tokio::spawn(async move { spotted_arms::server::warm_up(&state).await });
+    }
+
+    let lingering_deletes = state.lingering_deletes.clone();
+
     // Build app with fixed webhook path (/webhook)
     let app = spotted_arms::server::create_app(state);
 
-    let listener = TcpListener::bind((IpAddr::from(Ipv6Addr::UNSPECIFIED), cli.port))
-        .await
-        .unwrap();
+    let listener =
+        spotted_arms::server::bind_listener(SocketAddr::new(cli.bind_address, cli.port))?;
 
     info!("Starting server on {}", listener.local_addr()?);
 
@@ -104,5 +97,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .with_graceful_shutdown(spotted_arms::server::shutdown_signal())
         .await?;
 
+    // runners of failed jobs would otherwise outlive the process that was to delete them
+    let pending = lingering_deletes.pending();
+    if pending > 0 {
+        info!(pending, "Deleting lingering runners before exiting");
+        lingering_deletes.flush().await;
+    }
+
+    // the batch processor would otherwise drop the last requests' spans
+    tracing_guard.shutdown().await;
+
     Ok(())
 }
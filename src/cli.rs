@@ -0,0 +1,305 @@
+use crate::config::{Config, ConfigError};
+use crate::server::{AppState, GithubCredentials};
+use clap::{ArgMatches, Parser, Subcommand};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "spotted-arms",
+    version,
+    about = "Spotted Arms",
+    long_about = "Spotted Arms — an ephemeral GitHub Actions runner on Google Compute Engine"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// 🚪 TCP port for the HTTP server
+    #[arg(long, short = 'p', env = "PORT", default_value_t = 3000)]
+    pub port: u16,
+
+    /// 🌐 Address to listen on, e.g. `[::]` (dual-stack where available), `0.0.0.0`, or `127.0.0.1`
+    #[arg(
+        long = "bind-address",
+        env = "BIND_ADDRESS",
+        default_value = "[::]",
+        value_parser = parse_bind_address
+    )]
+    pub bind_address: IpAddr,
+
+    /// 🔑 GitHub credentials JSON: {"token":"...","secret":"..."}
+    #[arg(long, env = "GITHUB_CREDENTIALS")]
+    pub github_credentials: Option<String>,
+
+    /// 🔑 File holding the GitHub credentials JSON, kept out of process listings; wins over --github-credentials
+    #[arg(long = "github-credentials-file", env = "GITHUB_CREDENTIALS_FILE")]
+    pub github_credentials_file: Option<PathBuf>,
+
+    /// 🔑 Secret Manager secret version holding the GitHub credentials JSON, e.g. `projects/p/secrets/github/versions/latest`; wins over the other sources
+    #[arg(
+        long = "github-credentials-secret",
+        env = "GITHUB_CREDENTIALS_SECRET",
+        value_parser = crate::secrets::parse_secret_version
+    )]
+    pub github_credentials_secret: Option<String>,
+
+    /// 🧩 GCE region instance template name
+    #[arg(long, env = "INSTANCE_TEMPLATE")]
+    pub instance_template: Option<String>,
+
+    /// 🏷️ Google Cloud project ID (sets GOOGLE_CLOUD_PROJECT and GCP_PROJECT)
+    #[arg(long = "project-id", env = "GOOGLE_CLOUD_PROJECT")]
+    pub project_id: Option<String>,
+
+    /// 📍 Google Cloud zone (e.g., us-central1-f)
+    #[arg(long = "zone", env = "GOOGLE_CLOUD_ZONE")]
+    pub zone: Option<String>,
+
+    /// 🗺️ Zones to spread instances across, overriding the region's built-in list (e.g. `us-central1-a,us-central1-c`)
+    #[arg(long = "zones", env = "GCE_ZONES", value_delimiter = ',')]
+    pub zones: Vec<String>,
+
+    /// 📊 Cloud Trace project override for telemetry
+    #[arg(long = "telemetry-project-id", env = "PROJECT_ID")]
+    pub telemetry_project_id: Option<String>,
+
+    /// 🔇 Default log filter directives, overridden per target by `RUST_LOG`
+    #[arg(
+        long = "default-log-filter",
+        env = "DEFAULT_LOG_FILTER",
+        default_value = crate::telemetry::DEFAULT_LOG_FILTER
+    )]
+    pub default_log_filter: String,
+
+    /// 📄 JSON file of provisioning options; flags and env vars take precedence
+    #[arg(long = "config-file", env = "CONFIG_FILE")]
+    pub config_file: Option<PathBuf>,
+
+    /// 🔐 Bearer token required by the admin routes `POST /config/validate` and `GET /reaper/preview` (default: unauthenticated)
+    #[arg(long = "admin-token", env = "ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    #[command(flatten)]
+    pub config: Config,
+}
+
+/// Parses an IP address, accepting IPv6 in brackets as in URLs (`[::1]`)
+pub fn parse_bind_address(s: &str) -> Result<IpAddr, std::net::AddrParseError> {
+    s.strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .unwrap_or(s)
+        .parse()
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 🚀 Serve webhooks (the default)
+    Serve,
+    /// 🖨️ Print the effective configuration as JSON (secrets redacted) and exit
+    PrintConfig,
+    /// 🩺 Validate configuration, credentials and permissions, then exit non-zero on any failure
+    Check,
+}
+
+/// One step of the `check` report
+#[derive(Debug)]
+pub struct CheckOutcome {
+    pub name: &'static str,
+    /// What was verified, or why it failed
+    pub result: Result<String, String>,
+}
+
+impl std::fmt::Display for CheckOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.result {
+            Ok(detail) => write!(f, "✅ {}: {detail}", self.name),
+            Err(e) => write!(f, "❌ {}: {e}", self.name),
+        }
+    }
+}
+
+/// Records a step's outcome, yielding its value when it passed
+fn record<T, E: std::fmt::Display>(
+    report: &mut Vec<CheckOutcome>,
+    name: &'static str,
+    result: Result<T, E>,
+    detail: impl FnOnce(&T) -> String,
+) -> Option<T> {
+    let (outcome, value) = match result {
+        Ok(value) => (Ok(detail(&value)), Some(value)),
+        Err(e) => (Err(e.to_string()), None),
+    };
+    report.push(CheckOutcome {
+        name,
+        result: outcome,
+    });
+    value
+}
+
+/// Fully resolved configuration, safe to print.
+#[derive(Debug, Serialize)]
+pub struct EffectiveConfig {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub github_credentials: Option<&'static str>,
+    pub github_credentials_file: Option<PathBuf>,
+    pub github_credentials_secret: Option<String>,
+    pub instance_template: Option<String>,
+    pub project_id: String,
+    pub region: String,
+    pub zones: Vec<String>,
+    pub telemetry_project_id: Option<String>,
+    pub default_log_filter: String,
+    #[serde(flatten)]
+    pub config: Config,
+}
+
+impl Cli {
+    /// Resolve project/region using CLI values when provided; otherwise discover
+    pub async fn resolve_project_region(
+        &self,
+    ) -> Result<(String, String), Box<dyn std::error::Error>> {
+        if self.project_id.is_none() && self.zone.is_none() {
+            return crate::server::AppState::discover_project_region().await;
+        }
+
+        let discovered = if self.project_id.is_none() || self.zone.is_none() {
+            Some(crate::server::AppState::discover_project_region().await?)
+        } else {
+            None
+        };
+        let project_id = self
+            .project_id
+            .clone()
+            .or_else(|| discovered.as_ref().map(|(p, _)| p.clone()))
+            .expect("project id resolution");
+        let region = if let Some(zone) = &self.zone {
+            zone.rsplit_once('-')
+                .map(|(r, _)| r.to_string())
+                .unwrap_or_else(|| zone.clone())
+        } else {
+            discovered.unwrap().1
+        };
+        Ok((project_id, region))
+    }
+
+    /// Provisioning options from the config file (if any) overlaid with flags and env vars
+    pub fn resolve_config(&self, matches: &ArgMatches) -> Result<Config, ConfigError> {
+        let config = match &self.config_file {
+            Some(path) => Config::load(path)?.overlay(&self.config, matches)?,
+            None => self.config.clone(),
+        };
+        config.validate().map_err(ConfigError::Rejected)?;
+        Ok(config)
+    }
+
+    /// The GitHub credentials to load, preferring a secret, then a file, then inline JSON
+    pub fn github_credentials_source(&self) -> Option<GithubCredentials> {
+        self.github_credentials_secret
+            .clone()
+            .map(GithubCredentials::Secret)
+            .or_else(|| {
+                self.github_credentials_file
+                    .clone()
+                    .map(GithubCredentials::File)
+            })
+            .or_else(|| self.github_credentials.clone().map(GithubCredentials::Json))
+    }
+
+    /// Builds the application state from resolved configuration, as served
+    pub async fn app_state(
+        &self,
+        config: Config,
+        project_id: String,
+        region: String,
+    ) -> Result<AppState, Box<dyn std::error::Error>> {
+        let creds = self.github_credentials_source().ok_or(
+            "Missing required --github-credentials-secret, --github-credentials-file, --github-credentials or GITHUB_CREDENTIALS env",
+        )?;
+        let instance_template = self
+            .instance_template
+            .as_deref()
+            .ok_or("Missing required --instance-template or INSTANCE_TEMPLATE env")?;
+
+        let mut state = AppState::new_with(
+            &creds,
+            project_id,
+            region,
+            self.zones.clone(),
+            instance_template.to_string(),
+            config,
+        )
+        .await?;
+        state.config_file = self.config_file.clone().map(Arc::new);
+        state.admin_token = self.admin_token.clone().map(Arc::new);
+        Ok(state)
+    }
+
+    /// Runs the startup path without serving, then probes GCP and GitHub.
+    ///
+    /// Steps stop at the first one that fails, since later ones depend on it.
+    pub async fn check(&self, matches: &ArgMatches) -> Vec<CheckOutcome> {
+        let mut report = Vec::new();
+
+        let Some(config) = record(
+            &mut report,
+            "configuration",
+            self.resolve_config(matches),
+            |_| "valid".to_string(),
+        ) else {
+            return report;
+        };
+        let Some((project_id, region)) = record(
+            &mut report,
+            "project",
+            self.resolve_project_region().await,
+            |(project_id, region)| format!("{project_id} in {region}"),
+        ) else {
+            return report;
+        };
+        let Some(state) = record(
+            &mut report,
+            "credentials",
+            self.app_state(config, project_id, region).await,
+            |_| "GitHub credentials and Compute API client loaded".to_string(),
+        ) else {
+            return report;
+        };
+
+        for (name, result) in state.check_dependencies().await {
+            let detail = match name {
+                "gcp" => format!("instance template {} readable", state.instance_template),
+                _ => "GitHub token accepted".to_string(),
+            };
+            record(&mut report, name, result, |_| detail);
+        }
+        report
+    }
+
+    /// Merges every configuration source into the effective configuration
+    pub async fn effective_config(
+        &self,
+        matches: &ArgMatches,
+    ) -> Result<EffectiveConfig, Box<dyn std::error::Error>> {
+        let config = self.resolve_config(matches)?;
+        let (project_id, region) = self.resolve_project_region().await?;
+
+        Ok(EffectiveConfig {
+            bind_address: self.bind_address,
+            port: self.port,
+            github_credentials: self.github_credentials.as_ref().map(|_| "<redacted>"),
+            github_credentials_file: self.github_credentials_file.clone(),
+            github_credentials_secret: self.github_credentials_secret.clone(),
+            instance_template: self.instance_template.clone(),
+            project_id,
+            region,
+            zones: self.zones.clone(),
+            telemetry_project_id: self.telemetry_project_id.clone(),
+            default_log_filter: self.default_log_filter.clone(),
+            config,
+        })
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt::Debug;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+
+/// Source of the current time, injected so time-dependent behavior can be tested deterministically
+pub trait Clock: Debug + Send + Sync {
+    /// Monotonic time, for intervals and expiry
+    fn now(&self) -> Instant;
+
+    /// Wall-clock time, for schedules and timestamps
+    fn system_now(&self) -> SystemTime;
+}
+
+/// The real time; `now` follows tokio's clock, so paused-time tests still control it
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that stands still until advanced, for tests
+#[derive(Debug)]
+pub struct FakeClock {
+    instant: Instant,
+    system: SystemTime,
+    elapsed: Mutex<Duration>,
+}
+
+impl FakeClock {
+    /// A clock reading `system` as the wall-clock time
+    pub fn new(system: SystemTime) -> Self {
+        Self {
+            instant: Instant::now(),
+            system,
+            elapsed: Mutex::default(),
+        }
+    }
+
+    /// Moves both readings forward by `by`
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap_or_else(PoisonError::into_inner) += by;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.instant + self.elapsed()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.system + self.elapsed()
+    }
+}
@@ -1,25 +1,186 @@
 use gcloud_sdk::GoogleRestApi;
 use gcloud_sdk::google_rest_apis::compute_v1;
 use gcloud_sdk::google_rest_apis::compute_v1::instances_api::{
-    ComputePeriodInstancesPeriodDeleteParams, ComputePeriodInstancesPeriodInsertParams,
-    compute_instances_delete, compute_instances_insert,
+    ComputePeriodInstancesPeriodDeleteParams, ComputePeriodInstancesPeriodGetParams,
+    ComputePeriodInstancesPeriodInsertParams, ComputePeriodInstancesPeriodListParams,
+    compute_instances_delete, compute_instances_get, compute_instances_insert,
+    compute_instances_list,
 };
 use gcloud_sdk::google_rest_apis::compute_v1::region_instance_templates_api::{
     ComputePeriodRegionInstanceTemplatesPeriodGetParams, compute_region_instance_templates_get,
 };
+use gcloud_sdk::google_rest_apis::compute_v1::zone_operations_api::{
+    ComputePeriodZoneOperationsPeriodGetParams, compute_zone_operations_get,
+};
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
-use tracing::instrument;
+use tokio::sync::Semaphore;
+use tracing::{info, instrument, warn};
+
+/// Interval before the first zone operation poll, doubled after every poll
+const OPERATION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest interval between zone operation polls
+const OPERATION_POLL_MAX_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Retries of a failed instance list page before the whole listing fails
+const LIST_PAGE_RETRIES: u32 = 2;
+
+/// Interval before retrying a failed instance list page, doubled after every retry
+const LIST_PAGE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, Error)]
 pub enum ComputeError {
     #[error("resource not found")]
     NotFound,
+    #[error(
+        "permission denied: {0}; grant the service account compute.instances.create and \
+         compute.instances.delete, e.g. via roles/compute.instanceAdmin.v1"
+    )]
+    PermissionDenied(String),
+    #[error("rate limited by the Compute API")]
+    RateLimited { retry_after: Option<Duration> },
+    #[error("zone resources exhausted: {0}")]
+    ResourceExhausted(String),
+    #[error("quota exceeded: {0}")]
+    QuotaExceeded(String),
+    #[error("operation {operation} still running at timeout")]
+    OperationTimeout { operation: String },
+    #[error("operation {operation} failed: {message}")]
+    OperationFailed { operation: String, message: String },
+    #[error("compute API unavailable: {0}")]
+    Unavailable(String),
     #[error("compute error: {0}")]
     Other(String),
 }
 
+impl ComputeError {
+    /// Whether GCE ran out of capacity or quota, so another zone may still succeed
+    pub fn is_zone_exhausted(&self) -> bool {
+        matches!(self, Self::ResourceExhausted(_) | Self::QuotaExceeded(_))
+    }
+}
+
+/// Classifies a GCE error reason or code that another zone may not share
+fn exhaustion_error(reason: &str, message: &str) -> Option<ComputeError> {
+    match reason {
+        // including `ZONE_RESOURCE_POOL_EXHAUSTED_WITH_DETAILS`
+        r if r.starts_with("ZONE_RESOURCE_POOL_EXHAUSTED") => {
+            Some(ComputeError::ResourceExhausted(message.to_string()))
+        }
+        "QUOTA_EXCEEDED" | "quotaExceeded" => {
+            Some(ComputeError::QuotaExceeded(message.to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Whether a Google API error reason means the caller is being throttled
+fn is_rate_limit_reason(reason: &str) -> bool {
+    matches!(reason, "rateLimitExceeded" | "userRateLimitExceeded")
+}
+
+/// The `error.errors[].reason` codes of a Google API error body
+fn error_reasons(content: &str) -> Vec<String> {
+    let Ok(body) = serde_json::from_str::<serde_json::Value>(content) else {
+        return Vec::new();
+    };
+    body.pointer("/error/errors")
+        .and_then(|errors| errors.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|e| Some(e.get("reason")?.as_str()?.to_string()))
+        .collect()
+}
+
+/// The `RetryInfo` delay of a Google API error body, e.g. `"retryDelay": "1.5s"`.
+///
+/// The generated client drops response headers, so `Retry-After` itself isn't available.
+fn retry_delay(content: &str) -> Option<Duration> {
+    let body = serde_json::from_str::<serde_json::Value>(content).ok()?;
+    body.pointer("/error/details")?
+        .as_array()?
+        .iter()
+        .filter_map(|detail| detail.get("retryDelay")?.as_str()?.strip_suffix('s'))
+        .find_map(|secs| Duration::try_from_secs_f64(secs.parse().ok()?).ok())
+}
+
+/// Converts a Compute REST error, surfacing missing IAM permissions distinctly
+fn api_error<T>(e: compute_v1::Error<T>) -> ComputeError {
+    match e {
+        compute_v1::Error::ResponseError(resp) => {
+            // quota and rate limit errors are also 403s, so they are told apart by their reason
+            let reasons = error_reasons(&resp.content);
+            if let Some(e) = reasons
+                .iter()
+                .find_map(|reason| exhaustion_error(reason, &resp.content))
+            {
+                return e;
+            }
+            let rate_limited = reasons.iter().any(|reason| is_rate_limit_reason(reason));
+            match resp.status {
+                reqwest::StatusCode::TOO_MANY_REQUESTS => ComputeError::RateLimited {
+                    retry_after: retry_delay(&resp.content),
+                },
+                reqwest::StatusCode::FORBIDDEN if rate_limited => ComputeError::RateLimited {
+                    retry_after: retry_delay(&resp.content),
+                },
+                reqwest::StatusCode::FORBIDDEN => ComputeError::PermissionDenied(resp.content),
+                status if status.is_server_error() => {
+                    ComputeError::Unavailable(compute_v1::Error::ResponseError(resp).to_string())
+                }
+                _ => ComputeError::Other(compute_v1::Error::ResponseError(resp).to_string()),
+            }
+        }
+        compute_v1::Error::Reqwest(e) => ComputeError::Unavailable(e.to_string()),
+        e => ComputeError::Other(e.to_string()),
+    }
+}
+
+/// Whether GCE is still working on the operation
+fn is_pending(operation: &compute_v1::Operation) -> bool {
+    matches!(
+        operation.status,
+        Some(compute_v1::operation::Status::Pending | compute_v1::operation::Status::Running)
+    )
+}
+
+/// Converts a finished operation into an error if it completed with errors
+fn check_operation(
+    operation: compute_v1::Operation,
+) -> Result<compute_v1::Operation, ComputeError> {
+    let Some(errors) = operation.error.as_ref().and_then(|e| e.errors.as_ref()) else {
+        return Ok(operation);
+    };
+
+    let message = errors
+        .iter()
+        .map(|e| {
+            format!(
+                "{}: {}",
+                e.code.as_deref().unwrap_or("UNKNOWN"),
+                e.message.as_deref().unwrap_or_default()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    if let Some(e) = errors
+        .iter()
+        .find_map(|e| exhaustion_error(e.code.as_deref().unwrap_or_default(), &message))
+    {
+        return Err(e);
+    }
+
+    Err(ComputeError::OperationFailed {
+        operation: operation.name.unwrap_or_default(),
+        message,
+    })
+}
+
 /// Abstraction over the subset of Google Compute API functionality we use.
 pub trait ComputeApi: Send + Sync {
     /// Low-level region instance templates get
@@ -28,66 +189,541 @@ pub trait ComputeApi: Send + Sync {
         params: ComputePeriodRegionInstanceTemplatesPeriodGetParams,
     ) -> Pin<Box<dyn Future<Output = Result<compute_v1::InstanceTemplate, ComputeError>> + Send>>;
 
-    /// Low-level instances insert
+    /// Low-level instances insert; `max_run_duration` is sent as `scheduling.maxRunDuration`
+    /// with the DELETE termination action, which the generated v1 models lack
     fn compute_instances_insert(
         &self,
         params: ComputePeriodInstancesPeriodInsertParams,
+        max_run_duration: Option<Duration>,
     ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>>;
 
+    /// Low-level instances get; a missing instance is [`ComputeError::NotFound`]
+    fn compute_instances_get(
+        &self,
+        params: ComputePeriodInstancesPeriodGetParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Instance, ComputeError>> + Send>>;
+
     /// Low-level instances delete
     fn compute_instances_delete(
         &self,
         params: ComputePeriodInstancesPeriodDeleteParams,
     ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>>;
+
+    /// Low-level zone operations get
+    fn compute_zone_operations_get(
+        &self,
+        params: ComputePeriodZoneOperationsPeriodGetParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>>;
+
+    /// Low-level instances list (a single page)
+    fn compute_instances_list(
+        &self,
+        params: ComputePeriodInstancesPeriodListParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::InstanceList, ComputeError>> + Send>>;
+
+    /// Prepares whatever the first request would otherwise set up, e.g. an access token
+    fn warm_up(&self) -> Pin<Box<dyn Future<Output = Result<(), ComputeError>> + Send + '_>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Lists every instance in a zone matching `filter`, following pagination.
+    ///
+    /// A page that fails transiently is retried with backoff, keeping the pages already fetched.
+    fn list_instances<'a>(
+        &'a self,
+        project: &str,
+        zone: &str,
+        filter: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<Vec<compute_v1::Instance>, ComputeError>> + Send + 'a>>
+    {
+        let (project, zone, filter) = (project.to_string(), zone.to_string(), filter.to_string());
+        Box::pin(async move {
+            let mut instances = Vec::new();
+            let mut page_token = None;
+            let mut pages = 0;
+            loop {
+                let mut attempt = 0;
+                let page = loop {
+                    let result = self
+                        .compute_instances_list(ComputePeriodInstancesPeriodListParams {
+                            project: project.clone(),
+                            zone: zone.clone(),
+                            filter: Some(filter.clone()),
+                            page_token: page_token.clone(),
+                            ..Default::default()
+                        })
+                        .await;
+                    // only throttling and transient failures; a bad request fails the same again
+                    let retry_after = match &result {
+                        Err(ComputeError::RateLimited { retry_after }) => *retry_after,
+                        Err(ComputeError::Unavailable(_)) => None,
+                        _ => break result,
+                    };
+                    if attempt == LIST_PAGE_RETRIES {
+                        warn!(
+                            zone,
+                            page = pages + 1,
+                            fetched = instances.len(),
+                            "Giving up listing instances after a page kept failing"
+                        );
+                        break result;
+                    }
+
+                    let delay = retry_after.unwrap_or(LIST_PAGE_RETRY_INTERVAL * 2u32.pow(attempt));
+                    attempt += 1;
+                    warn!(
+                        zone,
+                        page = pages + 1,
+                        fetched = instances.len(),
+                        attempt,
+                        error = ?result.err(),
+                        "Retrying failed instance list page"
+                    );
+                    tokio::time::sleep(delay).await;
+                }?;
+                pages += 1;
+
+                instances.extend(page.items.unwrap_or_default());
+                match page.next_page_token.filter(|t| !t.is_empty()) {
+                    Some(token) => page_token = Some(token),
+                    None => return Ok(instances),
+                }
+            }
+        })
+    }
+
+    /// Polls a zone operation until it is `DONE` or `timeout` elapses.
+    ///
+    /// Polls back off exponentially, and a rate-limited poll is retried after the next,
+    /// longer interval rather than failing the wait.
+    ///
+    /// An operation still pending at the deadline yields [`ComputeError::OperationTimeout`],
+    /// distinct from [`ComputeError::OperationFailed`] for one that finished with errors, so
+    /// callers can decide whether to keep or clean up the target resource.
+    fn wait_for_operation<'a>(
+        &'a self,
+        project: &str,
+        operation: compute_v1::Operation,
+        timeout: Duration,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send + 'a>>
+    {
+        let project = project.to_string();
+        Box::pin(async move {
+            let deadline = tokio::time::Instant::now() + timeout;
+            let name = operation.name.clone().unwrap_or_default();
+            // operations carry their zone as a URL; the API wants the bare name
+            let zone = operation
+                .zone
+                .as_deref()
+                .and_then(|z| z.rsplit('/').next())
+                .unwrap_or_default()
+                .to_string();
+
+            let mut operation = operation;
+            let mut interval = OPERATION_POLL_INTERVAL;
+            while is_pending(&operation) {
+                let now = tokio::time::Instant::now();
+                if now >= deadline {
+                    return Err(ComputeError::OperationTimeout { operation: name });
+                }
+                tokio::time::sleep(interval.min(deadline - now)).await;
+                interval = (interval * 2).min(OPERATION_POLL_MAX_INTERVAL);
+
+                match self
+                    .compute_zone_operations_get(ComputePeriodZoneOperationsPeriodGetParams {
+                        project: project.clone(),
+                        zone: zone.clone(),
+                        operation: name.clone(),
+                        ..Default::default()
+                    })
+                    .await
+                {
+                    Ok(polled) => operation = polled,
+                    Err(ComputeError::RateLimited { retry_after }) => {
+                        // wait at least as long as the API asked before the next poll
+                        interval = interval.max(retry_after.unwrap_or_default());
+                        tracing::warn!(
+                            operation = name,
+                            ?interval,
+                            "Operation poll rate limited; backing off"
+                        );
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            check_operation(operation)
+        })
+    }
+}
+
+/// Gauge of instance deletions currently running against the Compute API
+const DELETES_IN_FLIGHT: &str = "spotted_arms_instance_deletes_in_flight";
+
+/// Counts a deletion in [`DELETES_IN_FLIGHT`] until dropped
+struct DeleteInFlight;
+
+impl DeleteInFlight {
+    fn start() -> Self {
+        crate::metrics::registry().add(DELETES_IN_FLIGHT, &[], 1);
+        Self
+    }
+}
+
+impl Drop for DeleteInFlight {
+    fn drop(&mut self) {
+        crate::metrics::registry().add(DELETES_IN_FLIGHT, &[], -1);
+    }
+}
+
+/// Caps how many instance deletions run at once, whichever path deletes them; the rest
+/// queue for a permit. Other calls pass straight through.
+pub struct DeleteLimitedCompute {
+    inner: Arc<dyn ComputeApi>,
+    permits: Arc<Semaphore>,
+}
+
+impl DeleteLimitedCompute {
+    /// Wraps `inner`, allowing `limit` deletions at once
+    pub fn new(inner: Arc<dyn ComputeApi>, limit: usize) -> Self {
+        Self {
+            inner,
+            permits: Arc::new(Semaphore::new(limit.max(1))),
+        }
+    }
+}
+
+impl ComputeApi for DeleteLimitedCompute {
+    fn compute_region_instance_templates_get(
+        &self,
+        params: ComputePeriodRegionInstanceTemplatesPeriodGetParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::InstanceTemplate, ComputeError>> + Send>>
+    {
+        self.inner.compute_region_instance_templates_get(params)
+    }
+
+    fn compute_instances_insert(
+        &self,
+        params: ComputePeriodInstancesPeriodInsertParams,
+        max_run_duration: Option<Duration>,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>> {
+        self.inner
+            .compute_instances_insert(params, max_run_duration)
+    }
+
+    fn compute_instances_get(
+        &self,
+        params: ComputePeriodInstancesPeriodGetParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Instance, ComputeError>> + Send>> {
+        self.inner.compute_instances_get(params)
+    }
+
+    fn compute_instances_delete(
+        &self,
+        params: ComputePeriodInstancesPeriodDeleteParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>> {
+        let (inner, permits) = (self.inner.clone(), self.permits.clone());
+        Box::pin(async move {
+            let _permit = permits
+                .acquire_owned()
+                .await
+                .map_err(|e| ComputeError::Other(e.to_string()))?;
+            let _in_flight = DeleteInFlight::start();
+            inner.compute_instances_delete(params).await
+        })
+    }
+
+    fn compute_zone_operations_get(
+        &self,
+        params: ComputePeriodZoneOperationsPeriodGetParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>> {
+        self.inner.compute_zone_operations_get(params)
+    }
+
+    fn compute_instances_list(
+        &self,
+        params: ComputePeriodInstancesPeriodListParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::InstanceList, ComputeError>> + Send>> {
+        self.inner.compute_instances_list(params)
+    }
+
+    fn warm_up(&self) -> Pin<Box<dyn Future<Output = Result<(), ComputeError>> + Send + '_>> {
+        self.inner.warm_up()
+    }
+}
+
+/// Logs instance inserts and deletes instead of sending them, answering each with a
+/// finished synthetic operation. Reads pass straight through.
+pub struct DryRunCompute {
+    inner: Arc<dyn ComputeApi>,
+}
+
+impl DryRunCompute {
+    pub fn new(inner: Arc<dyn ComputeApi>) -> Self {
+        Self { inner }
+    }
+
+    /// A `DONE` operation, so nothing waits on it
+    fn operation(kind: &str, project: &str, zone: &str, instance: &str) -> compute_v1::Operation {
+        compute_v1::Operation {
+            name: Some(format!("dry-run-{kind}-{instance}")),
+            operation_type: Some(kind.to_string()),
+            status: Some(compute_v1::operation::Status::Done),
+            target_link: Some(format!(
+                "projects/{project}/zones/{zone}/instances/{instance}"
+            )),
+            zone: Some(format!("projects/{project}/zones/{zone}")),
+            ..Default::default()
+        }
+    }
+}
+
+impl ComputeApi for DryRunCompute {
+    fn compute_region_instance_templates_get(
+        &self,
+        params: ComputePeriodRegionInstanceTemplatesPeriodGetParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::InstanceTemplate, ComputeError>> + Send>>
+    {
+        self.inner.compute_region_instance_templates_get(params)
+    }
+
+    fn compute_instances_insert(
+        &self,
+        params: ComputePeriodInstancesPeriodInsertParams,
+        max_run_duration: Option<Duration>,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>> {
+        let request = crate::instance::redacted_insert_request(&params);
+        info!(%request, ?max_run_duration, "Dry run: skipping instance insert");
+        let instance = params.instance.and_then(|i| i.name).unwrap_or_default();
+        let operation = Self::operation("insert", &params.project, &params.zone, &instance);
+        Box::pin(async { Ok(operation) })
+    }
+
+    fn compute_instances_get(
+        &self,
+        params: ComputePeriodInstancesPeriodGetParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Instance, ComputeError>> + Send>> {
+        self.inner.compute_instances_get(params)
+    }
+
+    fn compute_instances_delete(
+        &self,
+        params: ComputePeriodInstancesPeriodDeleteParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>> {
+        info!(
+            project = params.project,
+            zone = params.zone,
+            instance = params.instance,
+            "Dry run: skipping instance delete"
+        );
+        let operation = Self::operation("delete", &params.project, &params.zone, &params.instance);
+        Box::pin(async { Ok(operation) })
+    }
+
+    fn compute_zone_operations_get(
+        &self,
+        params: ComputePeriodZoneOperationsPeriodGetParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>> {
+        self.inner.compute_zone_operations_get(params)
+    }
+
+    fn compute_instances_list(
+        &self,
+        params: ComputePeriodInstancesPeriodListParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::InstanceList, ComputeError>> + Send>> {
+        self.inner.compute_instances_list(params)
+    }
+
+    fn warm_up(&self) -> Pin<Box<dyn Future<Output = Result<(), ComputeError>> + Send + '_>> {
+        self.inner.warm_up()
+    }
+}
+
+/// Compute API base path served by `endpoint`, e.g. a Private Service Connect endpoint
+fn endpoint_base_path(endpoint: &url::Url) -> String {
+    format!("{}/compute/v1", endpoint.as_str().trim_end_matches('/'))
+}
+
+/// The JSON body of an instance insert whose scheduling also carries `maxRunDuration` and
+/// the DELETE termination action GCE requires alongside it
+fn insert_body(
+    instance: Option<&compute_v1::Instance>,
+    max_run_duration: Duration,
+) -> Result<serde_json::Value, ComputeError> {
+    let mut body = serde_json::to_value(instance.cloned().unwrap_or_default())
+        .map_err(|e| ComputeError::Other(e.to_string()))?;
+    let scheduling = body
+        .as_object_mut()
+        .ok_or_else(|| ComputeError::Other("instance isn't a JSON object".to_string()))?
+        .entry("scheduling")
+        .or_insert_with(|| serde_json::json!({}));
+    scheduling["instanceTerminationAction"] = "DELETE".into();
+    scheduling["maxRunDuration"] = serde_json::json!({
+        "seconds": max_run_duration.as_secs().to_string(),
+        "nanos": max_run_duration.subsec_nanos(),
+    });
+    Ok(body)
+}
+
+/// Posts an instance insert the way the generated client does, with the run duration added
+/// to its scheduling
+async fn insert_with_max_run_duration(
+    config: &compute_v1::configuration::Configuration,
+    params: ComputePeriodInstancesPeriodInsertParams,
+    max_run_duration: Duration,
+) -> Result<compute_v1::Operation, ComputeError> {
+    let url = format!(
+        "{}/projects/{}/zones/{}/instances",
+        config.base_path,
+        compute_v1::urlencode(&params.project),
+        compute_v1::urlencode(&params.zone),
+    );
+    let mut request = config
+        .client
+        .post(url)
+        .json(&insert_body(params.instance.as_ref(), max_run_duration)?);
+    if let Some(template) = &params.source_instance_template {
+        request = request.query(&[("sourceInstanceTemplate", template)]);
+    }
+    if let Some(request_id) = &params.request_id {
+        request = request.query(&[("requestId", request_id)]);
+    }
+    if let Some(token) = config
+        .oauth_access_token
+        .as_ref()
+        .or(config.bearer_access_token.as_ref())
+    {
+        request = request.bearer_auth(token);
+    }
+
+    // transport failures are transient, as `api_error` treats them for the generated calls
+    let response = request
+        .send()
+        .await
+        .map_err(|e| ComputeError::Unavailable(e.to_string()))?;
+    let status = response.status();
+    let content = response
+        .text()
+        .await
+        .map_err(|e| ComputeError::Unavailable(e.to_string()))?;
+    if status.is_client_error() || status.is_server_error() {
+        return Err(api_error::<()>(compute_v1::Error::ResponseError(
+            compute_v1::ResponseContent {
+                status,
+                content,
+                entity: None,
+            },
+        )));
+    }
+    serde_json::from_str(&content).map_err(|e| ComputeError::Other(e.to_string()))
 }
 
 /// Default GCP-backed implementation that wraps GoogleRestApi and builds config per call.
+#[derive(Clone)]
 pub struct ComputeClient {
     inner: std::sync::Arc<GoogleRestApi>,
+    /// Replaces the public `compute.googleapis.com` base path when set
+    base_path: Option<String>,
 }
 
 impl ComputeClient {
     pub async fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let inner = GoogleRestApi::new().await?;
-        Ok(Self {
-            inner: std::sync::Arc::new(inner),
-        })
+        Ok(Self::from_api(Arc::new(GoogleRestApi::new().await?)))
+    }
+
+    /// A client sharing `inner`'s credentials and token cache with other Google API calls
+    pub fn from_api(inner: Arc<GoogleRestApi>) -> Self {
+        Self {
+            inner,
+            base_path: None,
+        }
+    }
+
+    /// Sends Compute API requests to `endpoint` instead of `https://compute.googleapis.com`
+    pub fn with_endpoint(self, endpoint: &url::Url) -> Self {
+        Self {
+            base_path: Some(endpoint_base_path(endpoint)),
+            ..self
+        }
+    }
+
+    /// A request configuration carrying a fresh access token
+    async fn config(&self) -> Result<compute_v1::configuration::Configuration, ComputeError> {
+        let mut config = self
+            .inner
+            .create_google_compute_v1_config()
+            .await
+            .map_err(|e| ComputeError::Other(e.to_string()))?;
+        if let Some(base_path) = &self.base_path {
+            config.base_path.clone_from(base_path);
+        }
+        Ok(config)
     }
 }
 
 impl ComputeApi for ComputeClient {
+    fn warm_up(&self) -> Pin<Box<dyn Future<Output = Result<(), ComputeError>> + Send + '_>> {
+        Box::pin(async move { self.config().await.map(drop) })
+    }
+
     #[instrument(skip(self), err(Debug))]
     fn compute_region_instance_templates_get(
         &self,
         params: ComputePeriodRegionInstanceTemplatesPeriodGetParams,
     ) -> Pin<Box<dyn Future<Output = Result<compute_v1::InstanceTemplate, ComputeError>> + Send>>
     {
-        let inner = self.inner.clone();
+        let client = self.clone();
         Box::pin(async move {
-            let config = inner
-                .create_google_compute_v1_config()
-                .await
-                .map_err(|e| ComputeError::Other(e.to_string()))?;
+            let config = client.config().await?;
             compute_region_instance_templates_get(&config, params)
                 .await
-                .map_err(|e| ComputeError::Other(e.to_string()))
+                .map_err(api_error)
         })
     }
 
-    #[instrument(skip(self), err(Debug))]
+    // the params carry the JIT config in metadata, so only identifying fields are recorded
+    #[instrument(
+        skip(self, params),
+        fields(
+            project = %params.project,
+            zone = %params.zone,
+            instance = params.instance.as_ref().and_then(|i| i.name.as_deref()),
+        ),
+        err(Debug)
+    )]
     fn compute_instances_insert(
         &self,
         params: ComputePeriodInstancesPeriodInsertParams,
+        max_run_duration: Option<Duration>,
     ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>> {
-        let inner = self.inner.clone();
+        let client = self.clone();
         Box::pin(async move {
-            let config = inner
-                .create_google_compute_v1_config()
-                .await
-                .map_err(|e| ComputeError::Other(e.to_string()))?;
-            compute_instances_insert(&config, params)
-                .await
-                .map_err(|e| ComputeError::Other(e.to_string()))
+            let config = client.config().await?;
+            match max_run_duration {
+                Some(duration) => insert_with_max_run_duration(&config, params, duration).await,
+                None => compute_instances_insert(&config, params)
+                    .await
+                    .map_err(api_error),
+            }
+        })
+    }
+
+    #[instrument(skip(self), err(Debug))]
+    fn compute_instances_get(
+        &self,
+        params: ComputePeriodInstancesPeriodGetParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Instance, ComputeError>> + Send>> {
+        let client = self.clone();
+        Box::pin(async move {
+            let config = client.config().await?;
+            compute_instances_get(&config, params).await.map_err(|e| {
+                if let compute_v1::Error::ResponseError(resp) = &e
+                    && resp.status == reqwest::StatusCode::NOT_FOUND
+                {
+                    return ComputeError::NotFound;
+                }
+                api_error(e)
+            })
         })
     }
 
@@ -96,12 +732,9 @@ impl ComputeApi for ComputeClient {
         &self,
         params: ComputePeriodInstancesPeriodDeleteParams,
     ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>> {
-        let inner = self.inner.clone();
+        let client = self.clone();
         Box::pin(async move {
-            let config = inner
-                .create_google_compute_v1_config()
-                .await
-                .map_err(|e| ComputeError::Other(e.to_string()))?;
+            let config = client.config().await?;
             compute_instances_delete(&config, params)
                 .await
                 .map_err(|e| {
@@ -110,8 +743,231 @@ impl ComputeApi for ComputeClient {
                     {
                         return ComputeError::NotFound;
                     }
-                    ComputeError::Other(e.to_string())
+                    api_error(e)
                 })
         })
     }
+
+    #[instrument(skip(self), err(Debug))]
+    fn compute_zone_operations_get(
+        &self,
+        params: ComputePeriodZoneOperationsPeriodGetParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>> {
+        let client = self.clone();
+        Box::pin(async move {
+            let config = client.config().await?;
+            compute_zone_operations_get(&config, params)
+                .await
+                .map_err(api_error)
+        })
+    }
+
+    #[instrument(skip(self), err(Debug))]
+    fn compute_instances_list(
+        &self,
+        params: ComputePeriodInstancesPeriodListParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::InstanceList, ComputeError>> + Send>> {
+        let client = self.clone();
+        Box::pin(async move {
+            let config = client.config().await?;
+            compute_instances_list(&config, params)
+                .await
+                .map_err(api_error)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoint_replaces_the_public_base_path() {
+        let endpoint = "https://compute-psc.p.googleapis.com/".parse().unwrap();
+        assert_eq!(
+            endpoint_base_path(&endpoint),
+            "https://compute-psc.p.googleapis.com/compute/v1"
+        );
+
+        let endpoint = "http://10.0.0.5:8080".parse().unwrap();
+        assert_eq!(
+            endpoint_base_path(&endpoint),
+            "http://10.0.0.5:8080/compute/v1"
+        );
+    }
+
+    #[test]
+    fn forbidden_maps_to_permission_denied() {
+        let e = api_error::<()>(compute_v1::Error::ResponseError(
+            compute_v1::ResponseContent {
+                status: reqwest::StatusCode::FORBIDDEN,
+                content: "Required 'compute.instances.create' permission".to_string(),
+                entity: None,
+            },
+        ));
+
+        assert!(
+            matches!(&e, ComputeError::PermissionDenied(c) if c.contains("compute.instances.create"))
+        );
+        assert!(e.to_string().contains("roles/compute.instanceAdmin.v1"));
+
+        let e = api_error::<()>(compute_v1::Error::ResponseError(
+            compute_v1::ResponseContent {
+                status: reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+                content: String::new(),
+                entity: None,
+            },
+        ));
+        assert!(matches!(e, ComputeError::Unavailable(_)));
+
+        let e = api_error::<()>(compute_v1::Error::ResponseError(
+            compute_v1::ResponseContent {
+                status: reqwest::StatusCode::BAD_REQUEST,
+                content: String::new(),
+                entity: None,
+            },
+        ));
+        assert!(matches!(e, ComputeError::Other(_)));
+
+        let e = api_error::<()>(compute_v1::Error::ResponseError(
+            compute_v1::ResponseContent {
+                status: reqwest::StatusCode::TOO_MANY_REQUESTS,
+                content: String::new(),
+                entity: None,
+            },
+        ));
+        assert!(matches!(e, ComputeError::RateLimited { retry_after: None }));
+
+        let e = api_error::<()>(compute_v1::Error::ResponseError(
+            compute_v1::ResponseContent {
+                status: reqwest::StatusCode::TOO_MANY_REQUESTS,
+                content: r#"{"error":{"code":429,"details":[
+                    {"@type":"type.googleapis.com/google.rpc.RetryInfo","retryDelay":"1.5s"}
+                ]}}"#
+                    .to_string(),
+                entity: None,
+            },
+        ));
+        assert!(matches!(
+            e,
+            ComputeError::RateLimited { retry_after: Some(d) } if d == Duration::from_millis(1500)
+        ));
+
+        let e = api_error::<()>(compute_v1::Error::ResponseError(
+            compute_v1::ResponseContent {
+                status: reqwest::StatusCode::FORBIDDEN,
+                content: r#"{"error":{"errors":[{"reason":"quotaExceeded"}]}}"#.to_string(),
+                entity: None,
+            },
+        ));
+        assert!(matches!(e, ComputeError::QuotaExceeded(_)));
+        assert!(e.is_zone_exhausted());
+
+        let forbidden = |content: &str| {
+            api_error::<()>(compute_v1::Error::ResponseError(
+                compute_v1::ResponseContent {
+                    status: reqwest::StatusCode::FORBIDDEN,
+                    content: content.to_string(),
+                    entity: None,
+                },
+            ))
+        };
+
+        // throttling is told apart from a missing permission by its reason
+        for reason in ["rateLimitExceeded", "userRateLimitExceeded"] {
+            let e = forbidden(&format!(
+                r#"{{"error":{{"errors":[{{"reason":"{reason}"}}],"details":[
+                    {{"@type":"type.googleapis.com/google.rpc.RetryInfo","retryDelay":"2s"}}
+                ]}}}}"#
+            ));
+            assert!(
+                matches!(e, ComputeError::RateLimited { retry_after: Some(d) } if d == Duration::from_secs(2)),
+                "{reason}: {e:?}"
+            );
+        }
+
+        // the words alone, e.g. in a message, don't make an error a quota or stockout one
+        let e = forbidden(
+            r#"{"error":{"errors":[{"reason":"forbidden","message":"quota project not exhausted"}]}}"#,
+        );
+        assert!(matches!(e, ComputeError::PermissionDenied(_)), "{e:?}");
+        let e = forbidden("quota exceeded, ZONE_RESOURCE_POOL_EXHAUSTED");
+        assert!(matches!(e, ComputeError::PermissionDenied(_)), "{e:?}");
+    }
+
+    #[tokio::test]
+    async fn unreachable_insert_is_unavailable() {
+        // nothing listens on the discard port
+        let config = compute_v1::configuration::Configuration {
+            base_path: "http://127.0.0.1:9/compute/v1".to_string(),
+            ..Default::default()
+        };
+        let params = ComputePeriodInstancesPeriodInsertParams {
+            project: "test-project".to_string(),
+            zone: "us-central1-a".to_string(),
+            ..Default::default()
+        };
+
+        let e = insert_with_max_run_duration(&config, params, Duration::from_secs(60))
+            .await
+            .unwrap_err();
+        assert!(matches!(e, ComputeError::Unavailable(_)), "{e:?}");
+    }
+
+    #[test]
+    fn insert_body_adds_max_run_duration_to_the_scheduling() {
+        use compute_v1::scheduling::ProvisioningModel;
+
+        let instance = compute_v1::Instance {
+            name: Some("gha-1-2".to_string()),
+            scheduling: Some(Box::new(compute_v1::Scheduling {
+                provisioning_model: Some(ProvisioningModel::Spot),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let body = insert_body(Some(&instance), Duration::from_secs(6 * 3600)).unwrap();
+        assert_eq!(body["name"], "gha-1-2");
+        assert_eq!(
+            body["scheduling"],
+            serde_json::json!({
+                "provisioningModel": "SPOT",
+                "instanceTerminationAction": "DELETE",
+                "maxRunDuration": { "seconds": "21600", "nanos": 0 },
+            })
+        );
+
+        let body = insert_body(None, Duration::from_millis(1500)).unwrap();
+        assert_eq!(
+            body["scheduling"]["maxRunDuration"],
+            serde_json::json!({ "seconds": "1", "nanos": 500_000_000 })
+        );
+    }
+
+    #[test]
+    fn exhausted_operations_are_told_apart() {
+        let failed = |code: &str| compute_v1::Operation {
+            name: Some("operation-1".to_string()),
+            error: Some(Box::new(compute_v1::OperationError {
+                errors: Some(vec![
+                    compute_v1::ManagedInstanceLastAttemptErrorsErrorsInner {
+                        code: Some(code.to_string()),
+                        message: Some("no capacity".to_string()),
+                        ..Default::default()
+                    },
+                ]),
+            })),
+            ..Default::default()
+        };
+
+        let e = check_operation(failed("ZONE_RESOURCE_POOL_EXHAUSTED")).unwrap_err();
+        assert!(matches!(&e, ComputeError::ResourceExhausted(m) if m.contains("no capacity")));
+        assert!(e.is_zone_exhausted());
+
+        let e = check_operation(failed("QUOTA_EXCEEDED")).unwrap_err();
+        assert!(matches!(e, ComputeError::QuotaExceeded(_)));
+
+        let e = check_operation(failed("RESOURCE_NOT_READY")).unwrap_err();
+        assert!(!e.is_zone_exhausted());
+    }
 }
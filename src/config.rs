@@ -0,0 +1,1201 @@
+use crate::instance::{
+    CREATED_BY, CREATED_BY_LABEL, CreateStage, ErrorClass, JOB_ID_LABEL, REPOSITORY_LABEL,
+    RUN_ID_LABEL, SCHEMA_LABEL, SCHEMA_VERSION,
+};
+use crate::utils::{RunnerNameParts, resolve_runner_name, snapshot_resource, validate_gce_labels};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read config file {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("invalid config: {0}")]
+    Invalid(#[from] serde_json::Error),
+    #[error("invalid config: {}", .0.join("; "))]
+    Rejected(Vec<String>),
+}
+
+/// A label-based routing rule selecting the instance template for matching jobs.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Route {
+    /// Labels that must all be present on the job
+    pub labels: Vec<String>,
+    /// Region instance template used for matching jobs
+    pub template: String,
+    /// Instances created for each matching queued job
+    #[serde(default = "Route::default_instances")]
+    pub instances: usize,
+}
+
+impl Route {
+    fn default_instances() -> usize {
+        1
+    }
+}
+
+impl FromStr for Route {
+    type Err = String;
+
+    /// Parses `label1,label2=template`, optionally suffixed with `*N` to create N instances per job
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (labels, target) = s.split_once('=').ok_or_else(|| {
+            format!("route `{s}` must look like `label1,label2=template` or `label1=template*N`")
+        })?;
+        let (template, instances) = match target.rsplit_once('*') {
+            Some((template, n)) => (
+                template,
+                n.trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("route `{s}` has an invalid instance count"))?,
+            ),
+            None => (target, Route::default_instances()),
+        };
+
+        let labels = labels
+            .split(',')
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let template = template.trim();
+
+        if labels.is_empty() || template.is_empty() {
+            return Err(format!(
+                "route `{s}` needs at least one label and a template"
+            ));
+        }
+
+        Ok(Self {
+            labels,
+            template: template.to_string(),
+            instances,
+        })
+    }
+}
+
+/// A named set of labels qualifying a job for a runner; a job carrying every label of any
+/// configured group is provisioned, from the group's template when it names one.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LabelGroup {
+    pub name: String,
+    /// Labels that must all be present on the job
+    pub labels: Vec<String>,
+    /// Region instance template used for matching jobs, instead of the routes
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+impl FromStr for LabelGroup {
+    type Err = String;
+
+    /// Parses `name:label1,label2`, optionally suffixed with `=template`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, rest) = s.split_once(':').ok_or_else(|| {
+            format!(
+                "label group `{s}` must look like `name:label1,label2` or `name:label1=template`"
+            )
+        })?;
+        let (labels, template) = match rest.split_once('=') {
+            Some((labels, template)) => (labels, Some(template.trim())),
+            None => (rest, None),
+        };
+
+        let labels = labels
+            .split(',')
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let name = name.trim();
+
+        if name.is_empty() || labels.is_empty() || template == Some("") {
+            return Err(format!(
+                "label group `{s}` needs a name, at least one label and, after `=`, a template"
+            ));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            labels,
+            template: template.map(str::to_string),
+        })
+    }
+}
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// A weekly window during which queued jobs are provisioned, in a fixed UTC offset.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ProvisionWindow {
+    /// First and last day of the window, `0` being Monday; the range may wrap past Sunday
+    pub days: (u8, u8),
+    /// Minutes past midnight the window opens, inclusive
+    pub start: u16,
+    /// Minutes past midnight the window closes, exclusive
+    pub end: u16,
+    /// Offset of the window's local time from UTC, in minutes
+    pub utc_offset: i32,
+}
+
+impl ProvisionWindow {
+    /// Whether `at` falls inside the window
+    pub fn contains(&self, at: SystemTime) -> bool {
+        let secs = match at.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        } + i64::from(self.utc_offset) * 60;
+        // the epoch was a Thursday
+        let day = (secs.div_euclid(86_400) + 3).rem_euclid(7) as u8;
+        let minute = (secs.rem_euclid(86_400) / 60) as u16;
+
+        let (first, last) = self.days;
+        let on_day = if first <= last {
+            (first..=last).contains(&day)
+        } else {
+            day >= first || day <= last
+        };
+        on_day && (self.start..self.end).contains(&minute)
+    }
+}
+
+impl FromStr for ProvisionWindow {
+    type Err = String;
+
+    /// Parses `Mon-Fri 08:00-18:00`, optionally followed by a UTC offset such as `+02:00`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "schedule window `{s}` must look like `Mon-Fri 08:00-18:00` or `Sat 10:00-14:00 -05:00`"
+            )
+        };
+        let day = |d: &str| {
+            WEEKDAYS
+                .iter()
+                .position(|w| w.eq_ignore_ascii_case(d))
+                .map(|i| i as u8)
+        };
+        let minutes = |t: &str| {
+            let (h, m) = t.split_once(':')?;
+            let (h, m) = (h.parse::<u16>().ok()?, m.parse::<u16>().ok()?);
+            (h < 24 && m < 60 || h == 24 && m == 0).then_some(h * 60 + m)
+        };
+
+        let mut parts = s.split_whitespace();
+        let (days, times) = (
+            parts.next().ok_or_else(invalid)?,
+            parts.next().ok_or_else(invalid)?,
+        );
+        let utc_offset = match parts.next() {
+            None => 0,
+            Some(z) if z.eq_ignore_ascii_case("utc") || z == "Z" => 0,
+            Some(offset) => {
+                let (sign, hm) = match offset.split_at_checked(1) {
+                    Some(("+", hm)) => (1, hm),
+                    Some(("-", hm)) => (-1, hm),
+                    _ => return Err(invalid()),
+                };
+                sign * i32::from(minutes(hm).filter(|&m| m <= 18 * 60).ok_or_else(invalid)?)
+            }
+        };
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+
+        let days = match days.split_once('-') {
+            Some((first, last)) => (day(first), day(last)),
+            None => (day(days), day(days)),
+        };
+        let (Some(first), Some(last)) = days else {
+            return Err(invalid());
+        };
+        let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+        let (start, end) = (
+            minutes(start).ok_or_else(invalid)?,
+            minutes(end).ok_or_else(invalid)?,
+        );
+        if end <= start {
+            return Err(format!(
+                "schedule window `{s}` must close after it opens; split overnight windows in two"
+            ));
+        }
+
+        Ok(Self {
+            days: (first, last),
+            start,
+            end,
+            utc_offset,
+        })
+    }
+}
+
+impl TryFrom<String> for ProvisionWindow {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl std::fmt::Display for ProvisionWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (first, last) = self.days;
+        let days = if first == last {
+            WEEKDAYS[first as usize].to_string()
+        } else {
+            format!("{}-{}", WEEKDAYS[first as usize], WEEKDAYS[last as usize])
+        };
+        let sign = if self.utc_offset < 0 { '-' } else { '+' };
+        let offset = self.utc_offset.unsigned_abs();
+        write!(
+            f,
+            "{days} {:02}:{:02}-{:02}:{:02} {sign}{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60,
+            offset / 60,
+            offset % 60
+        )
+    }
+}
+
+impl From<ProvisionWindow> for String {
+    fn from(window: ProvisionWindow) -> Self {
+        window.to_string()
+    }
+}
+
+/// An allow-listed GCP project selectable with a `project=<name>` job label.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LabelProject {
+    /// Value of the job's `project=` label
+    pub name: String,
+    /// GCP project the instance is created in
+    pub project: String,
+}
+
+impl FromStr for LabelProject {
+    type Err = String;
+
+    /// Parses `name=gcp-project`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=').map(|(n, p)| (n.trim(), p.trim())) {
+            Some((name, project)) if !name.is_empty() && !project.is_empty() => Ok(Self {
+                name: name.to_string(),
+                project: project.to_string(),
+            }),
+            _ => Err(format!(
+                "project mapping `{s}` must look like `name=gcp-project`"
+            )),
+        }
+    }
+}
+
+/// A per-repository cap on concurrently provisioned instances.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RepoLimit {
+    /// Repository `owner/name`
+    pub repository: String,
+    /// Maximum instances the repository may have at once
+    pub limit: usize,
+}
+
+impl FromStr for RepoLimit {
+    type Err = String;
+
+    /// Parses `owner/repo=N`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("repo limit `{s}` must look like `owner/repo=N`");
+        let (repository, limit) = s
+            .split_once('=')
+            .map(|(r, l)| (r.trim(), l.trim()))
+            .filter(|(r, _)| !r.is_empty())
+            .ok_or_else(invalid)?;
+
+        Ok(Self {
+            repository: repository.to_string(),
+            limit: limit.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// An HTTP status returned for a class of GCE failure instead of its default.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ErrorStatus {
+    pub class: ErrorClass,
+    /// HTTP status code, e.g. `200` so GitHub doesn't redeliver
+    pub status: u16,
+}
+
+impl FromStr for ErrorStatus {
+    type Err = String;
+
+    /// Parses `class=status`, e.g. `quota-exceeded=200`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("error status `{s}` must look like `quota-exceeded=200`");
+        let (class, status) = s
+            .split_once('=')
+            .map(|(c, st)| (c.trim(), st.trim()))
+            .ok_or_else(invalid)?;
+        let class = ErrorClass::from_str(class, true).map_err(|_| {
+            let classes = ErrorClass::value_variants()
+                .iter()
+                .filter_map(|c| c.to_possible_value())
+                .map(|v| v.get_name().to_string())
+                .collect::<Vec<_>>();
+            format!(
+                "error status `{s}` names an unknown class; use one of {}",
+                classes.join(", ")
+            )
+        })?;
+        let status = status
+            .parse::<http::StatusCode>()
+            .map_err(|_| invalid())?
+            .as_u16();
+
+        Ok(Self { class, status })
+    }
+}
+
+/// Largest webhook body accepted when none is configured; `workflow_job` payloads are a few KiB
+pub const DEFAULT_MAX_WEBHOOK_BODY_BYTES: usize = 1024 * 1024;
+
+/// How long GCE operations are waited on when no timeout is configured
+pub const DEFAULT_OPERATION_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Retries of a failed runner deregistration when none is configured
+pub const DEFAULT_DEREGISTER_RETRIES: usize = 2;
+
+/// Interval between per-repo quota reconciliations when none is configured
+pub const DEFAULT_QUOTA_RECONCILE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Interval between reaper passes when none is configured
+pub const DEFAULT_REAPER_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Instance deletions run at once when no limit is configured
+pub const DEFAULT_MAX_CONCURRENT_DELETES: usize = 8;
+
+/// Delivery ids remembered for deduplication when no size is configured
+pub const DEFAULT_DELIVERY_CACHE_SIZE: usize = 10_000;
+
+/// How long a delivery id is remembered for deduplication when no TTL is configured
+pub const DEFAULT_DELIVERY_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Default run duration after which GCE deletes a runner whose `completed` webhook was lost
+pub const DEFAULT_MAX_RUN_DURATION: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Labels a job needs to be provisioned when none are configured
+pub const DEFAULT_REQUIRED_LABELS: &[&str] = &["linux", "self-hosted", "ARM64"];
+
+/// Job labels recorded on the request span when no cap is configured
+pub const DEFAULT_MAX_SPAN_LABELS: usize = 20;
+
+/// Zones listed at once while reconciling when no concurrency is configured
+pub const DEFAULT_RECONCILE_CONCURRENCY: usize = 4;
+
+/// Longest a reconciliation pass may take when no timeout is configured
+pub const DEFAULT_RECONCILE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Job label prefix selecting an allow-listed GCP project
+const PROJECT_LABEL_PREFIX: &str = "project=";
+
+/// Job label prefix naming an allow-listed instance template, overriding routing
+const TEMPLATE_LABEL_PREFIX: &str = "template=";
+
+/// What to do with a queued job that matches none of the configured routes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoRoute {
+    /// Provision from the default instance template
+    #[default]
+    Default,
+    /// Ignore the job
+    Ignore,
+}
+
+/// What to do with the `completed` event of a job cancelled before any runner picked it up.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CancelledBeforeStart {
+    /// Delete the job's instance like any other completed job
+    #[default]
+    Delete,
+    /// Skip the deletion, leaving any instance created for the job to other cleanup
+    Skip,
+}
+
+/// Which repositories' jobs get runners, by visibility.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepoVisibility {
+    /// Public and private repositories alike
+    #[default]
+    All,
+    /// Only private repositories, keeping untrusted public code off the runners
+    Private,
+    /// Only public repositories
+    Public,
+}
+
+impl RepoVisibility {
+    /// Whether a repository with the given `private` flag is allowed; one whose
+    /// visibility wasn't reported only passes `All`
+    pub fn allows(self, private: Option<bool>) -> bool {
+        match self {
+            RepoVisibility::All => true,
+            RepoVisibility::Private => private == Some(true),
+            RepoVisibility::Public => private == Some(false),
+        }
+    }
+}
+
+/// How runner registration behaves under `--dry-run`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DryRunJit {
+    /// Return a synthetic JIT config without calling GitHub, and skip deregistration
+    #[default]
+    Stub,
+    /// Register and deregister runners with GitHub as usual
+    Generate,
+}
+
+/// What to do when a queued job's instance name is already taken.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExistingInstance {
+    /// Leave the existing instance and create nothing
+    Skip,
+    /// Delete the existing instance, then create a new one
+    Replace,
+    /// Reject the job with 409 Conflict
+    Fail,
+}
+
+/// Runtime provisioning options shared by the webhook handler and the instance lifecycle.
+#[derive(Clone, Debug, Default, Args, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// 🗑️ Wait, up to the operation timeout, for instance deletions to finish before answering completed jobs
+    #[arg(long = "confirm-deletes", env = "CONFIRM_DELETES")]
+    pub confirm_deletes: bool,
+
+    /// 🚦 HTTP status returned for a class of GCE failure, e.g. `quota-exceeded=200` so GitHub doesn't redeliver (comma-separated)
+    #[arg(long = "error-status", env = "ERROR_STATUSES", value_delimiter = ',')]
+    pub error_statuses: Vec<ErrorStatus>,
+
+    /// 🏷️ Labels a job must all carry to get a runner (comma-separated; default: `linux,self-hosted,ARM64`)
+    #[arg(
+        long = "required-labels",
+        env = "REQUIRED_LABELS",
+        value_delimiter = ','
+    )]
+    pub required_labels: Option<Vec<String>>,
+
+    /// 🏷️ Label group `name:label1,label2[=template]`; a job matching any group gets a runner, replacing --required-labels (repeatable)
+    #[arg(long = "label-group", env = "LABEL_GROUPS", value_delimiter = ';')]
+    pub label_groups: Vec<LabelGroup>,
+
+    /// 🏷️ Job labels forwarded to GitHub when registering a runner (comma-separated; default: all)
+    #[arg(long = "jit-labels", env = "JIT_LABELS", value_delimiter = ',')]
+    pub jit_labels: Option<Vec<String>>,
+
+    /// 🏷️ Template metadata key listing extra runner labels (comma-separated) to register runners with, e.g. `runner-labels`
+    #[arg(long = "template-labels-key", env = "TEMPLATE_LABELS_KEY")]
+    pub template_labels_key: Option<String>,
+
+    /// 🔥 Tag instances with a network tag derived from the repository name
+    #[arg(long = "repo-network-tags", env = "REPO_NETWORK_TAGS")]
+    pub repo_network_tags: bool,
+
+    /// 🚦 Maximum webhook requests processed at once; excess requests get 503 (default: unlimited)
+    #[arg(long = "max-concurrent-requests", env = "MAX_CONCURRENT_REQUESTS")]
+    pub max_concurrent_requests: Option<usize>,
+
+    /// ⏱️ Longest a webhook request is processed before replying 504, e.g. `30s` (default: unlimited)
+    #[arg(
+        long = "request-timeout",
+        env = "REQUEST_TIMEOUT",
+        value_parser = humantime::parse_duration
+    )]
+    #[serde(with = "humantime_serde")]
+    pub request_timeout: Option<Duration>,
+
+    /// ⏳ Longest a GCE operation is waited on, e.g. `5m` (default: 2m)
+    #[arg(
+        long = "operation-timeout",
+        env = "OPERATION_TIMEOUT",
+        value_parser = humantime::parse_duration
+    )]
+    #[serde(with = "humantime_serde")]
+    pub operation_timeout: Option<Duration>,
+
+    /// 📦 Largest webhook body accepted, in bytes; larger ones get 413 before signature checks (default: 1 MiB)
+    #[arg(long = "max-webhook-body-bytes", env = "MAX_WEBHOOK_BODY_BYTES")]
+    pub max_webhook_body_bytes: Option<usize>,
+
+    /// 🧭 Routing rule `label1,label2=template`; the first rule whose labels are all on the job wins (repeatable)
+    #[arg(long = "route", env = "ROUTES", value_delimiter = ';')]
+    pub routes: Vec<Route>,
+
+    /// 🧭 Handling of queued jobs matching no route when routes are configured
+    #[arg(long = "on-no-route", env = "ON_NO_ROUTE", value_enum, default_value_t)]
+    pub on_no_route: NoRoute,
+
+    /// 🎯 Allow-listed instance templates a `template=<name>` job label selects directly, bypassing routes (comma-separated)
+    #[arg(
+        long = "label-templates",
+        env = "LABEL_TEMPLATES",
+        value_delimiter = ','
+    )]
+    pub label_templates: Vec<String>,
+
+    /// 🗂️ Allow-listed `name=gcp-project` selectable by a `project=<name>` job label (repeatable)
+    #[arg(long = "label-project", env = "LABEL_PROJECTS", value_delimiter = ';')]
+    pub label_projects: Vec<LabelProject>,
+
+    /// ⏱️ Maximum JIT runner configs generated per second; excess webhooks get 429 (default: unlimited)
+    #[arg(long = "jit-rate-limit", env = "JIT_RATE_LIMIT")]
+    pub jit_rate_limit: Option<f64>,
+
+    /// 📦 Maximum instances any one repository may have at once; excess webhooks get 429 (default: unlimited)
+    #[arg(long = "max-instances-per-repo", env = "MAX_INSTANCES_PER_REPO")]
+    pub max_instances_per_repo: Option<usize>,
+
+    /// 🧮 Most runner instances at once, across repositories; queued jobs past it get `503` (default: unlimited)
+    #[arg(long = "max-instances", env = "MAX_INSTANCES")]
+    pub max_instances: Option<usize>,
+
+    /// 📦 Per-repository instance limit `owner/repo=N`, overriding --max-instances-per-repo (repeatable)
+    #[arg(
+        long = "repo-instance-limit",
+        env = "REPO_INSTANCE_LIMITS",
+        value_delimiter = ';'
+    )]
+    pub repo_instance_limits: Vec<RepoLimit>,
+
+    /// 🔄 How often per-repo instance counts are reconciled against GCE, e.g. `5m` (default: 5m)
+    #[arg(
+        long = "quota-reconcile-interval",
+        env = "QUOTA_RECONCILE_INTERVAL",
+        value_parser = humantime::parse_duration
+    )]
+    #[serde(with = "humantime_serde")]
+    pub quota_reconcile_interval: Option<Duration>,
+
+    /// 🔄 Zones listed at once when reconciling per-repo counts, including the first pass at startup (default: 4)
+    #[arg(long = "reconcile-concurrency", env = "RECONCILE_CONCURRENCY")]
+    pub reconcile_concurrency: Option<usize>,
+
+    /// 🔄 Longest a reconciliation pass may take before it is abandoned, e.g. `30s` (default: 1m)
+    #[arg(
+        long = "reconcile-timeout",
+        env = "RECONCILE_TIMEOUT",
+        value_parser = humantime::parse_duration
+    )]
+    #[serde(with = "humantime_serde")]
+    pub reconcile_timeout: Option<Duration>,
+
+    /// 🧯 Instance deletions sent to the Compute API at once, from webhooks and the reaper alike; the rest queue (default: 8)
+    #[arg(long = "max-concurrent-deletes", env = "MAX_CONCURRENT_DELETES")]
+    pub max_concurrent_deletes: Option<usize>,
+
+    /// 🪦 Delete runner instances older than this, e.g. `12h`, in case their completed webhook was lost (default: never)
+    #[arg(
+        long = "reaper-ttl",
+        env = "REAPER_TTL",
+        value_parser = humantime::parse_duration
+    )]
+    #[serde(with = "humantime_serde")]
+    pub reaper_ttl: Option<Duration>,
+
+    /// 🪦 How often instances are checked against `--reaper-ttl`, e.g. `5m` (default: 10m)
+    #[arg(
+        long = "reaper-interval",
+        env = "REAPER_INTERVAL",
+        value_parser = humantime::parse_duration
+    )]
+    #[serde(with = "humantime_serde")]
+    pub reaper_interval: Option<Duration>,
+
+    /// 🪦 Only log the instances the reaper would delete, without deleting them
+    #[arg(long = "reaper-dry-run", env = "REAPER_DRY_RUN")]
+    pub reaper_dry_run: bool,
+
+    /// 📬 Most recent `X-GitHub-Delivery` ids remembered to drop redelivered webhooks (default: 10000)
+    #[arg(long = "delivery-cache-size", env = "DELIVERY_CACHE_SIZE")]
+    pub delivery_cache_size: Option<usize>,
+
+    /// 📬 How long a delivery id is remembered to drop redelivered webhooks, e.g. `10m` (default: 1h)
+    #[arg(
+        long = "delivery-cache-ttl",
+        env = "DELIVERY_CACHE_TTL",
+        value_parser = humantime::parse_duration
+    )]
+    #[serde(with = "humantime_serde")]
+    pub delivery_cache_ttl: Option<Duration>,
+
+    /// 🕘 Only provision queued jobs inside these weekly windows, e.g. `Mon-Fri 08:00-18:00 +02:00` (semicolon-separated; default: always)
+    #[arg(
+        long = "provision-schedule",
+        env = "PROVISION_SCHEDULE",
+        value_delimiter = ';'
+    )]
+    pub provision_schedule: Vec<ProvisionWindow>,
+
+    /// 🏷️ Most job labels recorded on the request span, the rest summarized as `+N more` (default: 20)
+    #[arg(long = "max-span-labels", env = "MAX_SPAN_LABELS")]
+    pub max_span_labels: Option<usize>,
+
+    /// 🧳 Propagate W3C `baggage` headers into request spans alongside `traceparent`
+    #[arg(long = "propagate-baggage", env = "PROPAGATE_BAGGAGE")]
+    pub propagate_baggage: bool,
+
+    /// ⚠️ Accept webhooks WITHOUT checking their HMAC signature; only behind trusted transport (e.g. mTLS)
+    #[arg(long = "no-signature-verification", env = "NO_SIGNATURE_VERIFICATION")]
+    pub no_signature_verification: bool,
+
+    /// 🔁 Instance creation failures retried once in full, reusing any registered runner (comma-separated)
+    #[arg(
+        long = "create-retry-on",
+        env = "CREATE_RETRY_ON",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub create_retry_on: Vec<CreateStage>,
+
+    /// 🧹 Retries, with backoff, of a failed runner deregistration before it is dead-lettered (default: 2)
+    #[arg(long = "deregister-retries", env = "DEREGISTER_RETRIES")]
+    pub deregister_retries: Option<usize>,
+
+    /// 🚫 Handling of completed jobs cancelled before a runner picked them up: `delete` or `skip`
+    #[arg(
+        long = "on-cancelled-before-start",
+        env = "ON_CANCELLED_BEFORE_START",
+        value_enum,
+        default_value_t
+    )]
+    pub on_cancelled_before_start: CancelledBeforeStart,
+
+    /// 🔐 Repositories whose jobs get runners, by visibility (`repository.private`)
+    #[arg(
+        long = "repo-visibility",
+        env = "REPO_VISIBILITY",
+        value_enum,
+        default_value_t
+    )]
+    pub repo_visibility: RepoVisibility,
+
+    /// 🧪 Log instance inserts and deletes instead of sending them to GCE
+    #[arg(long = "dry-run", env = "DRY_RUN")]
+    pub dry_run: bool,
+
+    /// 🧪 Under --dry-run, whether JIT configs are stubbed or really generated with GitHub
+    #[arg(long = "dry-run-jit", env = "DRY_RUN_JIT", value_enum, default_value_t)]
+    pub dry_run_jit: DryRunJit,
+
+    /// 🔍 Check whether the job's instance already exists before creating it, and what to do if so
+    #[arg(
+        long = "on-existing-instance",
+        env = "ON_EXISTING_INSTANCE",
+        value_enum
+    )]
+    pub on_existing_instance: Option<ExistingInstance>,
+
+    /// ⏲️ Record when the webhook was received in `gh-received-at` instance metadata (RFC 3339)
+    #[arg(long = "annotate-received-at", env = "ANNOTATE_RECEIVED_AT")]
+    pub annotate_received_at: bool,
+
+    /// 📝 Enable GCE guest attributes on runners, so startup scripts can report status through them
+    #[arg(long = "enable-guest-attributes", env = "ENABLE_GUEST_ATTRIBUTES")]
+    pub enable_guest_attributes: bool,
+
+    /// 🔒 Block project-wide SSH keys on runners, so only instance-level keys can log in
+    #[arg(long = "block-project-ssh-keys", env = "BLOCK_PROJECT_SSH_KEYS")]
+    pub block_project_ssh_keys: bool,
+
+    /// ⌛ Have GCE delete runners after this long via `scheduling.maxRunDuration` and the `DELETE` termination action (default: `6h`; `0s` disables)
+    #[arg(
+        long = "max-run-duration",
+        visible_alias = "max-runner-lifetime",
+        env = "MAX_RUN_DURATION",
+        value_parser = humantime::parse_duration
+    )]
+    #[serde(with = "humantime_serde", alias = "max_runner_lifetime")]
+    pub max_run_duration: Option<Duration>,
+
+    /// 🔬 Keep a failed job's runner around this long before deleting it, e.g. `30m`, so it can be inspected over SSH (default: delete immediately)
+    #[arg(
+        long = "linger-on-failure",
+        env = "LINGER_ON_FAILURE",
+        value_parser = humantime::parse_duration
+    )]
+    #[serde(with = "humantime_serde")]
+    pub linger_on_failure: Option<Duration>,
+
+    /// 📸 Boot runners from this disk snapshot instead of the template's boot image, e.g. `golden-1` or `projects/p/global/snapshots/golden-1`
+    #[arg(long = "boot-disk-snapshot", env = "BOOT_DISK_SNAPSHOT")]
+    pub boot_disk_snapshot: Option<String>,
+
+    /// 🪪 Runner name registered with GitHub, from `{instance}`, `{zone}`, `{project}`, `{repo}` and `{hostname}` (default: `{instance}`)
+    #[arg(long = "runner-name", env = "RUNNER_NAME")]
+    pub runner_name: Option<String>,
+
+    /// 🔁 Diagnostic mode: reply to verified webhooks with their parsed fields as JSON instead of provisioning
+    #[arg(long = "echo-mode", env = "ECHO_MODE")]
+    pub echo_mode: bool,
+
+    /// 🔒 Compute API endpoint, e.g. a Private Service Connect endpoint `https://compute-psc.p.googleapis.com` (default: https://compute.googleapis.com)
+    #[arg(long = "compute-endpoint", env = "COMPUTE_ENDPOINT")]
+    pub compute_endpoint: Option<url::Url>,
+
+    /// 📜 Write one NDJSON line per webhook decision to this path, e.g. `/dev/fd/3`, or `-` for stdout
+    #[arg(long = "decision-log", env = "DECISION_LOG")]
+    pub decision_log: Option<PathBuf>,
+
+    /// 🩺 Echo request headers in `GET /health_check`, for debugging proxies; they may include secrets
+    #[arg(long = "debug-health", env = "DEBUG_HEALTH")]
+    pub debug_health: bool,
+
+    /// 🔥 Warm up the Compute API token and GitHub credentials after startup, reporting `GET /ready` as 503 until done
+    #[arg(long = "warmup", env = "WARMUP")]
+    pub warmup: bool,
+
+    /// 🔥 Also fetch the default instance template while warming up; implies `--warmup`
+    #[arg(long = "warmup-template", env = "WARMUP_TEMPLATE")]
+    pub warmup_template: bool,
+
+    /// 🐙 GitHub REST API base URL, e.g. `https://ghe.example.com/api/v3` (default: https://api.github.com)
+    #[arg(long = "github-api-url", env = "GITHUB_API_URL")]
+    pub github_api_url: Option<url::Url>,
+}
+
+impl Config {
+    /// Loads options from a JSON config file
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.display().to_string(),
+            source,
+        })?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Loads and validates a config file without applying it
+    pub fn check(path: &Path) -> Result<Self, ConfigError> {
+        let config = Self::load(path)?;
+        config.validate().map_err(ConfigError::Rejected)?;
+        Ok(config)
+    }
+
+    /// Whether queued jobs are provisioned at `at`; always without a schedule
+    pub fn provisioning_open(&self, at: SystemTime) -> bool {
+        self.provision_schedule.is_empty() || self.provision_schedule.iter().any(|w| w.contains(at))
+    }
+
+    /// Status configured for a class of GCE failure, overriding its default
+    pub fn error_status(&self, class: ErrorClass) -> Option<http::StatusCode> {
+        self.error_statuses
+            .iter()
+            .rfind(|e| e.class == class)
+            .and_then(|e| http::StatusCode::from_u16(e.status).ok())
+    }
+
+    /// Labels a job must carry to be provisioned, falling back to [`DEFAULT_REQUIRED_LABELS`]
+    pub fn required_labels(&self) -> Vec<String> {
+        self.required_labels.clone().unwrap_or_else(|| {
+            DEFAULT_REQUIRED_LABELS
+                .iter()
+                .map(|l| l.to_string())
+                .collect()
+        })
+    }
+
+    /// How long GCE lets a runner run before deleting it, falling back to
+    /// [`DEFAULT_MAX_RUN_DURATION`]; `None` when disabled with a zero duration
+    pub fn max_run_duration(&self) -> Option<Duration> {
+        Some(self.max_run_duration.unwrap_or(DEFAULT_MAX_RUN_DURATION)).filter(|d| !d.is_zero())
+    }
+
+    /// The first label group whose labels are all on the job
+    pub fn label_group(&self, labels: &HashSet<String>) -> Option<&LabelGroup> {
+        self.label_groups
+            .iter()
+            .find(|group| group.labels.iter().all(|l| labels.contains(l)))
+    }
+
+    /// Checks option values that parse but can't work, returning every problem found
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+
+        if self.max_concurrent_requests == Some(0) {
+            problems.push("max_concurrent_requests must be at least 1".to_string());
+        }
+        if self.max_webhook_body_bytes == Some(0) {
+            problems.push("max_webhook_body_bytes must be at least 1".to_string());
+        }
+        if self
+            .jit_rate_limit
+            .is_some_and(|rate| !(rate.is_finite() && rate > 0.0))
+        {
+            problems.push("jit_rate_limit must be a positive number".to_string());
+        }
+        if self.max_instances == Some(0) {
+            problems.push("max_instances must be at least 1".to_string());
+        }
+        if self.max_instances_per_repo == Some(0) {
+            problems.push("max_instances_per_repo must be at least 1".to_string());
+        }
+        if self.quota_reconcile_interval == Some(Duration::ZERO) {
+            problems.push("quota_reconcile_interval must be longer than zero".to_string());
+        }
+        if self.reconcile_concurrency == Some(0) {
+            problems.push("reconcile_concurrency must be at least 1".to_string());
+        }
+        if self.reconcile_timeout == Some(Duration::ZERO) {
+            problems.push("reconcile_timeout must be longer than zero".to_string());
+        }
+        if self.max_concurrent_deletes == Some(0) {
+            problems.push("max_concurrent_deletes must be at least 1".to_string());
+        }
+        if self.reaper_ttl == Some(Duration::ZERO) {
+            problems.push("reaper_ttl must be longer than zero".to_string());
+        }
+        if self.reaper_interval == Some(Duration::ZERO) {
+            problems.push("reaper_interval must be longer than zero".to_string());
+        }
+        if self.delivery_cache_size == Some(0) {
+            problems.push("delivery_cache_size must be at least 1".to_string());
+        }
+        if self.delivery_cache_ttl == Some(Duration::ZERO) {
+            problems.push("delivery_cache_ttl must be longer than zero".to_string());
+        }
+        if self.request_timeout == Some(Duration::ZERO) {
+            problems.push("request_timeout must be longer than zero".to_string());
+        }
+        if self.operation_timeout == Some(Duration::ZERO) {
+            problems.push("operation_timeout must be longer than zero".to_string());
+        }
+        if let Some(Err(e)) = self.boot_disk_snapshot.as_deref().map(snapshot_resource) {
+            problems.push(e);
+        }
+
+        // every runner carries these labels, so a bad one fails startup rather than each insert;
+        // their per-job values are checked when the instance is created
+        let fixed_labels = [
+            (CREATED_BY_LABEL, CREATED_BY),
+            (SCHEMA_LABEL, SCHEMA_VERSION),
+            (REPOSITORY_LABEL, ""),
+            (RUN_ID_LABEL, ""),
+            (JOB_ID_LABEL, ""),
+        ];
+        if let Err(e) = validate_gce_labels(fixed_labels) {
+            problems.extend(e);
+        }
+
+        if let Some(pattern) = &self.runner_name {
+            let sample = RunnerNameParts {
+                instance: "gha-1-1",
+                zone: "us-central1-a",
+                project: "project",
+                repository: "owner/repo",
+            };
+            if let Err(e) = resolve_runner_name(pattern, &sample) {
+                problems.push(e);
+            }
+        }
+
+        let mut names = HashSet::new();
+        for mapping in &self.label_projects {
+            if !names.insert(mapping.name.as_str()) {
+                problems.push(format!("label project `{}` is listed twice", mapping.name));
+            }
+        }
+        let mut repositories = HashSet::new();
+        for limit in &self.repo_instance_limits {
+            if !repositories.insert(limit.repository.as_str()) {
+                problems.push(format!(
+                    "repository `{}` has more than one instance limit",
+                    limit.repository
+                ));
+            }
+        }
+        if self.required_labels.is_some() && !self.label_groups.is_empty() {
+            problems.push("required_labels and label_groups can't both be set".to_string());
+        }
+        let mut groups = HashSet::new();
+        for group in &self.label_groups {
+            if !groups.insert(group.name.as_str()) {
+                problems.push(format!("label group `{}` is listed twice", group.name));
+            }
+            if group.labels.is_empty() {
+                problems.push(format!("label group `{}` has no labels", group.name));
+            }
+        }
+        for route in &self.routes {
+            if route.instances == 0 {
+                problems.push(format!(
+                    "route to `{}` creates no instances",
+                    route.template
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Overlays options explicitly set by flag or env var in `matches` onto `self`
+    pub fn overlay(self, cli: &Config, matches: &ArgMatches) -> Result<Self, ConfigError> {
+        let mut merged = serde_json::to_value(self)?;
+        if let (Value::Object(merged), Value::Object(cli)) =
+            (&mut merged, serde_json::to_value(cli)?)
+        {
+            for (key, value) in cli {
+                if matches!(
+                    matches.value_source(&key),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                ) {
+                    merged.insert(key, value);
+                }
+            }
+        }
+        Ok(serde_json::from_value(merged)?)
+    }
+
+    /// GitHub REST API base URL
+    pub fn github_api_base(&self) -> url::Url {
+        self.github_api_url.clone().unwrap_or_else(|| {
+            crate::github::DEFAULT_API_URL
+                .parse()
+                .expect("valid default GitHub API URL")
+        })
+    }
+
+    /// Selects the job labels to register the runner with.
+    ///
+    /// Routing always sees the full label set; only the labels on the allow-list
+    /// (when one is configured) are forwarded to GitHub.
+    pub fn registration_labels(&self, labels: &[String]) -> Vec<String> {
+        match &self.jit_labels {
+            Some(allowed) => labels
+                .iter()
+                .filter(|label| allowed.contains(label))
+                .cloned()
+                .collect(),
+            None => labels.to_vec(),
+        }
+    }
+
+    /// Resolves the instance template for a job's labels and how many instances to create.
+    ///
+    /// An allow-listed `template=<name>` label wins over the job's label group template,
+    /// which wins over the routes; a label naming a template outside the allow-list is
+    /// ignored. Returns `None` when routes are configured, none of them match, and unrouted
+    /// jobs are to be ignored.
+    pub fn select_route<'a>(
+        &'a self,
+        labels: &'a HashSet<String>,
+        default_template: &'a str,
+    ) -> Option<(&'a str, usize)> {
+        if let Some(name) = labels
+            .iter()
+            .find_map(|l| l.strip_prefix(TEMPLATE_LABEL_PREFIX))
+        {
+            if self.label_templates.iter().any(|t| t == name) {
+                return Some((name, 1));
+            }
+            tracing::warn!(
+                template = name,
+                "Ignoring template label that is not allow-listed"
+            );
+        }
+
+        if let Some(template) = self
+            .label_group(labels)
+            .and_then(|group| group.template.as_deref())
+        {
+            return Some((template, 1));
+        }
+
+        if self.routes.is_empty() {
+            return Some((default_template, 1));
+        }
+
+        let routed = self
+            .routes
+            .iter()
+            .find(|route| route.labels.iter().all(|l| labels.contains(l)))
+            .map(|route| (route.template.as_str(), route.instances));
+
+        match (routed, self.on_no_route) {
+            (Some(target), _) => Some(target),
+            (None, NoRoute::Default) => Some((default_template, 1)),
+            (None, NoRoute::Ignore) => None,
+        }
+    }
+
+    /// Resolves the GCP project for a job from its `project=<name>` label.
+    ///
+    /// Jobs without the label use `default_project`; a label naming a project
+    /// outside the allow-list is an error.
+    pub fn select_project<'a>(
+        &'a self,
+        labels: &HashSet<String>,
+        default_project: &'a str,
+    ) -> Result<&'a str, String> {
+        let Some(name) = labels
+            .iter()
+            .find_map(|l| l.strip_prefix(PROJECT_LABEL_PREFIX))
+        else {
+            return Ok(default_project);
+        };
+
+        self.label_projects
+            .iter()
+            .find(|mapping| mapping.name == name)
+            .map(|mapping| mapping.project.as_str())
+            .ok_or_else(|| format!("project `{name}` is not allow-listed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_route() {
+        let route = "gpu, linux=gpu-template".parse::<Route>().unwrap();
+        assert_eq!(route.labels, vec!["gpu", "linux"]);
+        assert_eq!(route.template, "gpu-template");
+        assert_eq!(route.instances, 1);
+
+        let route = "matrix=pool-template*3".parse::<Route>().unwrap();
+        assert_eq!(route.template, "pool-template");
+        assert_eq!(route.instances, 3);
+        assert!("matrix=pool-template*0".parse::<Route>().is_err());
+        assert!("matrix=pool-template*x".parse::<Route>().is_err());
+
+        assert!("gpu-template".parse::<Route>().is_err());
+        assert!("=gpu-template".parse::<Route>().is_err());
+        assert!("gpu=".parse::<Route>().is_err());
+    }
+
+    #[test]
+    fn parse_label_group() {
+        let group = "gpu: linux, gpu=gpu-template"
+            .parse::<LabelGroup>()
+            .unwrap();
+        assert_eq!(group.name, "gpu");
+        assert_eq!(group.labels, vec!["linux", "gpu"]);
+        assert_eq!(group.template.as_deref(), Some("gpu-template"));
+
+        let group = "arm64:linux,ARM64".parse::<LabelGroup>().unwrap();
+        assert_eq!(group.template, None);
+
+        assert!("linux,gpu=gpu-template".parse::<LabelGroup>().is_err());
+        assert!(":linux".parse::<LabelGroup>().is_err());
+        assert!("gpu:".parse::<LabelGroup>().is_err());
+        assert!("gpu:linux=".parse::<LabelGroup>().is_err());
+    }
+
+    #[test]
+    fn provision_window_contains_local_time() {
+        // 2024-01-01 was a Monday
+        let at = |day: u64, hour: u64| {
+            UNIX_EPOCH + Duration::from_secs(1_704_067_200 + day * 86_400 + hour * 3_600)
+        };
+
+        let window = "Mon-Fri 08:00-18:00 +02:00"
+            .parse::<ProvisionWindow>()
+            .unwrap();
+        assert_eq!(window.to_string(), "Mon-Fri 08:00-18:00 +02:00");
+        assert!(window.contains(at(0, 6)), "08:00 local opens the window");
+        assert!(!window.contains(at(0, 16)), "18:00 local closes it");
+        assert!(!window.contains(at(5, 10)), "Saturday is outside");
+
+        let weekend = "sat-mon 00:00-24:00".parse::<ProvisionWindow>().unwrap();
+        assert!(weekend.contains(at(0, 23)));
+        assert!(!weekend.contains(at(1, 0)));
+
+        assert!("Mon-Fri 18:00-08:00".parse::<ProvisionWindow>().is_err());
+        assert!("Someday 08:00-18:00".parse::<ProvisionWindow>().is_err());
+        assert!("Mon 08:00-25:00".parse::<ProvisionWindow>().is_err());
+        assert!("Mon 08:00-18:00 CEST".parse::<ProvisionWindow>().is_err());
+    }
+
+    #[test]
+    fn parse_error_status() {
+        let mapped = "quota-exceeded = 200".parse::<ErrorStatus>().unwrap();
+        assert_eq!(mapped.class, ErrorClass::QuotaExceeded);
+        assert_eq!(mapped.status, 200);
+
+        assert!("quota-exceeded".parse::<ErrorStatus>().is_err());
+        assert!("quota-exceeded=2000".parse::<ErrorStatus>().is_err());
+        assert!("out-of-luck=200".parse::<ErrorStatus>().is_err());
+    }
+
+    #[test]
+    fn parse_repo_limit() {
+        let limit = "owner/repo = 3".parse::<RepoLimit>().unwrap();
+        assert_eq!(limit.repository, "owner/repo");
+        assert_eq!(limit.limit, 3);
+
+        assert!("owner/repo".parse::<RepoLimit>().is_err());
+        assert!("owner/repo=many".parse::<RepoLimit>().is_err());
+        assert!("=3".parse::<RepoLimit>().is_err());
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        assert!(Config::default().validate().is_ok());
+
+        let config = Config {
+            max_concurrent_requests: Some(0),
+            jit_rate_limit: Some(-1.0),
+            label_projects: vec!["a=one".parse().unwrap(), "a=two".parse().unwrap()],
+            boot_disk_snapshot: Some("global/images/golden".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(config.validate().unwrap_err().len(), 4);
+    }
+
+    #[test]
+    fn select_project_from_label() {
+        let config = Config {
+            label_projects: vec!["team-a=gcp-team-a".parse().unwrap()],
+            ..Default::default()
+        };
+        let labels = |ls: &[&str]| ls.iter().map(|l| l.to_string()).collect::<HashSet<_>>();
+
+        assert_eq!(
+            config.select_project(&labels(&["linux", "project=team-a"]), "default"),
+            Ok("gcp-team-a")
+        );
+        assert_eq!(
+            config.select_project(&labels(&["linux"]), "default"),
+            Ok("default")
+        );
+        assert!(
+            config
+                .select_project(&labels(&["project=team-b"]), "default")
+                .is_err()
+        );
+    }
+}
@@ -1,22 +1,126 @@
+use octocrab::models::Repository;
 use reqwest::Url;
 use serde_json::Value;
+use std::collections::VecDeque;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 use tracing::instrument;
 
+use crate::clock::Clock;
+
+/// GitHub REST API base used when none is configured
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// Resolves a repository's REST API URL, `{api_base}/repos/{owner}/{name}`.
+///
+/// Webhooks normally carry it as `repository.url`, but some payload sources put the
+/// html URL there, so a URL not under `api_base` is rebuilt from the owner and name.
+pub fn repository_api_url(repository: &Repository, api_base: &Url) -> Option<Url> {
+    let repos_prefix = format!("{}/repos/", api_base.path().trim_end_matches('/'));
+    if repository.url.origin() == api_base.origin()
+        && repository.url.path().starts_with(&repos_prefix)
+    {
+        return Some(repository.url.clone());
+    }
+
+    let (full_owner, full_name) = repository
+        .full_name
+        .as_deref()
+        .and_then(|f| f.split_once('/'))
+        .unzip();
+    let owner = repository
+        .owner
+        .as_ref()
+        .map(|o| o.login.as_str())
+        .or(full_owner)
+        .filter(|o| !o.is_empty())?;
+    let name = Some(repository.name.as_str())
+        .filter(|n| !n.is_empty())
+        .or(full_name)?;
+
+    let mut url = api_base.clone();
+    url.path_segments_mut()
+        .ok()?
+        .pop_if_empty()
+        .extend(["repos", owner, name]);
+    Some(url)
+}
+
 fn user_agent() -> String {
     let sha = option_env!("VERGEN_GIT_SHA_SHORT").unwrap_or("unknown");
     let ver = env!("CARGO_PKG_VERSION");
     format!("spotted-arms/{ver} ({sha})")
 }
 
+/// Records the latency and HTTP status (or `error` when no response arrived) of a GitHub call
+fn record_call(operation: &'static str, status: &str, started: Instant) {
+    let registry = crate::metrics::registry();
+    let labels = [("operation", operation), ("status", status)];
+    registry.increment("spotted_arms_github_requests_total", &labels);
+    registry.observe(
+        "spotted_arms_github_request_duration_seconds",
+        &labels,
+        started.elapsed().as_secs_f64(),
+    );
+}
+
 #[derive(Debug, Error)]
 pub enum GithubError {
+    #[error("github api rate limit exceeded")]
+    RateLimited,
+    /// A runner with the requested name is already registered (422)
+    #[error("runner {0} is already registered")]
+    RunnerExists(String),
+    /// GitHub refused the request as invalid (422), e.g. for malformed runner labels
+    #[error("github api rejected the request: {0}")]
+    Unprocessable(String),
+    /// GitHub answered with a 5xx, or not at all
+    #[error("github api unavailable: {0}")]
+    Unavailable(String),
     #[error("github api error: {0}")]
     Other(String),
 }
 
+impl GithubError {
+    /// Whether retrying the same request later may succeed
+    pub fn is_transient(&self) -> bool {
+        matches!(self, GithubError::RateLimited | GithubError::Unavailable(_))
+    }
+}
+
+/// Classifies an error status: `429`, or `403` with the rate limit spent, is throttling and
+/// a 5xx is transient, while anything else, e.g. bad credentials, is final
+fn status_error(resp: &reqwest::Response, err: &reqwest::Error) -> GithubError {
+    let status = resp.status();
+    let exhausted = resp
+        .headers()
+        .get("x-ratelimit-remaining")
+        .is_some_and(|remaining| remaining == "0");
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        || (status == reqwest::StatusCode::FORBIDDEN && exhausted)
+    {
+        GithubError::RateLimited
+    } else if status.is_server_error() {
+        GithubError::Unavailable(err.to_string())
+    } else {
+        GithubError::Other(err.to_string())
+    }
+}
+
+/// A just-in-time runner registration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JitConfig {
+    /// Id of the registered runner, needed to deregister it
+    pub runner_id: u64,
+    /// Name the runner was registered under
+    pub runner_name: String,
+    /// Encoded config handed to the runner on the instance
+    pub encoded: String,
+}
+
 pub trait GithubApi: Send + Sync {
     fn generate_jit_config(
         &self,
@@ -24,18 +128,72 @@ pub trait GithubApi: Send + Sync {
         github_token: &str,
         runner_name: &str,
         labels: &[String],
-    ) -> Pin<Box<dyn Future<Output = Result<String, GithubError>> + Send>>;
+    ) -> Pin<Box<dyn Future<Output = Result<JitConfig, GithubError>> + Send>>;
+
+    /// Removes a registered runner; one that is already gone is not an error
+    fn delete_runner(
+        &self,
+        repo_url: &Url,
+        github_token: &str,
+        runner_id: u64,
+    ) -> Pin<Box<dyn Future<Output = Result<(), GithubError>> + Send>>;
+
+    /// Looks up the id of the runner registered under `runner_name`, if there is one
+    fn find_runner(
+        &self,
+        repo_url: &Url,
+        github_token: &str,
+        runner_name: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<u64>, GithubError>> + Send>>;
+
+    /// Confirms the token is accepted with a call that doesn't count against the rate limit
+    fn check_token(
+        &self,
+        _github_token: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<(), GithubError>> + Send>> {
+        Box::pin(async { Ok(()) })
+    }
 }
 
 #[derive(Clone)]
 pub struct GithubClient {
     client: reqwest::Client,
+    api_base: Url,
 }
 
 impl GithubClient {
     pub fn new() -> Self {
+        Self::with_base_url(
+            DEFAULT_API_URL
+                .parse()
+                .expect("valid default GitHub API URL"),
+        )
+    }
+
+    /// A client for the GitHub REST API at `api_base`, e.g. a GitHub Enterprise Server's
+    /// `https://github.example.com/api/v3`
+    pub fn with_base_url(api_base: Url) -> Self {
         Self {
-            client: reqwest::Client::new(),
+            client: crate::http::shared_client(),
+            api_base,
+        }
+    }
+
+    /// Sends requests with `client`, e.g. one with its own timeouts or proxy, instead of the
+    /// shared client
+    pub fn with_client(self, client: reqwest::Client) -> Self {
+        Self { client, ..self }
+    }
+
+    /// Refuses repository URLs on another host, so the token is only sent to the configured API
+    fn check_repo_url(&self, repo_url: &Url) -> Result<(), GithubError> {
+        if repo_url.origin() == self.api_base.origin() {
+            Ok(())
+        } else {
+            Err(GithubError::Other(format!(
+                "repository URL {repo_url} is not on the GitHub API at {}",
+                self.api_base
+            )))
         }
     }
 }
@@ -54,7 +212,10 @@ impl GithubApi for GithubClient {
         github_token: &str,
         runner_name: &str,
         labels: &[String],
-    ) -> Pin<Box<dyn Future<Output = Result<String, GithubError>> + Send>> {
+    ) -> Pin<Box<dyn Future<Output = Result<JitConfig, GithubError>> + Send>> {
+        if let Err(e) = self.check_repo_url(repo_url) {
+            return Box::pin(async { Err(e) });
+        }
         let client = self.client.clone();
         let repo_url = repo_url.clone();
         let labels = labels.to_vec();
@@ -79,15 +240,34 @@ impl GithubApi for GithubClient {
                 .build()
                 .map_err(|e| GithubError::Other(e.to_string()))?;
 
-            let resp = client
-                .execute(req)
-                .await
-                .map_err(|e| GithubError::Other(e.to_string()))?;
+            let started = Instant::now();
+            let resp = client.execute(req).await;
+            let status = resp
+                .as_ref()
+                .map_or("error".to_string(), |r| r.status().as_u16().to_string());
+            record_call("generate_jit_config", &status, started);
+            let resp = resp.map_err(|e| GithubError::Unavailable(e.to_string()))?;
+
+            if resp.status() == reqwest::StatusCode::UNPROCESSABLE_ENTITY {
+                let body = resp.json::<Value>().await.unwrap_or_default();
+                let message = body
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or("unprocessable entity")
+                    .to_string();
+                // GitHub words a duplicate name as "Already exists - A runner with the name ..."
+                if message.starts_with("Already exists") {
+                    return Err(GithubError::RunnerExists(runner_name));
+                }
+                tracing::error!(message, "GitHub rejected the JIT config request");
+                return Err(GithubError::Unprocessable(message));
+            }
 
             if let Err(err) = resp.error_for_status_ref() {
+                let error = status_error(&resp, &err);
                 let body = resp.text().await.ok();
                 tracing::error!(err = %err, body = ?body, "Failed to generate JIT config");
-                return Err(GithubError::Other(err.to_string()));
+                return Err(error);
             }
 
             let json: Value = resp
@@ -95,18 +275,795 @@ impl GithubApi for GithubClient {
                 .await
                 .map_err(|e| GithubError::Other(e.to_string()))?;
 
-            json.get("encoded_jit_config")
+            let encoded = json
+                .get("encoded_jit_config")
                 .and_then(|v| v.as_str())
                 .map(|s| s.to_string())
-                .ok_or_else(|| GithubError::Other("encoded_jit_config missing".to_string()))
+                .ok_or_else(|| GithubError::Other("encoded_jit_config missing".to_string()))?;
+            let runner_id = json
+                .pointer("/runner/id")
+                .and_then(Value::as_u64)
+                .ok_or_else(|| GithubError::Other("runner.id missing".to_string()))?;
+            // GitHub echoes the requested name back; fall back to it if a proxy trims the runner
+            let runner_name = json
+                .pointer("/runner/name")
+                .and_then(Value::as_str)
+                .map_or(runner_name, str::to_string);
+
+            Ok(JitConfig {
+                runner_id,
+                runner_name,
+                encoded,
+            })
+        })
+    }
+
+    #[instrument(skip(self, github_token))]
+    fn delete_runner(
+        &self,
+        repo_url: &Url,
+        github_token: &str,
+        runner_id: u64,
+    ) -> Pin<Box<dyn Future<Output = Result<(), GithubError>> + Send>> {
+        if let Err(e) = self.check_repo_url(repo_url) {
+            return Box::pin(async { Err(e) });
+        }
+        let client = self.client.clone();
+        let repo_url = repo_url.clone();
+        let token = github_token.to_string();
+
+        Box::pin(async move {
+            let req = client
+                .delete(format!("{repo_url}/actions/runners/{runner_id}"))
+                .bearer_auth(&token)
+                .header("Accept", "application/vnd.github+json")
+                .header("User-Agent", user_agent())
+                .header("X-GitHub-Api-Version", "2022-11-28")
+                .build()
+                .map_err(|e| GithubError::Other(e.to_string()))?;
+
+            let started = Instant::now();
+            let resp = client.execute(req).await;
+            let status = resp
+                .as_ref()
+                .map_or("error".to_string(), |r| r.status().as_u16().to_string());
+            record_call("delete_runner", &status, started);
+            let resp = resp.map_err(|e| GithubError::Unavailable(e.to_string()))?;
+
+            if resp.status() == reqwest::StatusCode::NOT_FOUND {
+                return Ok(());
+            }
+            if let Err(err) = resp.error_for_status_ref() {
+                let error = status_error(&resp, &err);
+                let body = resp.text().await.ok();
+                tracing::error!(err = %err, body = ?body, "Failed to delete runner");
+                return Err(error);
+            }
+
+            Ok(())
+        })
+    }
+
+    #[instrument(skip(self, github_token))]
+    fn find_runner(
+        &self,
+        repo_url: &Url,
+        github_token: &str,
+        runner_name: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<u64>, GithubError>> + Send>> {
+        if let Err(e) = self.check_repo_url(repo_url) {
+            return Box::pin(async { Err(e) });
+        }
+        let client = self.client.clone();
+        let repo_url = repo_url.clone();
+        let runner_name = runner_name.to_string();
+        let token = github_token.to_string();
+
+        Box::pin(async move {
+            let req = client
+                .get(format!("{repo_url}/actions/runners"))
+                .query(&[("name", runner_name.as_str())])
+                .bearer_auth(&token)
+                .header("Accept", "application/vnd.github+json")
+                .header("User-Agent", user_agent())
+                .header("X-GitHub-Api-Version", "2022-11-28")
+                .build()
+                .map_err(|e| GithubError::Other(e.to_string()))?;
+
+            let started = Instant::now();
+            let resp = client.execute(req).await;
+            let status = resp
+                .as_ref()
+                .map_or("error".to_string(), |r| r.status().as_u16().to_string());
+            record_call("find_runner", &status, started);
+            let resp = resp.map_err(|e| GithubError::Unavailable(e.to_string()))?;
+
+            if let Err(err) = resp.error_for_status_ref() {
+                let error = status_error(&resp, &err);
+                let body = resp.text().await.ok();
+                tracing::error!(err = %err, body = ?body, "Failed to list runners");
+                return Err(error);
+            }
+
+            let json: Value = resp
+                .json()
+                .await
+                .map_err(|e| GithubError::Other(e.to_string()))?;
+            // the name filter is documented as exact, but matching again costs nothing
+            Ok(json
+                .get("runners")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .find(|runner| runner.get("name").and_then(Value::as_str) == Some(&runner_name))
+                .and_then(|runner| runner.get("id"))
+                .and_then(Value::as_u64))
+        })
+    }
+
+    #[instrument(skip(self, github_token))]
+    fn check_token(
+        &self,
+        github_token: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<(), GithubError>> + Send>> {
+        let client = self.client.clone();
+        let url = format!(
+            "{}/rate_limit",
+            self.api_base.as_str().trim_end_matches('/')
+        );
+        let token = github_token.to_string();
+
+        Box::pin(async move {
+            let req = client
+                .get(url)
+                .bearer_auth(&token)
+                .header("Accept", "application/vnd.github+json")
+                .header("User-Agent", user_agent())
+                .header("X-GitHub-Api-Version", "2022-11-28")
+                .build()
+                .map_err(|e| GithubError::Other(e.to_string()))?;
+
+            let started = Instant::now();
+            let resp = client.execute(req).await;
+            let status = resp
+                .as_ref()
+                .map_or("error".to_string(), |r| r.status().as_u16().to_string());
+            record_call("check_token", &status, started);
+            let resp = resp.map_err(|e| GithubError::Unavailable(e.to_string()))?;
+
+            resp.error_for_status()
+                .map(drop)
+                .map_err(|e| GithubError::Other(e.to_string()))
+        })
+    }
+}
+
+/// Produces the bearer token sent with GitHub API calls
+pub trait TokenProvider: Send + Sync {
+    fn token(&self) -> Pin<Box<dyn Future<Output = Result<String, GithubError>> + Send + '_>>;
+}
+
+/// A long-lived token, such as a personal access token, used as is
+pub struct StaticToken(pub String);
+
+impl TokenProvider for StaticToken {
+    fn token(&self) -> Pin<Box<dyn Future<Output = Result<String, GithubError>> + Send + '_>> {
+        let token = self.0.clone();
+        Box::pin(async move { Ok(token) })
+    }
+}
+
+/// How long before it expires a cached token is replaced
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// Shortest wait between background refreshes, also used after one fails
+const TOKEN_REFRESH_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A token and when it expires, refreshed by one caller at a time
+#[derive(Default)]
+pub struct TokenCache {
+    current: tokio::sync::Mutex<Option<(String, SystemTime)>>,
+}
+
+impl TokenCache {
+    /// The cached token while it outlives the refresh margin at `now`, otherwise the one minted
+    /// by `refresh`; concurrent callers wait on a single refresh rather than each running theirs
+    pub async fn get_or_refresh(
+        &self,
+        now: SystemTime,
+        refresh: impl Future<Output = Result<(String, SystemTime), GithubError>>,
+    ) -> Result<String, GithubError> {
+        let mut current = self.current.lock().await;
+        if let Some((token, expires_at)) = current.as_ref()
+            && now + TOKEN_REFRESH_MARGIN < *expires_at
+        {
+            return Ok(token.clone());
+        }
+
+        let (token, expires_at) = refresh.await?;
+        *current = Some((token.clone(), expires_at));
+        Ok(token)
+    }
+
+    /// When the cached token is next due to be replaced, if one is cached
+    pub async fn refresh_due(&self) -> Option<SystemTime> {
+        let current = self.current.lock().await;
+        current
+            .as_ref()
+            .map(|(_, expires_at)| *expires_at - TOKEN_REFRESH_MARGIN)
+    }
+}
+
+/// Installation tokens minted by authenticating as a GitHub App, cached until shortly before
+/// they expire
+pub struct AppInstallationToken {
+    client: reqwest::Client,
+    api_base: Url,
+    app_id: u64,
+    installation_id: u64,
+    key: jsonwebtoken::EncodingKey,
+    cache: TokenCache,
+    clock: Arc<dyn Clock>,
+}
+
+impl AppInstallationToken {
+    /// Authenticates as the app `app_id` with its PEM-encoded RSA private key, minting tokens
+    /// for `installation_id` from the GitHub API at `api_base`
+    pub fn new(
+        api_base: Url,
+        app_id: u64,
+        installation_id: u64,
+        private_key: &str,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self, GithubError> {
+        let key = jsonwebtoken::EncodingKey::from_rsa_pem(private_key.as_bytes())
+            .map_err(|e| GithubError::Other(format!("invalid GitHub App private key: {e}")))?;
+        Ok(Self {
+            client: crate::http::shared_client(),
+            api_base,
+            app_id,
+            installation_id,
+            key,
+            cache: TokenCache::default(),
+            clock,
+        })
+    }
+
+    /// A short-lived JWT identifying the app, exchanged for installation tokens
+    fn app_jwt(&self) -> Result<String, GithubError> {
+        let now = self
+            .clock
+            .system_now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        // backdated to allow for clock drift, and well within GitHub's ten minute limit
+        let claims = serde_json::json!({
+            "iat": now.saturating_sub(60),
+            "exp": now + 9 * 60,
+            "iss": self.app_id.to_string(),
+        });
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256),
+            &claims,
+            &self.key,
+        )
+        .map_err(|e| GithubError::Other(format!("failed to sign GitHub App JWT: {e}")))
+    }
+
+    #[instrument(skip(self), fields(app_id = self.app_id, installation_id = self.installation_id))]
+    async fn mint(&self) -> Result<(String, SystemTime), GithubError> {
+        let jwt = self.app_jwt()?;
+        let mut url = self.api_base.clone();
+        url.path_segments_mut()
+            .map_err(|()| GithubError::Other(format!("invalid GitHub API URL {}", self.api_base)))?
+            .pop_if_empty()
+            .extend([
+                "app",
+                "installations",
+                &self.installation_id.to_string(),
+                "access_tokens",
+            ]);
+
+        let req = self
+            .client
+            .post(url)
+            .bearer_auth(&jwt)
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", user_agent())
+            .header("X-GitHub-Api-Version", "2022-11-28")
+            .build()
+            .map_err(|e| GithubError::Other(e.to_string()))?;
+
+        let started = Instant::now();
+        let resp = self.client.execute(req).await;
+        let status = resp
+            .as_ref()
+            .map_or("error".to_string(), |r| r.status().as_u16().to_string());
+        record_call("create_installation_token", &status, started);
+        let resp = resp.map_err(|e| GithubError::Unavailable(e.to_string()))?;
+
+        if let Err(err) = resp.error_for_status_ref() {
+            let error = status_error(&resp, &err);
+            let body = resp.text().await.ok();
+            tracing::error!(err = %err, body = ?body, "Failed to create installation token");
+            return Err(error);
+        }
+
+        let json: Value = resp
+            .json()
+            .await
+            .map_err(|e| GithubError::Other(e.to_string()))?;
+        let token = json
+            .get("token")
+            .and_then(Value::as_str)
+            .ok_or_else(|| GithubError::Other("token missing".to_string()))?;
+        let expires_at = json
+            .get("expires_at")
+            .and_then(Value::as_str)
+            .ok_or_else(|| GithubError::Other("expires_at missing".to_string()))?;
+        let expires_at = humantime::parse_rfc3339(expires_at)
+            .map_err(|e| GithubError::Other(format!("invalid expires_at {expires_at}: {e}")))?;
+
+        tracing::info!(
+            expires_at = %humantime::format_rfc3339(expires_at),
+            "Minted GitHub App installation token"
+        );
+        Ok((token.to_string(), expires_at))
+    }
+}
+
+impl AppInstallationToken {
+    /// Replaces the cached token as it nears expiry, so webhooks rarely wait on GitHub for one;
+    /// stops once the provider is dropped
+    pub fn refresh_in_background(self: &Arc<Self>) {
+        let provider = Arc::downgrade(self);
+        tokio::spawn(async move {
+            while let Some(provider) = provider.upgrade() {
+                let wait = match provider.token().await {
+                    Ok(_) => provider
+                        .cache
+                        .refresh_due()
+                        .await
+                        .and_then(|due| due.duration_since(provider.clock.system_now()).ok())
+                        .unwrap_or_default(),
+                    Err(e) => {
+                        tracing::warn!(?e, "Failed to refresh GitHub App installation token");
+                        TOKEN_REFRESH_RETRY_INTERVAL
+                    }
+                };
+                drop(provider);
+                tokio::time::sleep(wait.max(TOKEN_REFRESH_RETRY_INTERVAL)).await;
+            }
+        });
+    }
+}
+
+impl TokenProvider for AppInstallationToken {
+    fn token(&self) -> Pin<Box<dyn Future<Output = Result<String, GithubError>> + Send + '_>> {
+        Box::pin(
+            self.cache
+                .get_or_refresh(self.clock.system_now(), self.mint()),
+        )
+    }
+}
+
+/// Token bucket refilled continuously at `rate` tokens per second.
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    state: Mutex<(f64, tokio::time::Instant)>,
+    clock: Arc<dyn Clock>,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket allowing bursts of up to one second's worth of tokens
+    pub fn new(rate: f64, clock: Arc<dyn Clock>) -> Self {
+        let capacity = rate.ceil().max(1.0);
+        Self {
+            rate,
+            capacity,
+            state: Mutex::new((capacity, clock.now())),
+            clock,
+        }
+    }
+
+    /// Takes a token if one is available
+    pub fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let (tokens, last) = &mut *state;
+
+        let now = self.clock.now();
+        *tokens =
+            (*tokens + now.duration_since(*last).as_secs_f64() * self.rate).min(self.capacity);
+        *last = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Stands in for runner registration under `--dry-run`: JIT configs are synthetic and
+/// deregistration is skipped. Token checks still reach GitHub.
+pub struct DryRunGithub {
+    inner: Arc<dyn GithubApi>,
+}
+
+impl DryRunGithub {
+    pub fn new(inner: Arc<dyn GithubApi>) -> Self {
+        Self { inner }
+    }
+}
+
+impl GithubApi for DryRunGithub {
+    fn generate_jit_config(
+        &self,
+        repo_url: &Url,
+        _github_token: &str,
+        runner_name: &str,
+        labels: &[String],
+    ) -> Pin<Box<dyn Future<Output = Result<JitConfig, GithubError>> + Send>> {
+        tracing::info!(%repo_url, runner_name, ?labels, "Dry run: stubbing JIT config");
+        let config = JitConfig {
+            runner_id: 0,
+            runner_name: runner_name.to_string(),
+            encoded: "dry-run".to_string(),
+        };
+        Box::pin(async { Ok(config) })
+    }
+
+    fn delete_runner(
+        &self,
+        repo_url: &Url,
+        _github_token: &str,
+        runner_id: u64,
+    ) -> Pin<Box<dyn Future<Output = Result<(), GithubError>> + Send>> {
+        tracing::info!(%repo_url, runner_id, "Dry run: skipping runner deregistration");
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Stubbed registrations never collide, so there is no runner to find
+    fn find_runner(
+        &self,
+        _repo_url: &Url,
+        _github_token: &str,
+        _runner_name: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<u64>, GithubError>> + Send>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    fn check_token(
+        &self,
+        github_token: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<(), GithubError>> + Send>> {
+        self.inner.check_token(github_token)
+    }
+}
+
+/// Caps the rate of JIT config generation across all webhooks.
+pub struct RateLimitedGithub {
+    inner: Arc<dyn GithubApi>,
+    bucket: TokenBucket,
+}
+
+impl RateLimitedGithub {
+    /// Wraps `inner`, allowing `rate` JIT config generations per second
+    pub fn new(inner: Arc<dyn GithubApi>, rate: f64, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            inner,
+            bucket: TokenBucket::new(rate, clock),
+        }
+    }
+}
+
+impl GithubApi for RateLimitedGithub {
+    fn generate_jit_config(
+        &self,
+        repo_url: &Url,
+        github_token: &str,
+        runner_name: &str,
+        labels: &[String],
+    ) -> Pin<Box<dyn Future<Output = Result<JitConfig, GithubError>> + Send>> {
+        if !self.bucket.try_acquire() {
+            tracing::warn!(runner_name, "Throttling JIT config generation");
+            crate::metrics::registry().increment("spotted_arms_jit_throttled_total", &[]);
+            return Box::pin(async { Err(GithubError::RateLimited) });
+        }
+
+        self.inner
+            .generate_jit_config(repo_url, github_token, runner_name, labels)
+    }
+
+    /// Deregistration cleans up after throttled work, so it isn't throttled itself
+    fn delete_runner(
+        &self,
+        repo_url: &Url,
+        github_token: &str,
+        runner_id: u64,
+    ) -> Pin<Box<dyn Future<Output = Result<(), GithubError>> + Send>> {
+        self.inner.delete_runner(repo_url, github_token, runner_id)
+    }
+
+    fn find_runner(
+        &self,
+        repo_url: &Url,
+        github_token: &str,
+        runner_name: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<u64>, GithubError>> + Send>> {
+        self.inner.find_runner(repo_url, github_token, runner_name)
+    }
+
+    fn check_token(
+        &self,
+        github_token: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<(), GithubError>> + Send>> {
+        self.inner.check_token(github_token)
+    }
+}
+
+/// Dead-lettered runners kept at most; the oldest are dropped first
+const DEAD_LETTER_CAPACITY: usize = 1000;
+
+/// A runner that couldn't be deregistered
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct DeadLetter {
+    /// API URL of the runner's repository
+    pub repository: Url,
+    pub runner_id: u64,
+}
+
+/// Runners that couldn't be deregistered, listed on `GET /runners/dead-letters` so they
+/// can be removed by hand
+#[derive(Default)]
+pub struct DeadLetters {
+    runners: Mutex<VecDeque<DeadLetter>>,
+}
+
+impl DeadLetters {
+    /// Records a runner of `repo_url` whose deregistration ultimately failed
+    pub fn record(&self, repo_url: &Url, runner_id: u64) {
+        crate::metrics::registry().increment("spotted_arms_runner_dead_letters_total", &[]);
+        let mut runners = self.runners.lock().unwrap_or_else(PoisonError::into_inner);
+        if runners.len() == DEAD_LETTER_CAPACITY
+            && let Some(dropped) = runners.pop_front()
+        {
+            tracing::warn!(
+                repository = %dropped.repository,
+                runner_id = dropped.runner_id,
+                "Dropping the oldest dead-lettered runner"
+            );
+        }
+        runners.push_back(DeadLetter {
+            repository: repo_url.clone(),
+            runner_id,
+        });
+    }
+
+    /// Runners still awaiting cleanup, oldest first
+    pub fn runners(&self) -> Vec<DeadLetter> {
+        self.runners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Delay before the first deregistration retry; later ones double it
+const DEREGISTER_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Retries deregistering runners that fail transiently, dead-lettering the ones that still
+/// fail. The first attempt's outcome is returned right away; retries back off in the
+/// background so the caller isn't held up.
+pub struct RetryingGithub {
+    inner: Arc<dyn GithubApi>,
+    retries: usize,
+    dead_letters: Arc<DeadLetters>,
+}
+
+impl RetryingGithub {
+    /// Wraps `inner`, retrying each deregistration up to `retries` times
+    pub fn new(inner: Arc<dyn GithubApi>, retries: usize, dead_letters: Arc<DeadLetters>) -> Self {
+        Self {
+            inner,
+            retries,
+            dead_letters,
+        }
+    }
+}
+
+impl GithubApi for RetryingGithub {
+    fn generate_jit_config(
+        &self,
+        repo_url: &Url,
+        github_token: &str,
+        runner_name: &str,
+        labels: &[String],
+    ) -> Pin<Box<dyn Future<Output = Result<JitConfig, GithubError>> + Send>> {
+        self.inner
+            .generate_jit_config(repo_url, github_token, runner_name, labels)
+    }
+
+    fn delete_runner(
+        &self,
+        repo_url: &Url,
+        github_token: &str,
+        runner_id: u64,
+    ) -> Pin<Box<dyn Future<Output = Result<(), GithubError>> + Send>> {
+        let inner = self.inner.clone();
+        let retries = self.retries;
+        let dead_letters = self.dead_letters.clone();
+        let repo_url = repo_url.clone();
+        let token = github_token.to_string();
+
+        Box::pin(async move {
+            let error = match inner.delete_runner(&repo_url, &token, runner_id).await {
+                Ok(()) => return Ok(()),
+                Err(e) if e.is_transient() && retries > 0 => e,
+                Err(e) => {
+                    dead_letters.record(&repo_url, runner_id);
+                    return Err(e);
+                }
+            };
+
+            tokio::spawn(async move {
+                let mut interval = DEREGISTER_RETRY_INTERVAL;
+                for attempt in 1..=retries {
+                    tokio::time::sleep(interval).await;
+                    interval *= 2;
+                    tracing::warn!(runner_id, attempt, "Retrying runner deregistration");
+                    match inner.delete_runner(&repo_url, &token, runner_id).await {
+                        Ok(()) => return,
+                        Err(e) if e.is_transient() && attempt < retries => {
+                            tracing::warn!(runner_id, attempt, error = %e, "Runner deregistration failed");
+                        }
+                        Err(e) => {
+                            tracing::error!(runner_id, error = %e, "Giving up on runner deregistration");
+                            break;
+                        }
+                    }
+                }
+                dead_letters.record(&repo_url, runner_id);
+            });
+            Err(error)
         })
     }
+
+    fn find_runner(
+        &self,
+        repo_url: &Url,
+        github_token: &str,
+        runner_name: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<u64>, GithubError>> + Send>> {
+        self.inner.find_runner(repo_url, github_token, runner_name)
+    }
+
+    fn check_token(
+        &self,
+        github_token: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<(), GithubError>> + Send>> {
+        self.inner.check_token(github_token)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn repository(url: &str) -> Repository {
+        serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": "repo",
+            "url": url,
+            "full_name": "owner/repo"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn repository_api_url_from_owner_and_name() {
+        let github: Url = DEFAULT_API_URL.parse().unwrap();
+        let expected = "https://api.github.com/repos/owner/repo";
+
+        for url in [
+            "https://api.github.com/repos/owner/repo",
+            "https://github.com/owner/repo",
+            "https://example.com/somewhere/else",
+        ] {
+            assert_eq!(
+                repository_api_url(&repository(url), &github)
+                    .unwrap()
+                    .as_str(),
+                expected,
+                "{url}"
+            );
+        }
+
+        let enterprise: Url = "https://ghe.example.com/api/v3/".parse().unwrap();
+        assert_eq!(
+            repository_api_url(
+                &repository("https://ghe.example.com/owner/repo"),
+                &enterprise
+            )
+            .unwrap()
+            .as_str(),
+            "https://ghe.example.com/api/v3/repos/owner/repo"
+        );
+        assert_eq!(
+            repository_api_url(
+                &repository("https://ghe.example.com/api/v3/repos/owner/repo"),
+                &enterprise
+            )
+            .unwrap()
+            .as_str(),
+            "https://ghe.example.com/api/v3/repos/owner/repo"
+        );
+    }
+
+    #[tokio::test]
+    async fn client_refuses_repositories_on_other_hosts() {
+        let client = GithubClient::with_base_url("https://ghe.example.com/api/v3".parse().unwrap());
+        let public: Url = "https://api.github.com/repos/owner/repo".parse().unwrap();
+
+        let err = client
+            .generate_jit_config(&public, "token", "runner", &[])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("ghe.example.com"));
+        assert!(client.delete_runner(&public, "token", 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn expired_token_is_refreshed_once_under_concurrent_access() {
+        let cache = TokenCache::default();
+        let now = SystemTime::now();
+        let refreshes = std::sync::atomic::AtomicUsize::new(0);
+        let refresh = |expires_in: Duration| {
+            let refreshes = &refreshes;
+            async move {
+                let n = refreshes.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                tokio::task::yield_now().await;
+                Ok((format!("token-{n}"), now + expires_in))
+            }
+        };
+
+        // cached, but inside the refresh margin
+        cache
+            .get_or_refresh(now, refresh(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        let tokens = futures_util::future::join_all(
+            (0..8).map(|_| cache.get_or_refresh(now, refresh(Duration::from_secs(3600)))),
+        )
+        .await;
+
+        assert!(tokens.iter().all(|t| t.as_deref().ok() == Some("token-2")));
+        assert_eq!(refreshes.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(
+            cache.refresh_due().await,
+            Some(now + Duration::from_secs(3600) - TOKEN_REFRESH_MARGIN)
+        );
+    }
+
+    #[test]
+    fn token_bucket_throttles_bursts() {
+        let clock = Arc::new(crate::clock::FakeClock::new(SystemTime::now()));
+        let bucket = TokenBucket::new(2.0, clock.clone());
+        assert!(bucket.try_acquire());
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+
+        // refilled at two tokens a second
+        clock.advance(Duration::from_millis(500));
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+    }
+
     #[test]
     fn user_agent_contains_version_and_sha() {
         let ua = user_agent();
@@ -0,0 +1,10 @@
+use std::sync::OnceLock;
+
+/// The process-wide HTTP client for GitHub and the GCP metadata server.
+///
+/// `reqwest::Client` is a handle to a connection pool, so clones share connections and TLS
+/// sessions instead of each call setting up its own.
+pub fn shared_client() -> reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new).clone()
+}
@@ -1,16 +1,30 @@
 use crate::compute::{ComputeApi, ComputeError};
-use crate::github::GithubApi;
+use crate::config::{Config, DEFAULT_OPERATION_TIMEOUT, ExistingInstance};
+use crate::github::{GithubApi, GithubError, JitConfig, repository_api_url};
+use crate::quota::{INSTANCE_LIMIT_RETRY_AFTER, INSTANCE_PERMIT_TIMEOUT, InstanceLimit};
+use crate::utils::{
+    RunnerNameParts, repo_label_value, repo_network_tag, resolve_runner_name, sanitize_label_value,
+    snapshot_resource, validate_gce_labels, validate_gce_tags,
+};
 use axum::response::ErrorResponse;
+use clap::ValueEnum;
 use gcloud_sdk::google_rest_apis::compute_v1;
 use gcloud_sdk::google_rest_apis::compute_v1::Instance;
 use gcloud_sdk::google_rest_apis::compute_v1::instances_api::{
-    ComputePeriodInstancesPeriodDeleteParams, ComputePeriodInstancesPeriodInsertParams,
+    ComputePeriodInstancesPeriodDeleteParams, ComputePeriodInstancesPeriodGetParams,
+    ComputePeriodInstancesPeriodInsertParams,
 };
 use gcloud_sdk::google_rest_apis::compute_v1::region_instance_templates_api::ComputePeriodRegionInstanceTemplatesPeriodGetParams;
-use serde_json::Value;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use tracing::{Span, field, info, instrument};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{OwnedMutexGuard, watch};
+use tokio::task::JoinSet;
+use tracing::{Span, field, info, instrument, warn};
 
 // Supported zones for us-central1 region
 const US_CENTRAL1_ZONES: &[&str] = &[
@@ -20,6 +34,233 @@ const US_CENTRAL1_ZONES: &[&str] = &[
     "us-central1-f",
 ];
 
+/// Instance label carrying the sanitized repository name, used to count instances per repo
+pub const REPOSITORY_LABEL: &str = "repository";
+
+/// Instance label carrying the workflow run id, for attributing cost
+pub const RUN_ID_LABEL: &str = "run_id";
+
+/// Instance label carrying the workflow job id, for attributing cost
+pub const JOB_ID_LABEL: &str = "job_id";
+
+/// Instance label marking instances this service created, set to [`CREATED_BY`]
+pub const CREATED_BY_LABEL: &str = "created-by";
+
+/// Value of [`CREATED_BY_LABEL`] on runner instances
+pub const CREATED_BY: &str = "spotted-arms";
+
+/// Instance label recording which label and metadata contract the instance was created
+/// under, set to [`SCHEMA_VERSION`]
+pub const SCHEMA_LABEL: &str = "spotted-arms-schema";
+
+/// Version of the labels and metadata set on runner instances; bump it whenever that
+/// contract changes, so reapers and reconcilers can tell older instances apart
+pub const SCHEMA_VERSION: &str = "v1";
+
+/// Zones instances are spread across in a supported region
+pub fn region_zones(region: &str) -> Option<&'static [&'static str]> {
+    match region {
+        "us-central1" => Some(US_CENTRAL1_ZONES),
+        _ => None,
+    }
+}
+
+/// Zones instances are spread across: `overrides` when set, otherwise the region's built-in list
+pub fn effective_zones<'a>(region: &str, overrides: &'a [String]) -> Option<Vec<&'a str>> {
+    if overrides.is_empty() {
+        region_zones(region).map(<[_]>::to_vec)
+    } else {
+        Some(overrides.iter().map(String::as_str).collect())
+    }
+}
+
+/// Full attempts at `create_instance` beyond the first for configured transient failures
+const CREATE_RETRIES: usize = 1;
+
+/// Step of instance creation that failed, selectable for retrying the whole flow
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CreateStage {
+    /// Generating the runner's JIT config
+    JitConfig,
+    /// Reading the instance template
+    TemplateGet,
+    /// Inserting the instance
+    Insert,
+}
+
+/// Class of GCE failure, whose HTTP status can be overridden with `--error-status`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorClass {
+    /// The Compute API throttled the call (default `429`)
+    RateLimited,
+    /// A project or regional quota ran out (default `503`)
+    QuotaExceeded,
+    /// Every attempted zone lacked capacity (default `503`)
+    ResourceExhausted,
+    /// The service account lacks a permission (default `500`)
+    PermissionDenied,
+    /// Any other failure (default `500`)
+    Other,
+}
+
+impl ErrorClass {
+    fn of(e: &ComputeError) -> Self {
+        match e {
+            ComputeError::RateLimited { .. } => Self::RateLimited,
+            ComputeError::QuotaExceeded(_) => Self::QuotaExceeded,
+            ComputeError::ResourceExhausted(_) => Self::ResourceExhausted,
+            ComputeError::PermissionDenied(_) => Self::PermissionDenied,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Scheduling for a runner limited to a run duration, layered over the template's scheduling.
+///
+/// The generated models can't carry `maxRunDuration`, so the duration itself is added to the
+/// insert by [`ComputeApi::compute_instances_insert`]; GCE then deletes the instance once it
+/// has run that long.
+fn runner_scheduling(template: Option<compute_v1::Scheduling>) -> compute_v1::Scheduling {
+    compute_v1::Scheduling {
+        instance_termination_action: Some(Some(
+            compute_v1::scheduling::InstanceTerminationAction::Delete,
+        )),
+        ..template.unwrap_or_default()
+    }
+}
+
+/// The template's disks with the boot disk created from `snapshot` rather than an image.
+///
+/// Instance disks replace the template's, so the other disks are carried over, and a boot
+/// disk is added if the template has none.
+fn snapshot_boot_disks(
+    template: Option<Vec<compute_v1::AttachedDisk>>,
+    snapshot: String,
+) -> Vec<compute_v1::AttachedDisk> {
+    let mut disks = template.unwrap_or_default();
+    let boot = match disks.iter().position(|d| d.boot == Some(true)) {
+        Some(i) => &mut disks[i],
+        None => {
+            disks.insert(
+                0,
+                compute_v1::AttachedDisk {
+                    boot: Some(true),
+                    auto_delete: Some(true),
+                    ..Default::default()
+                },
+            );
+            &mut disks[0]
+        }
+    };
+    // a boot disk attached by `source` is an existing disk, which can't be shared by runners
+    boot.source = None;
+    let params = boot.initialize_params.get_or_insert_default();
+    params.source_image = None;
+    params.source_image_encryption_key = None;
+    params.source_snapshot = Some(snapshot);
+    disks
+}
+
+/// Job label launching the runner as a SPOT instance
+pub const SPOT_LABEL: &str = "spot";
+
+/// SPOT provisioning layered over `scheduling`. The instance is deleted when preempted, and
+/// GCE requires spot instances to terminate on host maintenance rather than migrate or restart.
+fn spot_scheduling(scheduling: compute_v1::Scheduling) -> compute_v1::Scheduling {
+    compute_v1::Scheduling {
+        provisioning_model: Some(compute_v1::scheduling::ProvisioningModel::Spot),
+        instance_termination_action: Some(Some(
+            compute_v1::scheduling::InstanceTerminationAction::Delete,
+        )),
+        automatic_restart: Some(false),
+        on_host_maintenance: Some(compute_v1::scheduling::OnHostMaintenance::Terminate),
+        ..scheduling
+    }
+}
+
+/// Extension point rewriting a runner's assembled metadata right before the instance is
+/// inserted, e.g. to inject computed values
+pub trait MetadataTransform: Send + Sync {
+    fn transform(
+        &self,
+        instance_name: &str,
+        event: &crate::webhook::WorkflowJobWebhook,
+        metadata: &mut Vec<compute_v1::MetadataItemsInner>,
+    );
+}
+
+impl<F> MetadataTransform for F
+where
+    F: Fn(&str, &crate::webhook::WorkflowJobWebhook, &mut Vec<compute_v1::MetadataItemsInner>)
+        + Send
+        + Sync,
+{
+    fn transform(
+        &self,
+        instance_name: &str,
+        event: &crate::webhook::WorkflowJobWebhook,
+        metadata: &mut Vec<compute_v1::MetadataItemsInner>,
+    ) {
+        self(instance_name, event, metadata)
+    }
+}
+
+/// Leaves metadata unchanged
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopMetadataTransform;
+
+impl MetadataTransform for NoopMetadataTransform {
+    fn transform(
+        &self,
+        _instance_name: &str,
+        _event: &crate::webhook::WorkflowJobWebhook,
+        _metadata: &mut Vec<compute_v1::MetadataItemsInner>,
+    ) {
+    }
+}
+
+/// Metadata key holding when the job's webhook was received (RFC 3339)
+pub const RECEIVED_AT_METADATA_KEY: &str = "gh-received-at";
+
+/// Metadata key turning on GCE guest attributes for the instance
+pub const GUEST_ATTRIBUTES_METADATA_KEY: &str = "enable-guest-attributes";
+
+/// Metadata key keeping project-wide SSH keys off the instance
+pub const BLOCK_PROJECT_SSH_KEYS_METADATA_KEY: &str = "block-project-ssh-keys";
+
+// Metadata keys whose values are credentials and must never be logged
+const SECRET_METADATA_KEYS: &[&str] = &["JIT_CONFIG"];
+
+/// Renders an insert request for logging with secret metadata values redacted
+pub(crate) fn redacted_insert_request(request: &ComputePeriodInstancesPeriodInsertParams) -> Value {
+    let mut instance = request.instance.clone().unwrap_or_default();
+
+    let items = instance
+        .metadata
+        .as_mut()
+        .and_then(|m| m.items.as_mut())
+        .into_iter()
+        .flatten();
+    for item in items {
+        if item
+            .key
+            .as_deref()
+            .is_some_and(|k| SECRET_METADATA_KEYS.contains(&k))
+        {
+            item.value = Some("<redacted>".to_string());
+        }
+    }
+
+    json!({
+        "project": request.project,
+        "zone": request.zone,
+        "source_instance_template": request.source_instance_template,
+        "instance": instance,
+    })
+}
+
 fn add_event_fields_to_span(event: &crate::webhook::WorkflowJobWebhook) {
     let payload = &event.payload;
 
@@ -51,22 +292,26 @@ fn add_event_fields_to_span(event: &crate::webhook::WorkflowJobWebhook) {
 }
 
 /// Deterministically selects a zone based on instance name hash
-fn select_zone_for_region(region: &str, instance_name: &str) -> Result<String, Box<ErrorResponse>> {
-    if region != "us-central1" {
+fn select_zone_for_region(
+    region: &str,
+    zones: &[String],
+    instance_name: &str,
+) -> Result<String, Box<ErrorResponse>> {
+    let Some(zones) = effective_zones(region, zones) else {
         tracing::error!(
             "Unsupported region: {}. Only us-central1 is currently supported.",
             region
         );
 
         return Err(ErrorResponse::from(http::StatusCode::BAD_REQUEST).into());
-    }
+    };
 
     let mut hasher = DefaultHasher::new();
     instance_name.hash(&mut hasher);
     let hash = hasher.finish();
 
-    let zone_index = (hash as usize) % US_CENTRAL1_ZONES.len();
-    let selected_zone = US_CENTRAL1_ZONES[zone_index];
+    let zone_index = (hash as usize) % zones.len();
+    let selected_zone = zones[zone_index];
 
     tracing::debug!(
         "Selected zone {} for instance {} in region {}",
@@ -78,9 +323,80 @@ fn select_zone_for_region(region: &str, instance_name: &str) -> Result<String, B
     Ok(selected_zone.to_string())
 }
 
+/// Zones to try for `instance_name`: its deterministic zone first, then the rest in order
+fn zones_to_try(
+    region: &str,
+    zones: &[String],
+    instance_name: &str,
+) -> Result<Vec<String>, Box<ErrorResponse>> {
+    let primary = select_zone_for_region(region, zones, instance_name)?;
+    let mut ordered = vec![primary];
+    ordered.extend(
+        effective_zones(region, zones)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|&zone| zone != ordered[0])
+            .map(str::to_string)
+            .collect::<Vec<_>>(),
+    );
+
+    Ok(ordered)
+}
+
+/// Maps a Compute API failure to the status the webhook replies with.
+///
+/// Rate limits and exhausted capacity or quota are transient, so they're reported as such
+/// (with `Retry-After` when GCE gave a delay) rather than as a server error.
+fn compute_error_response(e: &ComputeError, config: &Config) -> ErrorResponse {
+    let (status, body) = match e {
+        ComputeError::RateLimited { .. } => (http::StatusCode::TOO_MANY_REQUESTS, e.to_string()),
+        ComputeError::QuotaExceeded(_) | ComputeError::ResourceExhausted(_) => {
+            (http::StatusCode::SERVICE_UNAVAILABLE, e.to_string())
+        }
+        ComputeError::PermissionDenied(_) => {
+            (http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        }
+        _ => (http::StatusCode::INTERNAL_SERVER_ERROR, format!("{e:?}")),
+    };
+    let status = config.error_status(ErrorClass::of(e)).unwrap_or(status);
+
+    match e {
+        ComputeError::RateLimited {
+            retry_after: Some(retry_after),
+        } => (
+            status,
+            [(
+                http::header::RETRY_AFTER,
+                retry_after.as_secs_f64().ceil().to_string(),
+            )],
+            body,
+        )
+            .into(),
+        _ => (status, body).into(),
+    }
+}
+
+/// Why an attempt at creating an instance failed
+struct CreateFailure {
+    stage: CreateStage,
+    /// GCE ran out of capacity or quota in the attempted zone
+    zone_exhausted: bool,
+    response: Box<ErrorResponse>,
+}
+
+impl From<(CreateStage, Box<ErrorResponse>)> for CreateFailure {
+    fn from((stage, response): (CreateStage, Box<ErrorResponse>)) -> Self {
+        Self {
+            stage,
+            zone_exhausted: false,
+            response,
+        }
+    }
+}
+
 /// Creates a new compute instance from a template for the given workflow job
 #[instrument(
-    skip(api, github, event, github_token),
+    skip(api, github, event, github_token, metadata_transform, limit),
     fields(job_id, repo_url, repository, run_attempt, run_id),
     err(Debug)
 )]
@@ -90,17 +406,22 @@ pub async fn create_instance(
     github: &dyn GithubApi,
     project_id: &str,
     region: &str,
+    zones: &[String],
     github_token: &str,
     instance_template: &str,
+    machine_type: Option<&str>,
+    config: &Config,
     instance_name: &str,
     event: &crate::webhook::WorkflowJobWebhook,
+    received_at: SystemTime,
+    metadata_transform: &dyn MetadataTransform,
+    limit: &InstanceLimit,
 ) -> Result<(), Box<ErrorResponse>> {
     add_event_fields_to_span(event);
 
-    let repo_url = event.repository.url.clone();
-    if repo_url.host_str() != Some("api.github.com") {
+    let Some(repo_url) = repository_api_url(&event.repository, &config.github_api_base()) else {
         tracing::error!(
-            repo_url = display(repo_url),
+            repo_url = display(&event.repository.url),
             "Unexpected repository URL format"
         );
         return Err(Box::new(
@@ -110,12 +431,305 @@ pub async fn create_instance(
             )
                 .into(),
         ));
+    };
+
+    // The deterministic zone first; stockouts and quota errors fall back to the others
+    let zones = zones_to_try(region, zones, instance_name)?;
+
+    if !limit.acquire(instance_name, INSTANCE_PERMIT_TIMEOUT).await {
+        warn!(instance_name, "Rejecting job over the instance limit");
+        return Err(Box::new(
+            (
+                http::StatusCode::SERVICE_UNAVAILABLE,
+                [(
+                    http::header::RETRY_AFTER,
+                    INSTANCE_LIMIT_RETRY_AFTER.as_secs().to_string(),
+                )],
+                "instance limit reached",
+            )
+                .into(),
+        ));
     }
 
-    // Extract runner name and labels from the event payload
-    let runner_name = instance_name; // Use instance name as runner name
-    let payload = &event.payload;
-    let labels = payload
+    let mut jit_config = None;
+    let created = async {
+        for (attempt, zone) in zones.iter().enumerate() {
+            let last_zone = attempt + 1 == zones.len();
+            if attempt > 0 {
+                warn!(instance_name, zone, "Falling back to the next zone");
+            }
+
+            if let Some(policy) = config.on_existing_instance
+                && !prepare_instance_name(
+                    api,
+                    project_id,
+                    zone,
+                    instance_name,
+                    policy,
+                    config
+                        .operation_timeout
+                        .unwrap_or(DEFAULT_OPERATION_TIMEOUT),
+                )
+                .await?
+            {
+                return Ok(());
+            }
+
+            let mut retries = 0;
+            loop {
+                match try_create_instance(
+                    api,
+                    github,
+                    project_id,
+                    region,
+                    zone,
+                    github_token,
+                    instance_template,
+                    machine_type,
+                    config,
+                    instance_name,
+                    event,
+                    received_at,
+                    metadata_transform,
+                    &repo_url,
+                    &mut jit_config,
+                )
+                .await
+                {
+                    Ok(()) => return Ok(()),
+                    Err(failure) if failure.zone_exhausted && !last_zone => {
+                        // a runner name pattern may embed the zone, so it is registered anew
+                        if config.runner_name.is_some()
+                            && let Some(jit) = jit_config.take()
+                        {
+                            deregister_runner(github, &repo_url, github_token, &jit).await;
+                        }
+                        break;
+                    }
+                    Err(failure)
+                        if retries < CREATE_RETRIES
+                            && config.create_retry_on.contains(&failure.stage) =>
+                    {
+                        retries += 1;
+                        warn!(
+                            stage = ?failure.stage,
+                            retries, "Retrying instance creation after transient failure"
+                        );
+                    }
+                    Err(failure) => return Err(failure.response),
+                }
+            }
+        }
+
+        unreachable!("the last zone returns its failure")
+    }
+    .await;
+
+    // a runner registered for an instance that never came up would sit offline forever
+    if created.is_err() {
+        limit.release(instance_name);
+        if let Some(jit) = jit_config.take() {
+            deregister_runner(github, &repo_url, github_token, &jit).await;
+        }
+    }
+
+    created
+}
+
+/// Removes a runner whose instance won't be created, logging rather than failing on errors
+async fn deregister_runner(
+    github: &dyn GithubApi,
+    repo_url: &reqwest::Url,
+    github_token: &str,
+    jit: &JitConfig,
+) {
+    match github
+        .delete_runner(repo_url, github_token, jit.runner_id)
+        .await
+    {
+        Ok(()) => info!(runner_id = jit.runner_id, "Deregistered unused runner"),
+        Err(e) => warn!(
+            runner_id = jit.runner_id,
+            error = %e,
+            "Failed to deregister unused runner"
+        ),
+    }
+}
+
+/// Deregisters the runner already registered under `runner_name` so the name can be
+/// registered again; one that has meanwhile disappeared is not an error
+async fn remove_stale_runner(
+    github: &dyn GithubApi,
+    repo_url: &reqwest::Url,
+    github_token: &str,
+    runner_name: &str,
+) -> Result<(), GithubError> {
+    let Some(runner_id) = github
+        .find_runner(repo_url, github_token, runner_name)
+        .await?
+    else {
+        return Ok(());
+    };
+    warn!(
+        runner_name,
+        runner_id, "Replacing stale runner registration"
+    );
+    github
+        .delete_runner(repo_url, github_token, runner_id)
+        .await
+}
+
+/// The response for a JIT config request GitHub refused
+fn jit_error_response(e: GithubError) -> ErrorResponse {
+    match e {
+        GithubError::RateLimited => (
+            axum::http::StatusCode::TOO_MANY_REQUESTS,
+            "jit config rate limited",
+        )
+            .into(),
+        GithubError::Unprocessable(message) => (
+            axum::http::StatusCode::UNPROCESSABLE_ENTITY,
+            format!("jit config rejected: {message}"),
+        )
+            .into(),
+        GithubError::RunnerExists(name) => (
+            axum::http::StatusCode::CONFLICT,
+            format!("runner {name} is already registered"),
+        )
+            .into(),
+        e => {
+            tracing::error!(?e, "Failed to generate JIT config");
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "jit config failed",
+            )
+                .into()
+        }
+    }
+}
+
+/// Applies the configured policy when `instance_name` is already taken.
+///
+/// Returns whether creation should proceed.
+async fn prepare_instance_name(
+    api: &dyn ComputeApi,
+    project_id: &str,
+    zone: &str,
+    instance_name: &str,
+    policy: ExistingInstance,
+    operation_timeout: Duration,
+) -> Result<bool, Box<ErrorResponse>> {
+    let internal_error = |e: ComputeError| -> Box<ErrorResponse> {
+        Box::new((http::StatusCode::INTERNAL_SERVER_ERROR, format!("{e:?}")).into())
+    };
+
+    match api
+        .compute_instances_get(ComputePeriodInstancesPeriodGetParams {
+            project: project_id.to_string(),
+            zone: zone.to_string(),
+            instance: instance_name.to_string(),
+            ..Default::default()
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(ComputeError::NotFound) => return Ok(true),
+        Err(e) => {
+            tracing::error!(
+                instance_name,
+                ?e,
+                "Failed to check for an existing instance"
+            );
+            return Err(internal_error(e));
+        }
+    }
+
+    match policy {
+        ExistingInstance::Skip => {
+            info!(
+                instance_name,
+                zone, "Instance already exists; skipping creation"
+            );
+            Ok(false)
+        }
+        ExistingInstance::Fail => {
+            info!(
+                instance_name,
+                zone, "Instance already exists; rejecting job"
+            );
+            Err(Box::new(
+                (http::StatusCode::CONFLICT, "instance already exists").into(),
+            ))
+        }
+        ExistingInstance::Replace => {
+            info!(instance_name, zone, "Instance already exists; replacing it");
+            let deleted = api
+                .compute_instances_delete(ComputePeriodInstancesPeriodDeleteParams {
+                    project: project_id.to_string(),
+                    zone: zone.to_string(),
+                    instance: instance_name.to_string(),
+                    ..Default::default()
+                })
+                .await;
+            match deleted {
+                Ok(operation) => {
+                    api.wait_for_operation(project_id, operation, operation_timeout)
+                        .await
+                        .map_err(internal_error)?;
+                }
+                Err(ComputeError::NotFound) => {}
+                Err(e) => return Err(internal_error(e)),
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// One attempt at generating a runner config and inserting the instance.
+///
+/// `jit_config` carries a runner registered by an earlier attempt so retries reuse it.
+#[allow(clippy::too_many_arguments)]
+async fn try_create_instance(
+    api: &dyn ComputeApi,
+    github: &dyn GithubApi,
+    project_id: &str,
+    region: &str,
+    zone: &str,
+    github_token: &str,
+    instance_template: &str,
+    machine_type: Option<&str>,
+    config: &Config,
+    instance_name: &str,
+    event: &crate::webhook::WorkflowJobWebhook,
+    received_at: SystemTime,
+    metadata_transform: &dyn MetadataTransform,
+    repo_url: &reqwest::Url,
+    jit_config: &mut Option<JitConfig>,
+) -> Result<(), CreateFailure> {
+    // The runner name defaults to the instance name but may follow its own scheme
+    let runner_name = match &config.runner_name {
+        Some(pattern) => {
+            let parts = RunnerNameParts {
+                instance: instance_name,
+                zone,
+                project: project_id,
+                repository: event.repository.full_name.as_deref().unwrap_or_default(),
+            };
+            resolve_runner_name(pattern, &parts).map_err(|e| {
+                tracing::error!(error = e, "Failed to resolve runner name");
+                (
+                    CreateStage::JitConfig,
+                    Box::new((axum::http::StatusCode::INTERNAL_SERVER_ERROR, e).into()),
+                )
+            })?
+        }
+        None => instance_name.to_string(),
+    };
+    let runner_name = runner_name.as_str();
+
+    // Extract labels from the event payload
+    let job_labels = event
+        .payload
         .workflow_job
         .get("labels")
         .and_then(|v| v.as_array())
@@ -126,45 +740,84 @@ pub async fn create_instance(
         })
         .unwrap_or_default();
 
+    // Only register the runner with the allow-listed subset of the job labels
+    let labels = config.registration_labels(&job_labels);
+
     // Use provided instance template
     let template_name = instance_template.to_string();
 
-    // Generate JIT config and fetch template metadata concurrently
-    let (jit_config, template_metadata) = tokio::try_join!(
-        async {
-            github
-                .generate_jit_config(&repo_url, github_token, runner_name, &labels)
-                .await
-                .map_err(|e| -> ErrorResponse {
-                    tracing::error!(?e, "Failed to generate JIT config");
-                    (
-                        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                        "jit config failed",
-                    )
-                        .into()
-                })
-        },
-        async {
-            api.compute_region_instance_templates_get(
-                ComputePeriodRegionInstanceTemplatesPeriodGetParams {
-                    project: project_id.to_string(),
-                    region: region.to_string(),
-                    instance_template: template_name.to_string(),
-                    fields: Some("properties.metadata".to_string()),
-                    ..Default::default()
-                },
-            )
+    let cached_jit = jit_config.clone();
+    let generate_jit = |labels: Vec<String>| async move {
+        if let Some(jit_config) = cached_jit {
+            return Ok(jit_config);
+        }
+        match github
+            .generate_jit_config(repo_url, github_token, runner_name, &labels)
             .await
-            .map_err(|e| -> ErrorResponse {
-                tracing::error!(?e, "Failed to get instance template metadata");
-                (
-                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                    "templates get failed",
-                )
-                    .into()
-            })
+        {
+            // a runner left behind, e.g. by an earlier delivery, holds the name; an instance
+            // booted without fresh credentials could never register, so it is replaced
+            Err(GithubError::RunnerExists(_)) => {
+                remove_stale_runner(github, repo_url, github_token, runner_name)
+                    .await
+                    .map_err(jit_error_response)?;
+                github
+                    .generate_jit_config(repo_url, github_token, runner_name, &labels)
+                    .await
+            }
+            generated => generated,
         }
-    )?;
+        .map_err(jit_error_response)
+    };
+    let fetch_template = async {
+        api.compute_region_instance_templates_get(
+            ComputePeriodRegionInstanceTemplatesPeriodGetParams {
+                project: project_id.to_string(),
+                region: region.to_string(),
+                instance_template: template_name.to_string(),
+                fields: Some(
+                    "properties.metadata,properties.tags,properties.labels,properties.scheduling,properties.disks"
+                        .to_string(),
+                ),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|e| -> ErrorResponse {
+            tracing::error!(?e, "Failed to get instance template metadata");
+            if let ComputeError::RateLimited { .. } = e {
+                return compute_error_response(&e, config);
+            }
+            (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "templates get failed",
+            )
+                .into()
+        })
+    };
+
+    // Generate JIT config and fetch template metadata concurrently; both always run to
+    // completion so a runner registered on a failed attempt is reused, not registered twice.
+    // Labels propagated from the template are needed for registration, so then the template
+    // comes first and no runner is registered when it can't be read.
+    let (jit_result, template_result) = match &config.template_labels_key {
+        Some(key) => {
+            let template = fetch_template
+                .await
+                .map_err(|e| (CreateStage::TemplateGet, Box::new(e)))?;
+            let labels = merge_template_labels(labels, template_runner_labels(&template, key));
+            (generate_jit(labels).await, Ok(template))
+        }
+        None => tokio::join!(generate_jit(labels), fetch_template),
+    };
+    let jit = jit_result.map_err(|e| (CreateStage::JitConfig, Box::new(e)))?;
+    *jit_config = Some(jit.clone());
+    info!(
+        runner_id = jit.runner_id,
+        runner_name = jit.runner_name,
+        "Using runner registration for instance"
+    );
+    let template_metadata = template_result.map_err(|e| (CreateStage::TemplateGet, Box::new(e)))?;
 
     info!(
         instance_name,
@@ -172,9 +825,6 @@ pub async fn create_instance(
         "Creating instance from template for job",
     );
 
-    // Select zone deterministically based on instance name
-    let zone = select_zone_for_region(region, instance_name)?;
-
     // Use the preexisting instance template
     let source_instance_template = format!(
         "projects/{}/regions/{}/instanceTemplates/{}",
@@ -183,23 +833,128 @@ pub async fn create_instance(
 
     info!(source_instance_template, zone, "Using instance template");
 
+    let properties = template_metadata.properties.unwrap_or_default();
+
     // there isn't a way to merge metadata items, so we have to do it manually
-    let mut metadata = template_metadata
-        .properties
-        .and_then(|p| p.metadata)
+    let mut metadata = properties
+        .metadata
         .and_then(|m| m.items)
         .unwrap_or_default();
     metadata.push(compute_v1::MetadataItemsInner {
         key: Some("JIT_CONFIG".to_string()),
-        value: Some(jit_config),
+        value: Some(jit.encoded),
     });
+    if config.annotate_received_at {
+        metadata.push(compute_v1::MetadataItemsInner {
+            key: Some(RECEIVED_AT_METADATA_KEY.to_string()),
+            value: Some(humantime::format_rfc3339_millis(received_at).to_string()),
+        });
+    }
+    if config.enable_guest_attributes {
+        // replaces any value the template set rather than adding a conflicting duplicate
+        metadata.retain(|item| item.key.as_deref() != Some(GUEST_ATTRIBUTES_METADATA_KEY));
+        metadata.push(compute_v1::MetadataItemsInner {
+            key: Some(GUEST_ATTRIBUTES_METADATA_KEY.to_string()),
+            value: Some("TRUE".to_string()),
+        });
+    }
+    if config.block_project_ssh_keys {
+        // a template value of `false` must not outlive the override
+        metadata.retain(|item| item.key.as_deref() != Some(BLOCK_PROJECT_SSH_KEYS_METADATA_KEY));
+        metadata.push(compute_v1::MetadataItemsInner {
+            key: Some(BLOCK_PROJECT_SSH_KEYS_METADATA_KEY.to_string()),
+            value: Some("true".to_string()),
+        });
+    }
+
+    metadata_transform.transform(instance_name, event, &mut metadata);
+
+    // instance tags replace the template's, so carry those over alongside the repo tag
+    let tags: Option<Box<compute_v1::Tags>> = if config.repo_network_tags {
+        let mut items = properties.tags.and_then(|t| t.items).unwrap_or_default();
+        items.extend(
+            event
+                .repository
+                .full_name
+                .as_deref()
+                .and_then(repo_network_tag),
+        );
+        Some(
+            compute_v1::Tags {
+                items: Some(items),
+                ..Default::default()
+            }
+            .into(),
+        )
+    } else {
+        None
+    };
+
+    // instance labels also replace the template's; the repository label drives per-repo quotas,
+    // the created-by label lets the reaper find leaked runners, and together with the run and
+    // job ids they attribute cost in billing exports
+    let mut instance_labels = properties.labels.unwrap_or_default();
+    instance_labels.insert(CREATED_BY_LABEL.to_string(), CREATED_BY.to_string());
+    instance_labels.insert(SCHEMA_LABEL.to_string(), SCHEMA_VERSION.to_string());
+    for (label, field) in [(RUN_ID_LABEL, "run_id"), (JOB_ID_LABEL, "id")] {
+        if let Some(id) = event
+            .payload
+            .workflow_job
+            .get(field)
+            .and_then(Value::as_i64)
+        {
+            instance_labels.insert(label.to_string(), sanitize_label_value(&id.to_string()));
+        }
+    }
+    if let Some(full_name) = event.repository.full_name.as_deref() {
+        instance_labels.insert(REPOSITORY_LABEL.to_string(), repo_label_value(full_name));
+    }
+
+    // labels and tags carry payload-derived values, so they're checked here rather than
+    // leaving GCE to reject the insert
+    let tag_items = tags
+        .as_ref()
+        .and_then(|t| t.items.as_deref())
+        .unwrap_or_default();
+    validate_gce_labels(
+        instance_labels
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str())),
+    )
+    .and(validate_gce_tags(tag_items.iter().map(String::as_str)))
+    .map_err(|problems| {
+        let problems = problems.join("; ");
+        tracing::error!(problems, "Rejecting instance with invalid labels or tags");
+        (
+            CreateStage::Insert,
+            Box::new((axum::http::StatusCode::BAD_REQUEST, problems).into()),
+        )
+    })?;
+
+    // instance scheduling also replaces the template's, so it is only set to bound the
+    // lifetime or to launch a spot runner
+    let template_scheduling = properties.scheduling.map(|s| *s);
+    let max_run_duration = config.max_run_duration();
+    let mut scheduling = max_run_duration.map(|_| runner_scheduling(template_scheduling.clone()));
+    if job_labels.iter().any(|l| l == SPOT_LABEL) {
+        let base = scheduling.or(template_scheduling).unwrap_or_default();
+        scheduling = Some(spot_scheduling(base));
+    }
+
+    let disks = match config.boot_disk_snapshot.as_deref().map(snapshot_resource) {
+        Some(Ok(snapshot)) => Some(snapshot_boot_disks(properties.disks, snapshot)),
+        // rejected when the config is validated
+        Some(Err(_)) | None => None,
+    };
 
     let request = ComputePeriodInstancesPeriodInsertParams {
         project: project_id.to_string(),
-        zone: zone.clone(),
+        zone: zone.to_string(),
         source_instance_template: Some(source_instance_template),
         instance: Some(Instance {
             name: Some(instance_name.to_string()),
+            // unset keeps the template's machine type
+            machine_type: machine_type.map(|t| format!("zones/{zone}/machineTypes/{t}")),
             metadata: Some(
                 compute_v1::Metadata {
                     items: Some(metadata),
@@ -207,86 +962,295 @@ pub async fn create_instance(
                 }
                 .into(),
             ),
+            tags,
+            labels: Some(instance_labels),
+            scheduling: scheduling.map(Box::new),
+            disks,
             ..Instance::new()
         }),
         ..Default::default()
     };
 
-    match api.compute_instances_insert(request).await {
-        Ok(_operation) => {
+    tracing::debug!(
+        request = %redacted_insert_request(&request),
+        ?max_run_duration,
+        "Resolved instance insert request"
+    );
+
+    // the insert only starts the operation; it can still fail, e.g. on a stockout
+    let created = match api
+        .compute_instances_insert(request, max_run_duration)
+        .await
+    {
+        Ok(operation) => {
             info!(
                 instance_name,
-                zone, "Successfully initiated instance creation from template",
+                zone, "Initiated instance creation from template; waiting for it",
+            );
+            api.wait_for_operation(
+                project_id,
+                operation,
+                config
+                    .operation_timeout
+                    .unwrap_or(DEFAULT_OPERATION_TIMEOUT),
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match created {
+        Ok(_) => {
+            info!(
+                instance_name,
+                zone, "Successfully created instance from template",
             );
             Ok(())
         }
+        Err(e) if e.is_zone_exhausted() => {
+            warn!(
+                instance_name,
+                zone,
+                error = %e,
+                "GCE is out of capacity or quota in zone"
+            );
+
+            Err(CreateFailure {
+                stage: CreateStage::Insert,
+                zone_exhausted: true,
+                response: Box::new(compute_error_response(&e, config)),
+            })
+        }
+        Err(e @ ComputeError::PermissionDenied(_)) => {
+            tracing::error!(
+                instance_name,
+                error = %e,
+                "GCE denied instance creation; check the service account's IAM roles"
+            );
+
+            Err((
+                CreateStage::Insert,
+                Box::new(compute_error_response(&e, config)),
+            )
+                .into())
+        }
         Err(e) => {
             tracing::error!(instance_name, ?e, "Failed to create instance from template",);
 
-            Err(Box::new(
-                (
-                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("{e:?}"),
-                )
-                    .into(),
-            ))
+            Err((
+                CreateStage::Insert,
+                Box::new(compute_error_response(&e, config)),
+            )
+                .into())
+        }
+    }
+}
+
+/// Counts an instance deletion by how far it was seen through
+fn record_deletion(result: &str) {
+    crate::metrics::registry().increment(
+        "spotted_arms_instance_deletions_total",
+        &[("result", result)],
+    );
+}
+
+/// Runner labels listed, comma-separated, in the template's `key` metadata item
+fn template_runner_labels(template: &compute_v1::InstanceTemplate, key: &str) -> Vec<String> {
+    template
+        .properties
+        .as_ref()
+        .and_then(|p| p.metadata.as_ref())
+        .and_then(|m| m.items.as_ref())
+        .and_then(|items| items.iter().find(|item| item.key.as_deref() == Some(key)))
+        .and_then(|item| item.value.as_deref())
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|label| !label.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Appends template labels the job doesn't already carry; GitHub labels are
+/// case-insensitive, so the job's spelling wins
+fn merge_template_labels(mut labels: Vec<String>, template: Vec<String>) -> Vec<String> {
+    for label in template {
+        if !labels.iter().any(|l| l.eq_ignore_ascii_case(&label)) {
+            labels.push(label);
         }
     }
+    labels
+}
+
+/// Per-instance-name locks serializing the creation and deletion of one runner, so a
+/// `completed` event racing its `queued` one (or a redelivery) waits rather than deleting
+/// mid-insert. Unrelated names never contend.
+#[derive(Default)]
+pub struct InstanceLocks {
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl InstanceLocks {
+    /// Waits for exclusive use of `name`, held until the returned guard is dropped
+    pub async fn lock(&self, name: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap_or_else(PoisonError::into_inner);
+            // locks nobody holds or waits on are dropped as new ones are taken
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(name.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+}
+
+/// Deletions held back by `--linger-on-failure`, so failed runners can be inspected first
+pub struct LingeringDeletes {
+    tasks: Mutex<JoinSet<()>>,
+    /// Set at shutdown to run every pending deletion without waiting out its delay
+    flushed: watch::Sender<bool>,
+}
+
+impl Default for LingeringDeletes {
+    fn default() -> Self {
+        Self {
+            tasks: Mutex::default(),
+            flushed: watch::Sender::new(false),
+        }
+    }
+}
+
+impl LingeringDeletes {
+    /// Runs `delete` after `delay`, or as soon as the deletions are flushed
+    pub fn schedule(&self, delay: Duration, delete: impl Future<Output = ()> + Send + 'static) {
+        let mut flushed = self.flushed.subscribe();
+        let mut tasks = self.tasks.lock().unwrap_or_else(PoisonError::into_inner);
+        // finished deletions are dropped here rather than held until shutdown
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = flushed.wait_for(|flushed| *flushed) => {}
+            }
+            delete.await;
+        });
+    }
+
+    /// Deletions not yet finished
+    pub fn pending(&self) -> usize {
+        let mut tasks = self.tasks.lock().unwrap_or_else(PoisonError::into_inner);
+        while tasks.try_join_next().is_some() {}
+        tasks.len()
+    }
+
+    /// Runs every pending deletion now and waits for them to finish, e.g. at shutdown
+    pub async fn flush(&self) {
+        self.flushed.send_replace(true);
+        let mut tasks =
+            std::mem::take(&mut *self.tasks.lock().unwrap_or_else(PoisonError::into_inner));
+        while tasks.join_next().await.is_some() {}
+    }
 }
 
 /// Deletes the compute instance for the given workflow job
 #[instrument(
-    skip(api, event),
+    skip(api, event, config, limit),
     fields(conclusion, job_id, repo_url, repository, run_attempt, run_id),
     err(Debug)
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn delete_instance(
     api: &dyn ComputeApi,
     project_id: &str,
     region: &str,
+    zones: &[String],
     instance_name: &str,
     event: &crate::webhook::WorkflowJobWebhook,
+    config: &Config,
+    limit: &InstanceLimit,
 ) -> Result<(), Box<ErrorResponse>> {
     add_event_fields_to_span(event);
 
     info!(instance_name, "Deleting instance");
 
-    // Select the same zone that was used for creation
-    let zone = select_zone_for_region(region, instance_name)?;
-
-    match api
-        .compute_instances_delete(ComputePeriodInstancesPeriodDeleteParams {
-            project: project_id.to_string(),
-            zone: zone.clone(),
-            instance: instance_name.to_string(),
-            ..Default::default()
-        })
-        .await
-    {
-        Ok(_) => {
-            info!(
-                instance_name,
-                zone, "Successfully initiated instance deletion"
-            );
-        }
-        Err(ComputeError::NotFound) => {
-            info!(
-                instance_name,
-                zone, "Instance not found in zone (may have already been deleted)"
-            );
-        }
-        Err(other) => {
-            tracing::error!(instance_name, ?other, "Failed to delete instance");
-            return Err(Box::new(
-                (
-                    http::StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("{other:?}"),
-                )
-                    .into(),
-            ));
+    // Creation may have fallen back from the deterministic zone, so the others are searched
+    for zone in zones_to_try(region, zones, instance_name)? {
+        match api
+            .compute_instances_delete(ComputePeriodInstancesPeriodDeleteParams {
+                project: project_id.to_string(),
+                zone: zone.clone(),
+                instance: instance_name.to_string(),
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(operation) if config.confirm_deletes => {
+                info!(
+                    instance_name,
+                    zone, "Initiated instance deletion; waiting for it"
+                );
+                let timeout = config
+                    .operation_timeout
+                    .unwrap_or(DEFAULT_OPERATION_TIMEOUT);
+                match api.wait_for_operation(project_id, operation, timeout).await {
+                    Ok(_) => {
+                        info!(instance_name, zone, "Instance deletion completed");
+                        record_deletion("confirmed");
+                    }
+                    Err(ComputeError::OperationTimeout { operation }) => {
+                        warn!(
+                            instance_name,
+                            zone, operation, "Instance deletion still pending at timeout"
+                        );
+                        record_deletion("pending");
+                    }
+                    Err(other) => {
+                        tracing::error!(instance_name, ?other, "Instance deletion failed");
+                        record_deletion("failed");
+                        return Err(Box::new(
+                            (
+                                http::StatusCode::INTERNAL_SERVER_ERROR,
+                                format!("{other:?}"),
+                            )
+                                .into(),
+                        ));
+                    }
+                }
+                limit.release(instance_name);
+                return Ok(());
+            }
+            Ok(_) => {
+                info!(
+                    instance_name,
+                    zone, "Successfully initiated instance deletion"
+                );
+                record_deletion("initiated");
+                limit.release(instance_name);
+                return Ok(());
+            }
+            Err(ComputeError::NotFound) => {
+                tracing::debug!(instance_name, zone, "Instance not found in zone");
+            }
+            Err(other) => {
+                tracing::error!(instance_name, ?other, "Failed to delete instance");
+                return Err(Box::new(
+                    (
+                        http::StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("{other:?}"),
+                    )
+                        .into(),
+                ));
+            }
         }
     }
 
+    info!(
+        instance_name,
+        "Instance not found in any zone (may have already been deleted)"
+    );
+    limit.release(instance_name);
     Ok(())
 }
 
@@ -296,6 +1260,52 @@ mod tests {
     use std::env;
     use tracing_subscriber::EnvFilter;
 
+    #[test]
+    fn redacted_insert_request_hides_jit_config() {
+        let request = ComputePeriodInstancesPeriodInsertParams {
+            project: "test-project".to_string(),
+            zone: "us-central1-a".to_string(),
+            instance: Some(Instance {
+                name: Some("gha-1-2".to_string()),
+                metadata: Some(
+                    compute_v1::Metadata {
+                        items: Some(vec![
+                            compute_v1::MetadataItemsInner {
+                                key: Some("startup-script".to_string()),
+                                value: Some("echo hello".to_string()),
+                            },
+                            compute_v1::MetadataItemsInner {
+                                key: Some("JIT_CONFIG".to_string()),
+                                value: Some("super-secret-jit".to_string()),
+                            },
+                        ]),
+                        ..Default::default()
+                    }
+                    .into(),
+                ),
+                ..Instance::new()
+            }),
+            ..Default::default()
+        };
+
+        let logged = redacted_insert_request(&request);
+
+        assert!(!logged.to_string().contains("super-secret-jit"));
+        assert_eq!(
+            logged["instance"]["metadata"]["items"][1]["value"],
+            "<redacted>"
+        );
+        assert_eq!(
+            logged["instance"]["metadata"]["items"][0]["value"],
+            "echo hello"
+        );
+        assert_eq!(logged["zone"], "us-central1-a");
+
+        // the request itself is left untouched
+        let items = request.instance.unwrap().metadata.unwrap().items.unwrap();
+        assert_eq!(items[1].value.as_deref(), Some("super-secret-jit"));
+    }
+
     #[ignore] // Disabled test - run manually with `cargo test test_create_instance -- --ignored`
     #[tokio::test]
     async fn test_create_instance() {
@@ -345,10 +1355,16 @@ mod tests {
             &github,
             &project_id,
             &region,
+            &[],
             github_token,
             "test-template",
+            None,
+            &crate::config::Config::default(),
             instance_name,
             &mock_event,
+            SystemTime::now(),
+            &NoopMetadataTransform,
+            &InstanceLimit::default(),
         )
         .await;
 
@@ -1,7 +1,15 @@
+pub mod cli;
+pub mod clock;
 pub mod compute;
+pub mod config;
 pub mod github;
+pub mod http;
 pub mod instance;
 pub mod metadata;
+pub mod metrics;
+pub mod quota;
+pub mod reaper;
+pub mod secrets;
 pub mod server;
 pub mod telemetry;
 pub mod utils;
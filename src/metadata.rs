@@ -1,7 +1,77 @@
 use std::env;
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Project ID read from the metadata service, fetched at most once per process
+static METADATA_PROJECT_ID: OnceCell<String> = OnceCell::const_new();
+
+/// Zone read from the metadata service, fetched at most once per process
+static METADATA_ZONE: OnceCell<String> = OnceCell::const_new();
+
+const PROJECT_ID_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/project/project-id";
+const ZONE_URL: &str = "http://metadata.google.internal/computeMetadata/v1/instance/zone";
+
+/// Attempts at a metadata request before giving up
+const METADATA_ATTEMPTS: u32 = 3;
+
+/// Delay before the first metadata retry, doubled after every retry
+const METADATA_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Timeout of each metadata request
+const METADATA_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends a metadata service request, retrying connection errors and 5xx responses with
+/// backoff, e.g. while the server is still starting during instance boot.
+///
+/// The last response is returned whatever its status; 4xx responses aren't retried.
+pub async fn metadata_get(client: &reqwest::Client, url: &str) -> Result<reqwest::Response, Error> {
+    let mut interval = METADATA_RETRY_INTERVAL;
+    let mut attempt = 1;
+    loop {
+        let result = client
+            .get(url)
+            .header("Metadata-Flavor", "Google")
+            .timeout(METADATA_REQUEST_TIMEOUT)
+            .send()
+            .await;
+        let retryable = match &result {
+            Ok(response) => response.status().is_server_error(),
+            Err(_) => true,
+        };
+        if !retryable || attempt == METADATA_ATTEMPTS {
+            return Ok(result?);
+        }
+
+        tracing::warn!(
+            url,
+            attempt,
+            status = result.as_ref().ok().map(|r| r.status().as_u16()),
+            error = result.as_ref().err().map(tracing::field::display),
+            "Retrying metadata service request"
+        );
+        tokio::time::sleep(interval).await;
+        interval *= 2;
+        attempt += 1;
+    }
+}
+
+/// Answers from `cell`, running `fetch` to fill it; a failed fetch isn't cached, so the
+/// next call tries again
+async fn memoized<F, Fut>(cell: &OnceCell<String>, fetch: F) -> Result<String, Error>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<String, Error>>,
+{
+    cell.get_or_try_init(fetch).await.cloned()
+}
 
-/// Gets the current GCP project ID from the environment
-async fn get_project_id() -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+/// Gets the current GCP project ID from the environment or, once per process, the metadata
+/// service
+pub async fn get_project_id(client: &reqwest::Client) -> Result<String, Error> {
     // Try environment variable first
     if let Ok(project_id) = env::var("GOOGLE_CLOUD_PROJECT") {
         return Ok(project_id);
@@ -12,55 +82,50 @@ async fn get_project_id() -> Result<String, Box<dyn std::error::Error + Send + S
     }
 
     // Fallback: try to get from metadata service
-    let client = reqwest::Client::new();
-    let response = client
-        .get("http://metadata.google.internal/computeMetadata/v1/project/project-id")
-        .header("Metadata-Flavor", "Google")
-        .timeout(std::time::Duration::from_secs(5))
-        .send()
-        .await?;
-
-    if response.status().is_success() {
-        Ok(response.text().await?)
-    } else {
-        Err("Unable to determine project ID".into())
-    }
+    memoized(&METADATA_PROJECT_ID, || async {
+        let response = metadata_get(client, PROJECT_ID_URL).await?;
+
+        if response.status().is_success() {
+            Ok(response.text().await?)
+        } else {
+            Err("Unable to determine project ID".into())
+        }
+    })
+    .await
 }
 
-/// Gets the current zone from environment or metadata service.
+/// Gets the current zone from environment or, once per process, the metadata service.
 /// Returns an error if it cannot be determined.
-async fn get_current_zone() -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+async fn get_current_zone(client: &reqwest::Client) -> Result<String, Error> {
     // Try environment variable first
     if let Ok(zone) = env::var("GOOGLE_CLOUD_ZONE") {
         return Ok(zone);
     }
 
     // Fallback: try to get from metadata service
-    let client = reqwest::Client::new();
-    let response = client
-        .get("http://metadata.google.internal/computeMetadata/v1/instance/zone")
-        .header("Metadata-Flavor", "Google")
-        .timeout(std::time::Duration::from_secs(5))
-        .send()
-        .await?;
-
-    if response.status().is_success() {
-        let zone_path = response.text().await?;
-        // Extract zone name from the full path (e.g., "projects/123/zones/us-central1-f" -> "us-central1-f")
-        match zone_path.split('/').next_back() {
-            Some(z) if !z.is_empty() => Ok(z.to_string()),
-            _ => Err("Unable to determine zone from metadata response".into()),
+    memoized(&METADATA_ZONE, || async {
+        let response = metadata_get(client, ZONE_URL).await?;
+
+        if response.status().is_success() {
+            let zone_path = response.text().await?;
+            // Extract zone name from the full path (e.g., "projects/123/zones/us-central1-f" -> "us-central1-f")
+            match zone_path.split('/').next_back() {
+                Some(z) if !z.is_empty() => Ok(z.to_string()),
+                _ => Err("Unable to determine zone from metadata response".into()),
+            }
+        } else {
+            Err("Unable to determine zone".into())
         }
-    } else {
-        Err("Unable to determine zone".into())
-    }
+    })
+    .await
 }
 
-/// Gets the current GCP environment configuration (project ID, zone, and region) in parallel
-pub async fn get_gcp_environment()
--> Result<(String, String), Box<dyn std::error::Error + Send + Sync>> {
+/// Gets the current GCP environment configuration (project ID, zone, and region) in parallel,
+/// querying the metadata service with `client` for whatever the environment doesn't set
+pub async fn get_gcp_environment(client: &reqwest::Client) -> Result<(String, String), Error> {
     // Run project ID and zone lookups in parallel
-    let (project_result, zone_result) = tokio::join!(get_project_id(), get_current_zone());
+    let (project_result, zone_result) =
+        tokio::join!(get_project_id(client), get_current_zone(client));
 
     let project_id = project_result?;
     let zone = zone_result?;
@@ -81,6 +146,30 @@ fn zone_to_region(zone: String) -> String {
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn memoized_fetches_until_the_first_success() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let cell = OnceCell::new();
+        let fetches = AtomicUsize::new(0);
+        let fetch = |ok: bool| {
+            let fetches = &fetches;
+            move || async move {
+                fetches.fetch_add(1, Ordering::Relaxed);
+                if ok {
+                    Ok("my-project".to_string())
+                } else {
+                    Err(Error::from("metadata service unavailable"))
+                }
+            }
+        };
+
+        assert!(memoized(&cell, fetch(false)).await.is_err());
+        assert_eq!(memoized(&cell, fetch(true)).await.unwrap(), "my-project");
+        assert_eq!(memoized(&cell, fetch(false)).await.unwrap(), "my-project");
+        assert_eq!(fetches.load(Ordering::Relaxed), 2);
+    }
+
     #[test]
     fn test_zone_to_region_standard_zones() {
         // Test standard GCP zone naming convention
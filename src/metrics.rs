@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum MetricsError {
+    #[error("metrics registry poisoned by a panic while recording")]
+    Poisoned,
+    #[error("failed to encode metrics: {0}")]
+    Encode(#[from] std::fmt::Error),
+}
+
+/// Source of the metrics served at `/metrics`
+pub trait Gather: Send + Sync {
+    /// Renders every metric in the Prometheus text format
+    fn gather(&self) -> Result<String, MetricsError>;
+}
+
+type LabelSet = Vec<(&'static str, String)>;
+
+/// Upper bounds, in seconds, of the buckets every latency histogram is split into
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Observations of one histogram series
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    /// Non-cumulative count per bucket in [`LATENCY_BUCKETS`]; the last slot is `+Inf`
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Process-wide metrics, rendered in the Prometheus text exposition format.
+#[derive(Default)]
+pub struct Registry {
+    counters: Mutex<BTreeMap<&'static str, BTreeMap<LabelSet, u64>>>,
+    histograms: Mutex<BTreeMap<&'static str, BTreeMap<LabelSet, Histogram>>>,
+    gauges: Mutex<BTreeMap<&'static str, BTreeMap<LabelSet, i64>>>,
+}
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::default);
+
+/// Returns the global metrics registry
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+/// Locks one of the registry's maps, recovering it if a panic poisoned it. Every update is a
+/// single map write, so no series is left half-written; the poison is cleared so scrapes
+/// don't keep failing, and logged once per panic.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        tracing::error!("Metrics registry was poisoned by a panic while recording; recovering");
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+fn label_set(labels: &[(&'static str, &str)]) -> LabelSet {
+    labels.iter().map(|(k, v)| (*k, v.to_string())).collect()
+}
+
+impl Registry {
+    /// Increments the counter `name` for the given label values
+    ///
+    /// Recording never panics, even if an earlier panic poisoned the registry.
+    pub fn increment(&self, name: &'static str, labels: &[(&'static str, &str)]) {
+        let mut counters = lock(&self.counters);
+        let counter = counters
+            .entry(name)
+            .or_default()
+            .entry(label_set(labels))
+            .or_default();
+        *counter = counter.saturating_add(1);
+    }
+
+    /// Current value of the counter `name` for the given label values
+    pub fn counter(&self, name: &'static str, labels: &[(&'static str, &str)]) -> u64 {
+        let counters = lock(&self.counters);
+        counters
+            .get(name)
+            .and_then(|series| series.get(&label_set(labels)))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Adds `delta`, which may be negative, to the gauge `name` for the given label values
+    ///
+    /// Recording never panics, even if an earlier panic poisoned the registry.
+    pub fn add(&self, name: &'static str, labels: &[(&'static str, &str)], delta: i64) {
+        let mut gauges = lock(&self.gauges);
+        let gauge = gauges
+            .entry(name)
+            .or_default()
+            .entry(label_set(labels))
+            .or_default();
+        *gauge = gauge.saturating_add(delta);
+    }
+
+    /// Current value of the gauge `name` for the given label values
+    pub fn gauge(&self, name: &'static str, labels: &[(&'static str, &str)]) -> i64 {
+        let gauges = lock(&self.gauges);
+        gauges
+            .get(name)
+            .and_then(|series| series.get(&label_set(labels)))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Records a latency `seconds` in the histogram `name` for the given label values
+    ///
+    /// Recording never panics, even if an earlier panic poisoned the registry.
+    pub fn observe(&self, name: &'static str, labels: &[(&'static str, &str)], seconds: f64) {
+        let mut histograms = lock(&self.histograms);
+        let histogram = histograms
+            .entry(name)
+            .or_default()
+            .entry(label_set(labels))
+            .or_default();
+
+        histogram.buckets.resize(LATENCY_BUCKETS.len() + 1, 0);
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&le| seconds <= le)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        histogram.buckets[bucket] = histogram.buckets[bucket].saturating_add(1);
+        histogram.sum += seconds;
+        histogram.count = histogram.count.saturating_add(1);
+    }
+
+    /// Number of observations in the histogram `name` for the given label values
+    pub fn histogram_count(&self, name: &'static str, labels: &[(&'static str, &str)]) -> u64 {
+        let histograms = lock(&self.histograms);
+        histograms
+            .get(name)
+            .and_then(|series| series.get(&label_set(labels)))
+            .map_or(0, |h| h.count)
+    }
+
+    /// Renders all metrics in the Prometheus text format.
+    ///
+    /// Each map is copied out and its lock released before formatting, so recording only
+    /// waits on a scrape for as long as the copy takes.
+    pub fn render(&self) -> Result<String, MetricsError> {
+        let counters = lock(&self.counters).clone();
+
+        let mut out = String::new();
+        for (name, series) in counters.iter() {
+            writeln!(out, "# TYPE {name} counter")?;
+            for (labels, value) in series {
+                writeln!(out, "{name}{} {value}", format_labels(labels))?;
+            }
+        }
+
+        let gauges = lock(&self.gauges).clone();
+        for (name, series) in gauges.iter() {
+            writeln!(out, "# TYPE {name} gauge")?;
+            for (labels, value) in series {
+                writeln!(out, "{name}{} {value}", format_labels(labels))?;
+            }
+        }
+
+        let histograms = lock(&self.histograms).clone();
+        for (name, series) in histograms.iter() {
+            writeln!(out, "# TYPE {name} histogram")?;
+            for (labels, histogram) in series {
+                let mut cumulative = 0;
+                let bounds = LATENCY_BUCKETS
+                    .iter()
+                    .map(|le| le.to_string())
+                    .chain(["+Inf".to_string()]);
+                for (le, count) in bounds.zip(&histogram.buckets) {
+                    cumulative += count;
+                    let mut labels = labels.clone();
+                    labels.push(("le", le));
+                    writeln!(out, "{name}_bucket{} {cumulative}", format_labels(&labels))?;
+                }
+                let labels = format_labels(labels);
+                writeln!(out, "{name}_sum{labels} {}", histogram.sum)?;
+                writeln!(out, "{name}_count{labels} {}", histogram.count)?;
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl Gather for Registry {
+    fn gather(&self) -> Result<String, MetricsError> {
+        self.render()
+    }
+}
+
+fn format_labels(labels: &LabelSet) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let labels = labels
+        .iter()
+        .map(|(k, v)| format!("{k}=\"{}\"", v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>();
+    format!("{{{}}}", labels.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_render_in_prometheus_format() {
+        let registry = Registry::default();
+        registry.increment("events_total", &[("reason", "no_route")]);
+        registry.increment("events_total", &[("reason", "no_route")]);
+        registry.increment("events_total", &[("reason", "say \"hi\"")]);
+
+        assert_eq!(
+            registry.counter("events_total", &[("reason", "no_route")]),
+            2
+        );
+        assert_eq!(registry.counter("events_total", &[("reason", "other")]), 0);
+        assert_eq!(
+            registry.render().unwrap(),
+            "# TYPE events_total counter\n\
+             events_total{reason=\"no_route\"} 2\n\
+             events_total{reason=\"say \\\"hi\\\"\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn gauges_go_up_and_down() {
+        let registry = Registry::default();
+        registry.add("in_flight", &[], 2);
+        registry.add("in_flight", &[], -1);
+
+        assert_eq!(registry.gauge("in_flight", &[]), 1);
+        assert_eq!(registry.gauge("other", &[]), 0);
+        assert_eq!(
+            registry.render().unwrap(),
+            "# TYPE in_flight gauge\nin_flight 1\n"
+        );
+    }
+
+    #[test]
+    fn histograms_render_cumulative_buckets() {
+        let registry = Registry::default();
+        registry.observe("latency_seconds", &[("op", "jit")], 0.003);
+        registry.observe("latency_seconds", &[("op", "jit")], 0.2);
+        registry.observe("latency_seconds", &[("op", "jit")], 30.0);
+
+        assert_eq!(
+            registry.histogram_count("latency_seconds", &[("op", "jit")]),
+            3
+        );
+
+        let rendered = registry.render().unwrap();
+        assert!(rendered.contains("# TYPE latency_seconds histogram\n"));
+        assert!(rendered.contains("latency_seconds_bucket{op=\"jit\",le=\"0.005\"} 1\n"));
+        assert!(rendered.contains("latency_seconds_bucket{op=\"jit\",le=\"0.25\"} 2\n"));
+        assert!(rendered.contains("latency_seconds_bucket{op=\"jit\",le=\"+Inf\"} 3\n"));
+        assert!(rendered.contains("latency_seconds_sum{op=\"jit\"} 30.203\n"));
+        assert!(rendered.contains("latency_seconds_count{op=\"jit\"} 3\n"));
+    }
+
+    #[test]
+    fn poisoned_registry_keeps_recording_and_rendering() {
+        let registry = Registry::default();
+        let _ = std::panic::catch_unwind(|| {
+            let _guard = registry.counters.lock().unwrap();
+            panic!("poison the registry");
+        });
+
+        assert!(registry.render().is_ok());
+        registry.increment("events_total", &[]);
+        assert_eq!(registry.counter("events_total", &[]), 1);
+        assert!(registry.render().unwrap().contains("events_total 1\n"));
+        assert!(!registry.counters.is_poisoned());
+    }
+}
@@ -0,0 +1,309 @@
+use crate::clock::Clock;
+use crate::compute::{ComputeApi, ComputeError};
+use crate::config::{
+    Config, DEFAULT_QUOTA_RECONCILE_INTERVAL, DEFAULT_RECONCILE_CONCURRENCY,
+    DEFAULT_RECONCILE_TIMEOUT,
+};
+use crate::instance::REPOSITORY_LABEL;
+use crate::utils::repo_label_value;
+use futures_util::stream::{self, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+use tracing::{info, instrument, warn};
+
+/// An instance counted against its repository's quota
+#[derive(Debug)]
+struct Reservation {
+    repository: String,
+    reserved_at: Instant,
+}
+
+/// In-process tracker of instances per repository, enforcing per-repo limits.
+///
+/// Repositories are keyed by their sanitized instance label value so that counts
+/// rebuilt from GCE line up with reservations made by the webhook handler.
+#[derive(Debug)]
+pub struct RepoQuotas {
+    default_limit: Option<usize>,
+    limits: HashMap<String, usize>,
+    instances: Mutex<HashMap<String, Reservation>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RepoQuotas {
+    pub fn new(config: &Config, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            default_limit: config.max_instances_per_repo,
+            limits: config
+                .repo_instance_limits
+                .iter()
+                .map(|l| (repo_label_value(&l.repository), l.limit))
+                .collect(),
+            instances: Mutex::default(),
+            clock,
+        }
+    }
+
+    /// Whether any repository is limited
+    pub fn is_enabled(&self) -> bool {
+        self.default_limit.is_some() || !self.limits.is_empty()
+    }
+
+    fn limit(&self, repository: &str) -> Option<usize> {
+        self.limits.get(repository).copied().or(self.default_limit)
+    }
+
+    /// Counts `instance` against `repository`, unless that would exceed its limit.
+    ///
+    /// Reserving an instance that is already tracked always succeeds, so webhook
+    /// redeliveries are not rejected.
+    pub fn try_reserve(&self, repository: &str, instance: &str) -> bool {
+        let repository = repo_label_value(repository);
+        let mut instances = self
+            .instances
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if instances.contains_key(instance) {
+            return true;
+        }
+
+        if let Some(limit) = self.limit(&repository) {
+            let in_use = instances
+                .values()
+                .filter(|r| r.repository == repository)
+                .count();
+            if in_use >= limit {
+                return false;
+            }
+        }
+
+        instances.insert(
+            instance.to_string(),
+            Reservation {
+                repository,
+                reserved_at: self.clock.now(),
+            },
+        );
+        true
+    }
+
+    /// Stops counting `instance`
+    pub fn release(&self, instance: &str) {
+        self.instances
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(instance);
+    }
+
+    /// Number of instances currently counted against `repository`
+    pub fn in_use(&self, repository: &str) -> usize {
+        let repository = repo_label_value(repository);
+        self.instances
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .filter(|r| r.repository == repository)
+            .count()
+    }
+
+    /// Replaces the tracked instances with those listed from GCE.
+    ///
+    /// Reservations made after `listed_at` are kept even when missing from the
+    /// listing, since their instances may not have been visible yet.
+    fn replace(&self, listed: HashMap<String, String>, listed_at: Instant) {
+        let mut instances = self
+            .instances
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        instances.retain(|name, r| r.reserved_at > listed_at && !listed.contains_key(name));
+        for (name, repository) in listed {
+            instances.insert(
+                name,
+                Reservation {
+                    repository,
+                    reserved_at: listed_at,
+                },
+            );
+        }
+    }
+}
+
+/// How long a queued job waits for room under `--max-instances` before it is turned away
+pub const INSTANCE_PERMIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Delay GitHub is asked to wait when no instance could be created under `--max-instances`
+pub const INSTANCE_LIMIT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Global cap on runner instances, each holding a permit from its insert until its deletion
+#[derive(Debug, Default)]
+pub struct InstanceLimit {
+    /// Unlimited when unset
+    permits: Option<Arc<Semaphore>>,
+    held: Mutex<HashMap<String, OwnedSemaphorePermit>>,
+}
+
+impl InstanceLimit {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            permits: limit.map(|limit| Arc::new(Semaphore::new(limit))),
+            held: Mutex::default(),
+        }
+    }
+
+    /// Takes a permit for `instance`, waiting up to `timeout` for one to be released.
+    ///
+    /// An instance already holding a permit keeps it, so webhook redeliveries aren't
+    /// rejected. Returns `false` when no permit became available in time.
+    pub async fn acquire(&self, instance: &str, timeout: Duration) -> bool {
+        let Some(permits) = &self.permits else {
+            return true;
+        };
+        if self.holds(instance) {
+            return true;
+        }
+
+        let Ok(Ok(permit)) = tokio::time::timeout(timeout, permits.clone().acquire_owned()).await
+        else {
+            return false;
+        };
+        let previous = self
+            .held
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(instance.to_string(), permit);
+        if previous.is_none() {
+            crate::metrics::registry().add("spotted_arms_instances_in_use", &[], 1);
+        }
+        true
+    }
+
+    fn holds(&self, instance: &str) -> bool {
+        self.held
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(instance)
+    }
+
+    /// Returns the permit held by `instance`, if any
+    pub fn release(&self, instance: &str) {
+        let released = self
+            .held
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(instance);
+        if released.is_some() {
+            crate::metrics::registry().add("spotted_arms_instances_in_use", &[], -1);
+        }
+    }
+
+    /// Number of instances currently holding a permit
+    pub fn in_use(&self) -> usize {
+        self.held
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+}
+
+/// Rebuilds the per-repo counts from the repository-labeled instances in every zone.
+///
+/// Zones are listed `concurrency` at a time, and the whole pass gives up after `timeout`
+/// leaving the counts untouched.
+#[instrument(skip(api, quotas), err(Debug))]
+pub async fn reconcile(
+    api: &dyn ComputeApi,
+    quotas: &RepoQuotas,
+    projects: &[String],
+    zones: &[&str],
+    concurrency: usize,
+    timeout: Duration,
+) -> Result<(), ComputeError> {
+    let listed_at = quotas.clock.now();
+    let filter = format!("labels.{REPOSITORY_LABEL}:*");
+
+    let mut lists = Vec::new();
+    for project in projects {
+        for zone in zones {
+            lists.push(api.list_instances(project, zone, &filter));
+        }
+    }
+    let listing = stream::iter(lists)
+        .buffer_unordered(concurrency.max(1))
+        .collect::<Vec<_>>();
+    let pages = tokio::time::timeout(timeout, listing)
+        .await
+        .map_err(|_| ComputeError::Other(format!("listing instances took over {timeout:?}")))?;
+
+    let mut listed = HashMap::new();
+    for instances in pages {
+        for instance in instances? {
+            let repository = instance
+                .labels
+                .as_ref()
+                .and_then(|l| l.get(REPOSITORY_LABEL))
+                .cloned();
+            if let (Some(name), Some(repository)) = (instance.name, repository) {
+                listed.insert(name, repository);
+            }
+        }
+    }
+
+    let elapsed = quotas.clock.now().duration_since(listed_at);
+    crate::metrics::registry().observe(
+        "spotted_arms_quota_reconcile_duration_seconds",
+        &[],
+        elapsed.as_secs_f64(),
+    );
+    info!(
+        instances = listed.len(),
+        ?elapsed,
+        "Reconciled per-repo instance counts"
+    );
+    quotas.replace(listed, listed_at);
+    Ok(())
+}
+
+/// Periodically reconciles per-repo counts across the default and label-selectable projects
+pub async fn run_reconciler(
+    api: Arc<dyn ComputeApi>,
+    quotas: Arc<RepoQuotas>,
+    config: Arc<Config>,
+    project_id: Arc<String>,
+    zones: Vec<String>,
+) {
+    let zones = zones.iter().map(String::as_str).collect::<Vec<_>>();
+    let projects = std::iter::once(project_id.to_string())
+        .chain(config.label_projects.iter().map(|p| p.project.clone()))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let period = config
+        .quota_reconcile_interval
+        .unwrap_or(DEFAULT_QUOTA_RECONCILE_INTERVAL);
+    let concurrency = config
+        .reconcile_concurrency
+        .unwrap_or(DEFAULT_RECONCILE_CONCURRENCY);
+    let timeout = config
+        .reconcile_timeout
+        .unwrap_or(DEFAULT_RECONCILE_TIMEOUT);
+
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        if let Err(e) = reconcile(
+            api.as_ref(),
+            &quotas,
+            &projects,
+            &zones,
+            concurrency,
+            timeout,
+        )
+        .await
+        {
+            warn!(?e, "Failed to reconcile per-repo instance counts");
+        }
+    }
+}
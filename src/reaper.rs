@@ -0,0 +1,214 @@
+use crate::clock::Clock;
+use crate::compute::{ComputeApi, ComputeError};
+use crate::config::{Config, DEFAULT_REAPER_INTERVAL};
+use crate::instance::{CREATED_BY, CREATED_BY_LABEL};
+use gcloud_sdk::google_rest_apis::compute_v1::instances_api::ComputePeriodInstancesPeriodDeleteParams;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{info, instrument, warn};
+
+/// Parses a GCE `creationTimestamp`, e.g. `2024-05-01T10:20:30.123-07:00`.
+///
+/// humantime only understands UTC timestamps, so the offset is split off and applied here.
+fn parse_creation_timestamp(timestamp: &str) -> Option<SystemTime> {
+    if let Some(utc) = timestamp.strip_suffix('Z') {
+        return humantime::parse_rfc3339_weak(utc).ok();
+    }
+    let split = timestamp.len().checked_sub(6)?;
+    let (local, offset) = (timestamp.get(..split)?, timestamp.get(split..)?);
+    let (sign, offset) = offset.split_at(1);
+    let (hours, minutes) = offset.split_once(':')?;
+    let offset =
+        Duration::from_secs(hours.parse::<u64>().ok()? * 3600 + minutes.parse::<u64>().ok()? * 60);
+    let local = humantime::parse_rfc3339_weak(local).ok()?;
+    match sign {
+        "+" => local.checked_sub(offset),
+        "-" => local.checked_add(offset),
+        _ => None,
+    }
+}
+
+/// A runner instance old enough to be reaped
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReapCandidate {
+    pub project: String,
+    pub zone: String,
+    pub instance: String,
+    /// The instance's `creationTimestamp`, as reported by GCE
+    pub created: String,
+    pub age_seconds: u64,
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Lists the instances this service created whose age exceeds `ttl`, without deleting them.
+///
+/// Only instances labeled `created-by=spotted-arms` are considered. A zone that can't be
+/// listed is logged and skipped.
+#[instrument(skip(api))]
+pub async fn candidates(
+    api: &dyn ComputeApi,
+    projects: &[String],
+    zones: &[&str],
+    ttl: Duration,
+    now: SystemTime,
+) -> Vec<ReapCandidate> {
+    let filter = format!("labels.{CREATED_BY_LABEL}={CREATED_BY}");
+
+    let mut candidates = Vec::new();
+    for project in projects {
+        for zone in zones {
+            let instances = match api.list_instances(project, zone, &filter).await {
+                Ok(instances) => instances,
+                Err(e) => {
+                    warn!(project, zone, ?e, "Failed to list instances to reap");
+                    continue;
+                }
+            };
+
+            for instance in instances {
+                let (Some(name), Some(created)) = (instance.name, instance.creation_timestamp)
+                else {
+                    continue;
+                };
+                let Some(created_at) = parse_creation_timestamp(&created) else {
+                    continue;
+                };
+                let age = now.duration_since(created_at).unwrap_or_default();
+                if age <= ttl {
+                    continue;
+                }
+
+                candidates.push(ReapCandidate {
+                    project: project.clone(),
+                    zone: zone.to_string(),
+                    instance: name,
+                    created,
+                    age_seconds: age.as_secs(),
+                    labels: instance.labels.unwrap_or_default().into_iter().collect(),
+                });
+            }
+        }
+    }
+    candidates
+}
+
+/// Deletes the instances this service created whose age exceeds `ttl`.
+///
+/// An instance that can't be deleted is logged and skipped until the next pass.
+/// Returns the names of the instances whose deletion was initiated.
+#[instrument(skip(api))]
+pub async fn reap(
+    api: &dyn ComputeApi,
+    projects: &[String],
+    zones: &[&str],
+    ttl: Duration,
+    now: SystemTime,
+) -> Vec<String> {
+    let mut reaped = Vec::new();
+    for candidate in candidates(api, projects, zones, ttl, now).await {
+        let ReapCandidate {
+            project,
+            zone,
+            instance,
+            age_seconds,
+            ..
+        } = candidate;
+        let age = humantime::format_duration(Duration::from_secs(age_seconds));
+
+        match api
+            .compute_instances_delete(ComputePeriodInstancesPeriodDeleteParams {
+                project: project.clone(),
+                zone: zone.clone(),
+                instance: instance.clone(),
+                ..Default::default()
+            })
+            .await
+        {
+            Ok(_) | Err(ComputeError::NotFound) => {
+                info!(
+                    project,
+                    zone,
+                    instance,
+                    age = %age,
+                    "Reaped orphaned runner instance"
+                );
+                crate::metrics::registry().increment("spotted_arms_instances_reaped_total", &[]);
+                reaped.push(instance);
+            }
+            Err(e) => {
+                warn!(project, zone, instance, ?e, "Failed to reap instance");
+            }
+        }
+    }
+    reaped
+}
+
+/// Projects the reaper covers: the default one and the label-selectable ones
+pub fn reaper_projects(project_id: &str, config: &Config) -> Vec<String> {
+    std::iter::once(project_id.to_string())
+        .chain(config.label_projects.iter().map(|p| p.project.clone()))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Periodically reaps runner instances older than `ttl` across the default and
+/// label-selectable projects.
+///
+/// With `--reaper-dry-run`, the instances that would be reaped are only logged.
+pub async fn run_reaper(
+    api: Arc<dyn ComputeApi>,
+    config: Arc<Config>,
+    project_id: Arc<String>,
+    zones: Vec<String>,
+    clock: Arc<dyn Clock>,
+    ttl: Duration,
+) {
+    let zones = zones.iter().map(String::as_str).collect::<Vec<_>>();
+    let projects = reaper_projects(&project_id, &config);
+    let period = config.reaper_interval.unwrap_or(DEFAULT_REAPER_INTERVAL);
+
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let now = clock.system_now();
+        if !config.reaper_dry_run {
+            reap(api.as_ref(), &projects, &zones, ttl, now).await;
+            continue;
+        }
+        for candidate in candidates(api.as_ref(), &projects, &zones, ttl, now).await {
+            info!(
+                project = candidate.project,
+                zone = candidate.zone,
+                instance = candidate.instance,
+                age = %humantime::format_duration(Duration::from_secs(candidate.age_seconds)),
+                "Would reap orphaned runner instance (dry run)"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creation_timestamps_with_offsets_are_normalized_to_utc() {
+        let utc = humantime::parse_rfc3339("2024-05-01T17:20:30.123Z").unwrap();
+        assert_eq!(
+            parse_creation_timestamp("2024-05-01T10:20:30.123-07:00"),
+            Some(utc)
+        );
+        assert_eq!(
+            parse_creation_timestamp("2024-05-01T19:20:30.123+02:00"),
+            Some(utc)
+        );
+        assert_eq!(
+            parse_creation_timestamp("2024-05-01T17:20:30.123Z"),
+            Some(utc)
+        );
+        assert_eq!(parse_creation_timestamp("yesterday"), None);
+    }
+}
@@ -0,0 +1,80 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use gcloud_sdk::GoogleRestApi;
+use serde_json::Value;
+
+/// Secret Manager REST API base URL
+const SECRET_MANAGER_URL: &str = "https://secretmanager.googleapis.com/v1";
+
+/// Checks `name` is a full secret version name, e.g.
+/// `projects/my-project/secrets/github/versions/latest`
+pub fn parse_secret_version(name: &str) -> Result<String, String> {
+    match name.split('/').collect::<Vec<_>>().as_slice() {
+        ["projects", project, "secrets", secret, "versions", version]
+            if [project, secret, version].iter().all(|s| !s.is_empty()) =>
+        {
+            Ok(name.to_string())
+        }
+        _ => Err(format!(
+            "secret `{name}` must look like `projects/<project>/secrets/<secret>/versions/<version>`"
+        )),
+    }
+}
+
+/// Reads the payload of the Secret Manager secret version `name`, authenticated like the
+/// Compute API calls
+pub async fn access_secret_version(api: &GoogleRestApi, name: &str) -> Result<String, String> {
+    let failed = |e: &dyn std::fmt::Display| format!("failed to access secret {name}: {e}");
+
+    let response = api
+        .get(format!("{SECRET_MANAGER_URL}/{name}:access"))
+        .await
+        .map_err(|e| failed(&e))?
+        .send()
+        .await
+        .map_err(|e| failed(&e))?;
+    if let Err(e) = response.error_for_status_ref() {
+        return Err(failed(&e));
+    }
+    let body = response.json::<Value>().await.map_err(|e| failed(&e))?;
+    secret_payload(&body).map_err(|e| failed(&e))
+}
+
+/// Decodes the base64 `payload.data` of an `AccessSecretVersionResponse`
+fn secret_payload(body: &Value) -> Result<String, String> {
+    let data = body
+        .pointer("/payload/data")
+        .and_then(Value::as_str)
+        .ok_or("response has no payload")?;
+    let bytes = STANDARD
+        .decode(data)
+        .map_err(|e| format!("payload isn't base64: {e}"))?;
+    String::from_utf8(bytes).map_err(|_| "payload isn't UTF-8".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_versions_need_every_path_segment() {
+        let name = "projects/p/secrets/github/versions/latest";
+        assert_eq!(parse_secret_version(name).as_deref(), Ok(name));
+
+        assert!(parse_secret_version("projects/p/secrets/github").is_err());
+        assert!(parse_secret_version("projects/p/secrets//versions/1").is_err());
+        assert!(parse_secret_version("github").is_err());
+    }
+
+    #[test]
+    fn secret_payload_is_decoded_from_base64() {
+        let body = serde_json::json!({
+            "name": "projects/1/secrets/github/versions/3",
+            "payload": { "data": STANDARD.encode(r#"{"secret":"s"}"#) },
+        });
+        assert_eq!(secret_payload(&body).as_deref(), Ok(r#"{"secret":"s"}"#));
+
+        assert!(secret_payload(&serde_json::json!({})).is_err());
+        assert!(secret_payload(&serde_json::json!({ "payload": { "data": "%%" } })).is_err());
+    }
+}
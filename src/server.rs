@@ -1,21 +1,55 @@
-use crate::compute::{ComputeApi, ComputeClient};
-use crate::github::{GithubApi, GithubClient};
+use crate::clock::{Clock, SystemClock};
+use crate::compute::{
+    ComputeApi, ComputeClient, ComputeError, DeleteLimitedCompute, DryRunCompute,
+};
+use crate::config::{
+    Config, DEFAULT_DELIVERY_CACHE_SIZE, DEFAULT_DELIVERY_CACHE_TTL, DEFAULT_DEREGISTER_RETRIES,
+    DEFAULT_MAX_CONCURRENT_DELETES, DEFAULT_MAX_WEBHOOK_BODY_BYTES, DryRunJit,
+};
+use crate::github::{
+    AppInstallationToken, DeadLetters, DryRunGithub, GithubApi, GithubClient, RateLimitedGithub,
+    RetryingGithub, StaticToken, TokenProvider,
+};
+use crate::http::shared_client;
+use crate::instance::{
+    InstanceLocks, LingeringDeletes, MetadataTransform, NoopMetadataTransform, effective_zones,
+};
 use crate::metadata::get_gcp_environment;
-use crate::telemetry::PropagateHeaders;
-use crate::webhook::handle_workflow_job_event;
+use crate::metrics::Gather;
+use crate::quota::{InstanceLimit, RepoQuotas};
+use crate::reaper::{candidates, reaper_projects};
+use crate::secrets::access_secret_version;
+use crate::telemetry::{DecisionLog, JOB_TRACE_TTL, JobTraces, PropagateHeaders};
+use crate::webhook::{
+    SeenDeliveries, handle_unverified_workflow_job_event, handle_workflow_job_event,
+};
 use axum::Router;
 use axum::body::Body;
-use axum::extract::FromRef;
-use axum::http::Request;
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::{FromRef, Query, State};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Json, Response};
 use axum::routing::{get, post};
 use axum_github_webhook_extract::GithubToken;
+use gcloud_sdk::GoogleRestApi;
+use gcloud_sdk::google_rest_apis::compute_v1::region_instance_templates_api::ComputePeriodRegionInstanceTemplatesPeriodGetParams;
+use reqwest::Url;
 use serde::Deserialize;
-use serde_json::json;
+use serde_json::{Value, json};
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tower::ServiceBuilder;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tower::{BoxError, ServiceBuilder};
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::timeout::TimeoutLayer;
 use tower_http::trace::TraceLayer;
-use tracing::{info, instrument};
+use tracing::{info, instrument, warn};
 
 /// Application state containing shared resources
 #[derive(Clone)]
@@ -24,43 +58,308 @@ pub struct AppState {
     pub github_client: std::sync::Arc<dyn GithubApi>,
     pub project_id: Arc<String>,
     pub region: Arc<String>,
+    /// Zones overriding the region's built-in list; empty uses the built-in list
+    pub zones: Arc<Vec<String>>,
+    /// Labels a job must all carry to be provisioned
+    pub required_labels: Arc<Vec<String>>,
     pub secret: GithubToken,
-    pub token: Arc<String>,
+    /// Bearer tokens for GitHub API calls
+    pub token: Arc<dyn TokenProvider>,
     pub instance_template: Arc<String>,
+    pub config: Arc<Config>,
+    pub repo_quotas: Arc<RepoQuotas>,
+    pub metrics: &'static dyn Gather,
+    /// Config file checked by `POST /config/validate`
+    pub config_file: Option<Arc<PathBuf>>,
+    /// Bearer token the admin routes require, set by `--admin-token`; unset leaves them open
+    pub admin_token: Option<Arc<String>>,
+    /// Queued jobs' spans, linked from the spans handling their completion
+    pub job_traces: Arc<JobTraces>,
+    /// Applied to each runner's metadata right before its instance is inserted
+    pub metadata_transform: Arc<dyn MetadataTransform>,
+    /// Runners whose deregistration kept failing, awaiting cleanup
+    pub dead_letters: Arc<DeadLetters>,
+    /// Current time, for schedules, expiry and timestamps
+    pub clock: Arc<dyn Clock>,
+    /// Deletions of failed jobs' runners held back by `--linger-on-failure`
+    pub lingering_deletes: Arc<LingeringDeletes>,
+    /// NDJSON stream of webhook decisions set by `--decision-log`
+    pub decision_log: Arc<DecisionLog>,
+    /// Delivery ids already handled, so redelivered webhooks are dropped
+    pub deliveries: Arc<SeenDeliveries>,
+    /// Serializes creating and deleting the same runner across concurrent webhooks
+    pub instance_locks: Arc<InstanceLocks>,
+    /// Runner instances counted against `--max-instances`
+    pub instance_limit: Arc<InstanceLimit>,
+    /// Whether startup warmup has finished, as reported by `GET /ready`
+    pub readiness: Arc<Readiness>,
+}
+
+/// Whether the service is ready for traffic; only false while a startup warmup runs
+#[derive(Debug)]
+pub struct Readiness(AtomicBool);
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self(AtomicBool::new(true))
+    }
+}
+
+impl Readiness {
+    /// Not ready until [`Readiness::mark_ready`] is called
+    pub fn warming() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    pub fn mark_ready(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// Where the GitHub credentials JSON is read from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GithubCredentials {
+    /// The JSON itself, e.g. from `--github-credentials`
+    Json(String),
+    /// A file holding the JSON, e.g. from `--github-credentials-file`
+    File(PathBuf),
+    /// A Secret Manager secret version holding the JSON, e.g. from
+    /// `--github-credentials-secret`
+    Secret(String),
+}
+
+impl GithubCredentials {
+    /// Reads and parses the credentials; a Secret Manager secret is fetched with `rest_api`
+    async fn load(
+        &self,
+        rest_api: Option<&GoogleRestApi>,
+    ) -> Result<GithubCredentialsSecret, String> {
+        match self {
+            GithubCredentials::Json(json) => serde_json::from_str(json)
+                .map_err(|e| format!("invalid GitHub credentials JSON: {e}")),
+            GithubCredentials::Secret(name) => {
+                let api = rest_api.ok_or("no Google API client to read secrets with")?;
+                let json = access_secret_version(api, name).await?;
+                serde_json::from_str(&json)
+                    .map_err(|e| format!("invalid GitHub credentials JSON in secret {name}: {e}"))
+            }
+            GithubCredentials::File(path) => {
+                let json = std::fs::read_to_string(path).map_err(|e| {
+                    format!(
+                        "failed to read GitHub credentials file {}: {e}",
+                        path.display()
+                    )
+                })?;
+                serde_json::from_str(&json).map_err(|e| {
+                    format!("invalid GitHub credentials JSON in {}: {e}", path.display())
+                })
+            }
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct GithubCredentialsSecret {
-    token: String,
+    token: Option<String>,
     secret: String,
+    /// GitHub App authenticated as instead of a static `token`
+    app_id: Option<u64>,
+    installation_id: Option<u64>,
+    /// PEM-encoded RSA private key of the app
+    private_key: Option<String>,
+}
+
+impl GithubCredentialsSecret {
+    /// The configured static token, or a GitHub App's installation tokens
+    fn token_provider(
+        &self,
+        api_base: Url,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Arc<dyn TokenProvider>, Box<dyn std::error::Error>> {
+        match (self.app_id, self.installation_id, &self.private_key, &self.token) {
+            (Some(app_id), Some(installation_id), Some(private_key), None) => {
+                let provider = Arc::new(AppInstallationToken::new(
+                    api_base,
+                    app_id,
+                    installation_id,
+                    private_key,
+                    clock,
+                )?);
+                provider.refresh_in_background();
+                Ok(provider)
+            }
+            (None, None, None, Some(token)) => Ok(Arc::new(StaticToken(token.clone()))),
+            _ => Err("github credentials need either a token, or an app_id, installation_id and private_key".into()),
+        }
+    }
 }
 
 impl AppState {
     /// Construct state from provided configuration values.
     pub async fn new_with(
-        creds_json: &str,
+        creds: &GithubCredentials,
         project_id: String,
         region: String,
+        zones: Vec<String>,
         instance_template: String,
+        config: Config,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        let creds: GithubCredentialsSecret = serde_json::from_str(creds_json)?;
+        // only a secret needs Google credentials this early; the other sources fail fast
+        let rest_api = match creds {
+            GithubCredentials::Secret(_) => Some(Arc::new(GoogleRestApi::new().await?)),
+            _ => None,
+        };
+        // fetched once: the parsed secret and token provider live as long as the state
+        let creds = creds.load(rest_api.as_deref()).await?;
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let token = creds.token_provider(config.github_api_base(), clock.clone())?;
+
+        if let Some(zone) = zones
+            .iter()
+            .find(|zone| zone.rsplit_once('-').map(|(r, _)| r) != Some(region.as_str()))
+        {
+            return Err(format!("zone {zone} is not in region {region}").into());
+        }
+
+        let mut compute_client = match rest_api {
+            Some(api) => ComputeClient::from_api(api),
+            None => ComputeClient::new().await?,
+        };
+        if let Some(endpoint) = &config.compute_endpoint {
+            compute_client = compute_client.with_endpoint(endpoint);
+        }
+        let mut compute_client: Arc<dyn ComputeApi> = Arc::new(compute_client);
+        if config.dry_run {
+            warn!("Dry run: instances will not be created or deleted");
+            compute_client = Arc::new(DryRunCompute::new(compute_client));
+        }
+        let compute_client = DeleteLimitedCompute::new(
+            compute_client,
+            config
+                .max_concurrent_deletes
+                .unwrap_or(DEFAULT_MAX_CONCURRENT_DELETES),
+        );
 
-        let compute_client = ComputeClient::new().await?;
+        let mut github_client: Arc<dyn GithubApi> =
+            Arc::new(GithubClient::with_base_url(config.github_api_base()));
+        if let Some(rate) = config.jit_rate_limit {
+            github_client = Arc::new(RateLimitedGithub::new(github_client, rate, clock.clone()));
+        }
+        let dead_letters = Arc::new(DeadLetters::default());
+        github_client = Arc::new(RetryingGithub::new(
+            github_client,
+            config
+                .deregister_retries
+                .unwrap_or(DEFAULT_DEREGISTER_RETRIES),
+            dead_letters.clone(),
+        ));
+        if config.dry_run && config.dry_run_jit == DryRunJit::Stub {
+            github_client = Arc::new(DryRunGithub::new(github_client));
+        }
+
+        let deliveries = Arc::new(SeenDeliveries::new(
+            config
+                .delivery_cache_size
+                .unwrap_or(DEFAULT_DELIVERY_CACHE_SIZE),
+            config
+                .delivery_cache_ttl
+                .unwrap_or(DEFAULT_DELIVERY_CACHE_TTL),
+            clock.clone(),
+        ));
+        let decision_log = match &config.decision_log {
+            Some(path) => DecisionLog::open(path)
+                .map_err(|e| format!("failed to open decision log {}: {e}", path.display()))?,
+            None => DecisionLog::default(),
+        };
 
         Ok(Self {
             compute_client: Arc::new(compute_client),
-            github_client: Arc::new(GithubClient::new()),
+            github_client,
             project_id: Arc::new(project_id),
             region: Arc::new(region),
+            zones: Arc::new(zones),
+            required_labels: Arc::new(config.required_labels()),
             secret: GithubToken(Arc::new(creds.secret)),
-            token: Arc::new(creds.token),
+            token,
             instance_template: Arc::new(instance_template),
+            repo_quotas: Arc::new(RepoQuotas::new(&config, clock.clone())),
+            instance_limit: Arc::new(InstanceLimit::new(config.max_instances)),
+            readiness: Arc::new(if config.warmup || config.warmup_template {
+                Readiness::warming()
+            } else {
+                Readiness::default()
+            }),
+            config: Arc::new(config),
+            metrics: crate::metrics::registry(),
+            config_file: None,
+            admin_token: None,
+            job_traces: Arc::new(JobTraces::new(JOB_TRACE_TTL, clock.clone())),
+            deliveries,
+            metadata_transform: Arc::new(NoopMetadataTransform),
+            dead_letters,
+            clock,
+            lingering_deletes: Arc::default(),
+            decision_log: Arc::new(decision_log),
+            instance_locks: Arc::default(),
         })
     }
 
+    /// Reads the default instance template, so a missing one fails at boot rather than on
+    /// the first webhook.
+    ///
+    /// A templated name can only be resolved against a job's labels and isn't checked.
+    pub async fn validate(&self) -> Result<(), String> {
+        self.check_instance_template().await.map_err(|e| match e {
+            ComputeError::NotFound => format!(
+                "instance template {} does not exist in project {} region {}",
+                self.instance_template, self.project_id, self.region
+            ),
+            e => format!(
+                "failed to read instance template {}: {e}",
+                self.instance_template
+            ),
+        })
+    }
+
+    /// Checks GCP by reading the default instance template and GitHub by validating the
+    /// token, concurrently, returning each check's name and outcome
+    pub async fn check_dependencies(&self) -> [(&'static str, Result<(), String>); 2] {
+        let github = async {
+            let token = self.token.token().await.map_err(|e| e.to_string())?;
+            self.github_client
+                .check_token(&token)
+                .await
+                .map_err(|e| e.to_string())
+        };
+        let (gcp, github) = tokio::join!(self.validate(), github);
+        [("gcp", gcp), ("github", github)]
+    }
+
+    /// Fetches the default instance template unless its name is templated on job labels
+    async fn check_instance_template(&self) -> Result<(), ComputeError> {
+        if self.instance_template.contains('{') {
+            return Ok(());
+        }
+        self.compute_client
+            .compute_region_instance_templates_get(
+                ComputePeriodRegionInstanceTemplatesPeriodGetParams {
+                    project: self.project_id.to_string(),
+                    region: self.region.to_string(),
+                    instance_template: self.instance_template.to_string(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map(drop)
+    }
+
     /// Helper to discover missing project/region via metadata if needed
     pub async fn discover_project_region() -> Result<(String, String), Box<dyn std::error::Error>> {
-        let (project_id, region) = get_gcp_environment()
+        let (project_id, region) = get_gcp_environment(&shared_client())
             .await
             .map_err(|e| -> Box<dyn std::error::Error> { e })?;
         Ok((project_id, region))
@@ -79,36 +378,315 @@ pub async fn ping() -> &'static str {
     "pong"
 }
 
-/// Health check endpoint that returns service status and metadata
-#[instrument]
-pub async fn health_check(request: Request<Body>) -> String {
+/// Metrics endpoint in the Prometheus text exposition format
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    match state.metrics.gather() {
+        Ok(body) => ([(CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response(),
+        Err(e) => {
+            tracing::error!(%e, "Failed to gather metrics");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to gather metrics: {e}"),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Loads the config file and reports whether it would apply cleanly, without applying it
+pub async fn validate_config(State(state): State<AppState>) -> impl IntoResponse {
+    let Some(path) = state.config_file else {
+        return (StatusCode::NOT_FOUND, "no config file configured").into_response();
+    };
+
+    match Config::check(&path) {
+        Ok(_) => Json(json!({
+            "valid": true,
+            "path": path.as_ref(),
+        }))
+        .into_response(),
+        Err(e) => {
+            warn!(%e, path = %path.display(), "Config file failed validation");
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({
+                    "valid": false,
+                    "path": path.as_ref(),
+                    "error": e.to_string(),
+                })),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReaperPreviewParams {
+    /// Overrides `--reaper-ttl`, e.g. `12h`
+    #[serde(default, with = "humantime_serde")]
+    ttl: Option<Duration>,
+}
+
+/// Lists the instances the reaper would delete, with their age and labels, without deleting them
+pub async fn preview_reaper(
+    State(state): State<AppState>,
+    Query(params): Query<ReaperPreviewParams>,
+) -> impl IntoResponse {
+    let Some(ttl) = params.ttl.or(state.config.reaper_ttl) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "no reaper TTL configured; pass one as ?ttl=",
+        )
+            .into_response();
+    };
+    let Some(zones) = effective_zones(&state.region, &state.zones) else {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "unsupported region").into_response();
+    };
+
+    let projects = reaper_projects(&state.project_id, &state.config);
+    let candidates = candidates(
+        state.compute_client.as_ref(),
+        &projects,
+        &zones,
+        ttl,
+        state.clock.system_now(),
+    )
+    .await;
+
+    Json(json!({
+        "ttl": humantime::format_duration(ttl).to_string(),
+        "dry_run": state.config.reaper_dry_run,
+        "candidates": candidates,
+    }))
+    .into_response()
+}
+
+/// Lists the runners whose deregistration ultimately failed, oldest first, so they can be
+/// removed by hand
+pub async fn list_dead_letters(State(state): State<AppState>) -> Json<Value> {
+    Json(json!({ "runners": state.dead_letters.runners() }))
+}
+
+/// Health check endpoint that returns service status and build metadata.
+///
+/// Request headers are only echoed back with `--debug-health`, since they include
+/// secrets such as webhook signatures.
+#[instrument(skip_all)]
+pub async fn health_check(State(state): State<AppState>, request: Request<Body>) -> Json<Value> {
     info!(
         uri = %request.uri(),
         method = %request.method(),
     );
 
-    json!({
+    let mut health = json!({
         "status": "ok",
         "version": env!("CARGO_PKG_VERSION"),
-        "headers": request.headers().iter().map(|(k, v)| (k.as_str(), v.to_str().unwrap_or("invalid utf8"))).collect::<HashMap<_, _>>(),
-    }).to_string()
+        "git_sha": option_env!("VERGEN_GIT_SHA_SHORT").unwrap_or("unknown"),
+    });
+    if state.config.debug_health {
+        health["headers"] = json!(
+            request
+                .headers()
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.to_str().unwrap_or("invalid utf8")))
+                .collect::<HashMap<_, _>>()
+        );
+    }
+    Json(health)
+}
+
+/// Compares without short-circuiting, so the time taken doesn't reveal how much matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Rejects admin requests without `Authorization: Bearer <--admin-token>` when a token is set
+async fn require_admin_token(
+    State(token): State<Option<Arc<String>>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some(token) = token else {
+        return next.run(request).await;
+    };
+    let presented = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let authorized =
+        presented.is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes()));
+    if !authorized {
+        return (StatusCode::UNAUTHORIZED, "admin token required").into_response();
+    }
+    next.run(request).await
 }
 
 /// Creates the Axum router with all routes and middleware configured
 pub fn create_app(state: AppState) -> Router {
+    let max_concurrent_requests = state.config.max_concurrent_requests;
+    let max_webhook_body_bytes = state
+        .config
+        .max_webhook_body_bytes
+        .unwrap_or(DEFAULT_MAX_WEBHOOK_BODY_BYTES);
+    let request_timeout = state.config.request_timeout;
+    let propagate_headers = PropagateHeaders {
+        baggage: state.config.propagate_baggage,
+    };
+
+    let metrics_route = get(metrics).with_state(state.clone());
+    let admin = middleware::from_fn_with_state(state.admin_token.clone(), require_admin_token);
+    let validate_route = post(validate_config)
+        .with_state(state.clone())
+        .layer(admin.clone());
+    let reaper_preview_route = get(preview_reaper)
+        .with_state(state.clone())
+        .layer(admin.clone());
+    let dead_letters_route = get(list_dead_letters)
+        .with_state(state.clone())
+        .layer(admin);
+    let ready_route = get(ready).with_state(state.clone());
+    let health_route = get(health_check).with_state(state.clone());
+
+    if state.admin_token.is_none() {
+        warn!(
+            "Admin routes /config/validate, /reaper/preview and /runners/dead-letters are unauthenticated; set --admin-token or block them at the ingress"
+        );
+    }
+
+    if state.config.echo_mode {
+        warn!(
+            "Echo mode is ENABLED; webhooks are parsed and echoed back without provisioning runners"
+        );
+    }
+
+    let mut webhook = if state.config.no_signature_verification {
+        warn!(
+            "Webhook signature verification is DISABLED; any client able to reach /webhook can provision runners"
+        );
+        post(handle_unverified_workflow_job_event).with_state(state)
+    } else {
+        post(handle_workflow_job_event).with_state(state)
+    };
+
+    // Bounds only how long GitHub waits for a reply; GCE operations already submitted keep
+    // running server-side and are waited on separately up to `operation_timeout`
+    if let Some(timeout) = request_timeout {
+        webhook = webhook.layer(TimeoutLayer::with_status_code(
+            StatusCode::GATEWAY_TIMEOUT,
+            timeout,
+        ));
+    }
+
+    // Shed load past the global cap instead of queueing so GitHub redelivers later
+    if let Some(limit) = max_concurrent_requests {
+        webhook = webhook.layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(|_: BoxError| async {
+                    StatusCode::SERVICE_UNAVAILABLE
+                }))
+                .load_shed()
+                .concurrency_limit(limit),
+        );
+    }
+
+    // Outermost, so oversized bodies are refused (413) before they take a slot or are hashed
+    webhook = webhook.layer(RequestBodyLimitLayer::new(max_webhook_body_bytes));
+
     Router::new()
-        .route(
-            "/webhook",
-            post(handle_workflow_job_event).with_state(state),
-        )
+        .route("/webhook", webhook)
         .route("/ping", get(ping))
-        .route("/health_check", post(health_check))
+        .route("/ready", ready_route)
+        .route("/metrics", metrics_route)
+        .route("/config/validate", validate_route)
+        .route("/reaper/preview", reaper_preview_route)
+        .route("/runners/dead-letters", dead_letters_route)
+        .route("/health_check", health_route)
         .layer(
             ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http().make_span_with(PropagateHeaders)),
+                .layer(TraceLayer::new_for_http().make_span_with(propagate_headers)),
         )
 }
 
+/// Binds the HTTP listener.
+///
+/// The IPv6 wildcard address is made dual-stack explicitly rather than relying on the
+/// platform default; where that isn't supported it falls back to IPv6 only.
+pub fn bind_listener(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6()
+        && addr.ip().is_unspecified()
+        && let Err(e) = socket.set_only_v6(false)
+    {
+        warn!(?e, "Dual-stack sockets unavailable; listening on IPv6 only");
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Prepares the dependencies the first webhook would otherwise wait on, then marks the
+/// service ready.
+///
+/// The Compute API access token and the GitHub token are fetched and, with
+/// `--warmup-template`, the default instance template is read. A failed step is logged and
+/// doesn't hold readiness back, since the first webhook retries it anyway.
+#[instrument(skip_all)]
+pub async fn warm_up(state: &AppState) {
+    let started = tokio::time::Instant::now();
+
+    if let Err(e) = state.compute_client.warm_up().await {
+        warn!(?e, "Failed to warm up the Compute API client");
+    }
+    if let Err(e) = state.token.token().await {
+        warn!(?e, "Failed to warm up the GitHub token");
+    }
+    if state.config.warmup_template
+        && let Err(e) = state.check_instance_template().await
+    {
+        warn!(?e, "Failed to prefetch the instance template");
+    }
+
+    state.readiness.mark_ready();
+    info!(elapsed = ?started.elapsed(), "Warmup complete; ready for traffic");
+}
+
+/// Readiness probe: `503` while the startup warmup runs or while GCP or GitHub can't be
+/// reached, otherwise `200`; see [`AppState::check_dependencies`].
+pub async fn ready(State(state): State<AppState>) -> impl IntoResponse {
+    if !state.readiness.is_ready() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "warming up" })),
+        );
+    }
+
+    let [(_, gcp), (_, github)] = state.check_dependencies().await;
+
+    let check = |name: &str, result: &Result<(), String>| match result {
+        Ok(()) => json!("ok"),
+        Err(e) => {
+            warn!(check = name, error = %e, "Readiness check failed");
+            json!({ "error": e })
+        }
+    };
+    let (code, status) = if gcp.is_ok() && github.is_ok() {
+        (StatusCode::OK, "ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+    let body = json!({
+        "status": status,
+        "checks": { "gcp": check("gcp", &gcp), "github": check("github", &github) },
+    });
+    (code, Json(body))
+}
+
 /// Graceful shutdown signal handler
 pub async fn shutdown_signal() {
     let ctrl_c = async {
@@ -1,29 +1,77 @@
+use crate::clock::{Clock, SystemClock};
 use axum::http::Request;
+use opentelemetry::baggage::BaggageExt;
 use opentelemetry::global;
 use opentelemetry::propagation::TextMapPropagator;
-use opentelemetry::trace::TracerProvider;
+use opentelemetry::trace::{SpanContext, TracerProvider};
 use opentelemetry_gcloud_trace::GcpCloudTraceExporterBuilder;
 use opentelemetry_sdk::Resource;
-use opentelemetry_sdk::propagation::TraceContextPropagator;
-use opentelemetry_sdk::trace::TracerProviderBuilder;
+use opentelemetry_sdk::propagation::{BaggagePropagator, TraceContextPropagator};
+use opentelemetry_sdk::trace::{SdkTracerProvider, TracerProviderBuilder};
+use serde_json::Value;
 use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::time::Instant;
 use tower_http::trace::MakeSpan;
 use tracing::info_span;
 use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
 use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
 
-/// Initialize OpenTelemetry with Google Cloud Trace
+/// Default log filter, quieting chatty dependencies; `RUST_LOG` directives take precedence
+pub const DEFAULT_LOG_FILTER: &str =
+    "info,gcloud_sdk=warn,hyper=warn,hyper_util=warn,h2=warn,reqwest=warn,rustls=warn,tower=warn";
+
+/// Builds the log filter from `defaults` overlaid with `overrides` (typically `RUST_LOG`).
+///
+/// Override directives replace defaults for the same target, so `RUST_LOG=hyper=debug`
+/// re-enables one dependency without losing the others' defaults. Invalid directives are ignored.
+pub fn log_filter(defaults: &str, overrides: Option<&str>) -> EnvFilter {
+    let directives = match overrides.filter(|o| !o.trim().is_empty()) {
+        Some(overrides) => format!("{defaults},{overrides}"),
+        None => defaults.to_string(),
+    };
+    EnvFilter::builder().parse_lossy(directives)
+}
+
+/// Owns the tracer provider so buffered spans can be exported before the process exits
+pub struct TracingGuard {
+    provider: SdkTracerProvider,
+}
+
+impl TracingGuard {
+    pub fn new(provider: SdkTracerProvider) -> Self {
+        Self { provider }
+    }
+
+    /// Flushes pending spans and shuts the provider down; call once the server has drained
+    pub async fn shutdown(self) {
+        // the batch processor's shutdown blocks until its export completes
+        let provider = self.provider;
+        match tokio::task::spawn_blocking(move || provider.shutdown()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::warn!(?e, "Failed to flush spans on shutdown"),
+            Err(e) => tracing::warn!(?e, "Span flush task failed"),
+        }
+    }
+}
+
+/// Initialize OpenTelemetry with Google Cloud Trace, exporting to `project_id_override`
+/// (`--telemetry-project-id` / `PROJECT_ID`) or else the discovered project
 pub async fn init_tracing(
     project_id_override: Option<String>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Prefer explicit override, otherwise discover via metadata
-    let project_id = if let Some(pid) = project_id_override {
-        pid
-    } else {
-        let (pid, _region) = crate::metadata::get_gcp_environment()
+    default_log_filter: &str,
+) -> Result<TracingGuard, Box<dyn std::error::Error>> {
+    // Prefer explicit override, otherwise discover via environment or metadata; the zone
+    // isn't needed, so its lookup can't fail telemetry setup
+    let project_id = match project_id_override {
+        Some(pid) => pid,
+        None => crate::metadata::get_project_id(&crate::http::shared_client())
             .await
-            .map_err(|e| -> Box<dyn std::error::Error> { e })?;
-        pid
+            .map_err(|e| -> Box<dyn std::error::Error> { e })?,
     };
 
     // Create Google Cloud Trace exporter
@@ -51,38 +99,408 @@ pub async fn init_tracing(
 
     // Initialize tracing subscriber with both console and OpenTelemetry layers
     tracing_subscriber::registry()
-        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
+        .with(log_filter(
+            default_log_filter,
+            std::env::var(EnvFilter::DEFAULT_ENV).ok().as_deref(),
+        ))
         .with(tracing_subscriber::fmt::layer().json())
         .with(telemetry_layer)
         .init();
 
-    Ok(())
+    Ok(TracingGuard::new(tracer_provider))
+}
+
+/// Writes one NDJSON line per webhook decision, independent of the tracing subscriber's
+/// format; the default writes nothing
+#[derive(Default)]
+pub struct DecisionLog {
+    out: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl DecisionLog {
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self {
+            out: Some(Mutex::new(Box::new(out))),
+        }
+    }
+
+    /// Appends to the file at `path`, e.g. `/dev/fd/3`, or writes to stdout for `-`
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        if path == Path::new("-") {
+            return Ok(Self::new(std::io::stdout()));
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(file))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.out.is_some()
+    }
+
+    /// Writes `entry` as one line; a failed write is logged and the entry dropped
+    pub fn record(&self, entry: &Value) {
+        let Some(out) = &self.out else {
+            return;
+        };
+        let mut out = out.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Err(e) = writeln!(out, "{entry}").and_then(|()| out.flush()) {
+            tracing::warn!(?e, "Failed to write to the decision log");
+        }
+    }
+}
+
+/// How long a queued job's span is kept for its completion to link back to
+pub const JOB_TRACE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Spans that handled queued jobs, keyed by `(run_id, job_id)`, so the span handling a
+/// job's completion can link to the trace that created its runner
+#[derive(Debug)]
+pub struct JobTraces {
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+    spans: Mutex<HashMap<(u64, u64), (SpanContext, Instant)>>,
+}
+
+impl Default for JobTraces {
+    fn default() -> Self {
+        Self::new(JOB_TRACE_TTL, Arc::new(SystemClock))
+    }
+}
+
+impl JobTraces {
+    pub fn new(ttl: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            ttl,
+            clock,
+            spans: Mutex::default(),
+        }
+    }
+
+    /// Remembers the span that handled the job's queued event, dropping expired entries
+    pub fn record(&self, job: (u64, u64), span: SpanContext) {
+        if !span.is_valid() {
+            return;
+        }
+
+        let now = self.clock.now();
+        let mut spans = self.spans.lock().unwrap_or_else(PoisonError::into_inner);
+        spans.retain(|_, (_, recorded_at)| now.duration_since(*recorded_at) < self.ttl);
+        spans.insert(job, (span, now));
+    }
+
+    /// Takes the span recorded for the job, unless it has expired
+    pub fn take(&self, job: (u64, u64)) -> Option<SpanContext> {
+        let now = self.clock.now();
+        self.spans
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&job)
+            .filter(|(_, recorded_at)| now.duration_since(*recorded_at) < self.ttl)
+            .map(|(span, _)| span)
+    }
+}
+
+/// Checks a W3C `traceparent` header is well-formed: `version-traceid-spanid-flags`
+/// in lowercase hex, with non-zero ids and a version other than `ff`.
+///
+/// Version `00` must be exactly those four fields; later versions may append more.
+fn is_valid_traceparent(traceparent: &str) -> bool {
+    let is_hex = |s: &str, len: usize| {
+        s.len() == len
+            && s.bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    };
+    let is_zero = |s: &str| s.bytes().all(|b| b == b'0');
+
+    let mut fields = traceparent.trim().split('-');
+    let (Some(version), Some(trace_id), Some(span_id), Some(flags)) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return false;
+    };
+
+    is_hex(version, 2)
+        && version != "ff"
+        && (version != "00" || fields.next().is_none())
+        && is_hex(trace_id, 32)
+        && !is_zero(trace_id)
+        && is_hex(span_id, 16)
+        && !is_zero(span_id)
+        && is_hex(flags, 2)
+}
+
+/// Converts a GCP `X-Cloud-Trace-Context` header, `TRACE_ID/SPAN_ID;o=TRACE_TRUE` with a
+/// hex trace id and decimal span id, into the equivalent `traceparent`.
+///
+/// The `;o=` option is optional and only `o=1` marks the trace as sampled.
+fn cloud_trace_context_to_traceparent(header: &str) -> Option<String> {
+    let (trace_id, rest) = header.trim().split_once('/')?;
+    let (span_id, options) = rest.split_once(';').unwrap_or((rest, ""));
+    let span_id: u64 = span_id.parse().ok()?;
+    let flags = if options == "o=1" { "01" } else { "00" };
+
+    let traceparent = format!(
+        "00-{}-{span_id:016x}-{flags}",
+        trace_id.to_ascii_lowercase()
+    );
+    is_valid_traceparent(&traceparent).then_some(traceparent)
 }
 
 /// Custom trace span creator that propagates OpenTelemetry context from HTTP headers
-#[derive(Copy, Clone, Debug)]
-pub struct PropagateHeaders;
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PropagateHeaders {
+    /// Also propagate W3C `baggage`, recording each entry as a `baggage.<key>` span attribute
+    pub baggage: bool,
+}
 
 impl<B> MakeSpan<B> for PropagateHeaders {
     fn make_span(&mut self, request: &Request<B>) -> tracing::Span {
         static TRACEPARENT: &str = "traceparent";
+        static BAGGAGE: &str = "baggage";
+        static CLOUD_TRACE_CONTEXT: &str = "x-cloud-trace-context";
 
-        let traceparent = request
-            .headers()
-            .get(TRACEPARENT)
-            .and_then(|v| v.to_str().ok());
+        let header = |name| request.headers().get(name).and_then(|v| v.to_str().ok());
 
-        let extractor = if let Some(tp) = traceparent {
-            HashMap::from([(TRACEPARENT.to_string(), tp.to_string())])
-        } else {
-            HashMap::new()
+        // a malformed parent would detach the span, so start a fresh trace instead
+        let mut extractor = match header(TRACEPARENT) {
+            Some(tp) if is_valid_traceparent(tp) => {
+                HashMap::from([(TRACEPARENT.to_string(), tp.to_string())])
+            }
+            Some(tp) => {
+                tracing::debug!(traceparent = tp, "Ignoring malformed traceparent header");
+                crate::metrics::registry()
+                    .increment("spotted_arms_malformed_traceparent_total", &[]);
+                HashMap::new()
+            }
+            // GCP load balancers and clients only send their own header
+            None => header(CLOUD_TRACE_CONTEXT)
+                .and_then(cloud_trace_context_to_traceparent)
+                .map(|tp| HashMap::from([(TRACEPARENT.to_string(), tp)]))
+                .unwrap_or_default(),
         };
+        if let Some(baggage) = header(BAGGAGE).filter(|_| self.baggage) {
+            extractor.insert(BAGGAGE.to_string(), baggage.to_string());
+        }
 
-        let propagator = TraceContextPropagator::new();
-        let context = propagator.extract(&extractor);
+        let mut context = TraceContextPropagator::new().extract(&extractor);
+        if self.baggage {
+            context = BaggagePropagator::new().extract_with_context(&context, &extractor);
+        }
 
         let span = info_span!("axum");
+        for (key, (value, _)) in context.baggage() {
+            span.set_attribute(format!("baggage.{key}"), value.to_string());
+        }
         let _ = span.set_parent(context);
         span
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+
+    /// Makes a span for a request with the given headers and returns its OpenTelemetry context
+    fn span_context_for(
+        mut make_span: PropagateHeaders,
+        headers: &[(&str, &str)],
+    ) -> opentelemetry::Context {
+        let provider = SdkTracerProvider::builder().build();
+        let subscriber =
+            tracing_subscriber::registry().with(OpenTelemetryLayer::new(provider.tracer("test")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let mut request = Request::builder();
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            make_span.make_span(&request.body(()).unwrap()).context()
+        })
+    }
+
+    fn trace_id_for(traceparent: Option<&str>) -> TraceId {
+        let headers = traceparent.map(|tp| ("traceparent", tp));
+        span_context_for(PropagateHeaders::default(), headers.as_slice())
+            .span()
+            .span_context()
+            .trace_id()
+    }
+
+    #[test]
+    fn job_traces_expire_after_their_ttl() {
+        let clock = Arc::new(crate::clock::FakeClock::new(std::time::SystemTime::now()));
+        let traces = JobTraces::new(Duration::from_secs(60), clock.clone());
+        let span = SpanContext::new(
+            TraceId::from(1),
+            SpanId::from(1),
+            TraceFlags::SAMPLED,
+            false,
+            TraceState::default(),
+        );
+
+        traces.record((1, 1), span.clone());
+        assert_eq!(traces.take((1, 1)), Some(span.clone()));
+
+        traces.record((1, 2), span);
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(traces.take((1, 2)), None);
+    }
+
+    #[test]
+    fn log_filter_applies_defaults_and_honors_overrides() {
+        let enabled = |filter: EnvFilter| {
+            let subscriber = tracing_subscriber::registry().with(filter);
+            tracing::subscriber::with_default(subscriber, || {
+                (
+                    tracing::enabled!(target: "spotted_arms", tracing::Level::INFO),
+                    tracing::enabled!(target: "hyper::proto", tracing::Level::INFO),
+                    tracing::enabled!(target: "gcloud_sdk", tracing::Level::WARN),
+                )
+            })
+        };
+
+        assert_eq!(
+            enabled(log_filter(DEFAULT_LOG_FILTER, None)),
+            (true, false, true)
+        );
+        assert_eq!(
+            enabled(log_filter(DEFAULT_LOG_FILTER, Some("hyper=debug"))),
+            (true, true, true)
+        );
+        // a bare level only replaces the global default; per-target defaults still apply
+        assert_eq!(
+            enabled(log_filter(DEFAULT_LOG_FILTER, Some("error"))),
+            (false, false, true)
+        );
+    }
+
+    #[test]
+    fn traceparent_validation() {
+        assert!(is_valid_traceparent(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        ));
+        assert!(is_valid_traceparent(
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-future"
+        ));
+
+        for malformed in [
+            "",
+            "garbage",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6-00f067aa0ba902b7-01",
+        ] {
+            assert!(!is_valid_traceparent(malformed), "{malformed}");
+        }
+    }
+
+    #[test]
+    fn span_parent_from_traceparent() {
+        let valid = trace_id_for(Some(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ));
+        assert_eq!(
+            valid,
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+        );
+
+        // malformed and absent parents both start a fresh, valid trace
+        let malformed = trace_id_for(Some("00-not-a-traceparent-01"));
+        let absent = trace_id_for(None);
+        assert_ne!(malformed, TraceId::INVALID);
+        assert_ne!(absent, TraceId::INVALID);
+        assert_ne!(malformed, absent);
+    }
+
+    #[test]
+    fn cloud_trace_context_conversion() {
+        assert_eq!(
+            cloud_trace_context_to_traceparent(
+                "4bf92f3577b34da6a3ce929d0e0e4736/67667974448284343;o=1"
+            )
+            .as_deref(),
+            Some("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+        );
+        assert_eq!(
+            cloud_trace_context_to_traceparent("4BF92F3577B34DA6A3CE929D0E0E4736/1").as_deref(),
+            Some("00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000001-00")
+        );
+
+        for malformed in [
+            "",
+            "4bf92f3577b34da6a3ce929d0e0e4736",
+            "4bf92f3577b34da6a3ce929d0e0e4736/0;o=1",
+            "4bf92f3577b34da6a3ce929d0e0e4736/00f067aa0ba902b7;o=1",
+            "4bf92f3577b34da6/1;o=1",
+            "00000000000000000000000000000000/1;o=1",
+        ] {
+            assert_eq!(
+                cloud_trace_context_to_traceparent(malformed),
+                None,
+                "{malformed}"
+            );
+        }
+    }
+
+    #[test]
+    fn span_parent_from_cloud_trace_context() {
+        let cloud_trace = (
+            "x-cloud-trace-context",
+            "0af7651916cd43dd8448eb211c80319c/67667974448284343;o=1",
+        );
+        let context = span_context_for(PropagateHeaders::default(), &[cloud_trace]);
+        let span = context.span().span_context().clone();
+        assert_eq!(
+            span.trace_id(),
+            TraceId::from_hex("0af7651916cd43dd8448eb211c80319c").unwrap()
+        );
+        assert!(span.is_sampled());
+
+        // traceparent wins when both are sent
+        let headers = [
+            cloud_trace,
+            (
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            ),
+        ];
+        let context = span_context_for(PropagateHeaders::default(), &headers);
+        assert_eq!(
+            context.span().span_context().trace_id(),
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+        );
+    }
+
+    #[test]
+    fn baggage_propagates_when_enabled() {
+        let headers = [
+            (
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            ),
+            ("baggage", "tenant=acme,region=us"),
+        ];
+
+        let context = span_context_for(PropagateHeaders { baggage: true }, &headers);
+        assert_eq!(
+            context.baggage().get("tenant").map(|v| v.as_str()),
+            Some("acme")
+        );
+        assert_eq!(
+            context.baggage().get("region").map(|v| v.as_str()),
+            Some("us")
+        );
+        assert_eq!(
+            context.span().span_context().trace_id(),
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+        );
+
+        let context = span_context_for(PropagateHeaders::default(), &headers);
+        assert_eq!(context.baggage().len(), 0);
+    }
+}
@@ -1,12 +1,18 @@
 use octocrab::models::webhook_events::payload::WorkflowJobWebhookEventPayload;
 use serde_json::Value;
+use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use thiserror::Error;
 
 /// Generates a deterministic instance name from a workflow job event.
 ///
-/// The name format is: `gha-{run_id}-{job_id}`
+/// The name format is: `gha-{run_id}-{run_attempt}-{job_id}`
 /// - Lowercased; only `[a-z0-9-]` are retained
 /// - Truncated to 63 characters
-/// - 1:1 mapping per job via `run_id` and `id`
+/// - 1:1 mapping per job attempt via `run_id`, `run_attempt` and `id`, so a rerun doesn't
+///   collide with the previous attempt's instance while it is still being deleted
+/// - A missing `run_attempt` counts as the first attempt
 ///
 /// Example
 ///
@@ -25,28 +31,606 @@ use serde_json::Value;
 /// }"#).unwrap();
 ///
 /// let name = spotted_arms::utils::make_instance_name(&payload);
-/// assert_eq!(name, "gha-123-42");
+/// assert_eq!(name.as_deref(), Ok("gha-123-1-42"));
 /// ```
-pub fn make_instance_name(payload: &WorkflowJobWebhookEventPayload) -> String {
+pub fn make_instance_name(payload: &WorkflowJobWebhookEventPayload) -> Result<String, NameError> {
     let job = &payload.workflow_job;
-
-    // deterministic, <= 63 chars; include run_id for 1:1 mapping
-    format!(
-        "gha-{}-{}",
-        job.get("run_id")
+    let id = |field| {
+        job.get(field)
             .and_then(Value::as_i64)
-            .unwrap_or_default(),
-        job.get("id").and_then(Value::as_i64).unwrap_or_default(),
-    )
-    .to_lowercase()
-    .chars()
-    .filter(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '-')
-    .take(63)
-    .collect()
+            .ok_or(NameError::MissingId(field))
+    };
+
+    // deterministic, <= 63 chars; include run_id and run_attempt for 1:1 mapping
+    Ok(sanitize_instance_name(&format!(
+        "gha-{}-{}-{}",
+        id("run_id")?,
+        job.get("run_attempt").and_then(Value::as_i64).unwrap_or(1),
+        id("id")?,
+    )))
+}
+
+/// Why a workflow job can't be given an instance name
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum NameError {
+    /// Without its ids, distinct jobs would share a name and clobber each other's instances
+    #[error("workflow job has no numeric `{0}`")]
+    MissingId(&'static str),
+}
+
+/// Shortest instance name [`sanitize_instance_name`] returns
+const MIN_INSTANCE_NAME_LEN: usize = 4;
+
+/// Reduces `raw` to an RFC 1035 name as GCE requires: `[a-z0-9-]`, starting with a letter,
+/// not ending with a hyphen, and within the 63 character limit.
+///
+/// Runs of hyphens collapse into one, and names that don't start with a letter get a `gha-`
+/// prefix. Names that filter down to fewer than [`MIN_INSTANCE_NAME_LEN`] characters get a
+/// hash of `raw` appended, so distinct inputs still map to distinct, valid names.
+fn sanitize_instance_name(raw: &str) -> String {
+    let mut name = String::new();
+    for c in raw
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '-')
+    {
+        if c != '-' || !name.ends_with('-') {
+            name.push(c);
+        }
+    }
+    if name.len() >= MIN_INSTANCE_NAME_LEN {
+        if !name.starts_with(|c: char| c.is_ascii_lowercase()) {
+            name = format!("gha-{}", name.trim_start_matches('-'));
+        }
+        name.truncate(63);
+        return name.trim_end_matches('-').to_string();
+    }
+
+    let mut hasher = DefaultHasher::new();
+    raw.hash(&mut hasher);
+    let stem = name.trim_matches('-');
+    let prefix = if stem.starts_with(|c: char| c.is_ascii_lowercase()) {
+        ""
+    } else {
+        "gha-"
+    };
+    let separator = if stem.is_empty() { "" } else { "-" };
+    format!("{prefix}{stem}{separator}{:016x}", hasher.finish())
+}
+
+/// Names the instances of a batch created for one job.
+///
+/// The first keeps the job's instance name so single-instance jobs are unaffected;
+/// the rest append their index, e.g. `gha-123-1-42-1`.
+pub fn batch_instance_names(instance_name: &str, count: usize) -> Vec<String> {
+    (0..count.max(1))
+        .map(|i| match i {
+            0 => instance_name.to_string(),
+            i => format!("{instance_name}-{i}"),
+        })
+        .collect()
+}
+
+/// Runner architecture labels and the `{arch}` value each resolves to
+const ARCH_LABELS: &[(&str, &str)] = &[("arm64", "arm64"), ("arm", "arm"), ("x64", "x64")];
+
+/// Runner OS labels and the `{os}` value each resolves to
+const OS_LABELS: &[(&str, &str)] = &[
+    ("linux", "linux"),
+    ("windows", "windows"),
+    ("macos", "macos"),
+];
+
+/// Finds the first job label (case-insensitively) in `known`
+fn label_value<'a>(labels: &HashSet<String>, known: &[(&str, &'a str)]) -> Option<&'a str> {
+    known
+        .iter()
+        .find(|(label, _)| labels.iter().any(|l| l.eq_ignore_ascii_case(label)))
+        .map(|(_, value)| *value)
+}
+
+/// Resolves `{region}`, `{arch}` and `{os}` placeholders in an instance template name.
+///
+/// `{arch}` and `{os}` come from the job's runner labels (e.g. `ARM64`, `linux`). The
+/// result must be a valid GCE resource name: lowercase letters, digits and hyphens,
+/// starting with a letter, at most 63 characters.
+///
+/// ```rust
+/// use std::collections::HashSet;
+///
+/// let labels = HashSet::from(["linux".to_string(), "ARM64".to_string()]);
+/// let name = spotted_arms::utils::resolve_template_name("runner-{region}-{arch}", "us-central1", &labels);
+/// assert_eq!(name.as_deref(), Ok("runner-us-central1-arm64"));
+/// ```
+pub fn resolve_template_name(
+    pattern: &str,
+    region: &str,
+    labels: &HashSet<String>,
+) -> Result<String, String> {
+    let resolved = fill_placeholders(pattern, |name| match name {
+        "region" => Some(Some(region)),
+        "arch" => Some(label_value(labels, ARCH_LABELS)),
+        "os" => Some(label_value(labels, OS_LABELS)),
+        _ => None,
+    })
+    .map_err(|e| e.describe("template", pattern, "job labels don't resolve"))?;
+
+    let valid = resolved.len() <= 63
+        && resolved.starts_with(|c: char| c.is_ascii_lowercase())
+        && !resolved.ends_with('-')
+        && resolved
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !valid {
+        return Err(format!(
+            "resolved template name `{resolved}` is not a valid GCE name"
+        ));
+    }
+    Ok(resolved)
+}
+
+/// Job label prefix overriding the template's machine type, e.g. `machine:n2-standard-8`
+pub const MACHINE_TYPE_LABEL_PREFIX: &str = "machine:";
+
+/// The machine type requested with a `machine:<type>` job label, if any.
+///
+/// The type must match `^[a-z]([a-z0-9-]{0,61}[a-z0-9])?$`, as GCE machine types such as
+/// `n2-standard-8` or `custom-4-8192` do.
+///
+/// ```
+/// use std::collections::HashSet;
+///
+/// let labels = HashSet::from(["linux".to_string(), "machine:n2-standard-8".to_string()]);
+/// assert_eq!(spotted_arms::utils::machine_type_label(&labels), Ok(Some("n2-standard-8")));
+/// ```
+pub fn machine_type_label(labels: &HashSet<String>) -> Result<Option<&str>, String> {
+    let Some(machine_type) = labels
+        .iter()
+        .find_map(|l| l.strip_prefix(MACHINE_TYPE_LABEL_PREFIX))
+    else {
+        return Ok(None);
+    };
+
+    if !is_resource_name(machine_type) {
+        return Err(format!(
+            "machine type `{machine_type}` is not a valid GCE machine type"
+        ));
+    }
+    Ok(Some(machine_type))
+}
+
+/// Whether `name` matches `^[a-z]([a-z0-9-]{0,61}[a-z0-9])?$`, the GCE resource name format
+fn is_resource_name(name: &str) -> bool {
+    name.len() <= 63
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && !name.ends_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Most labels or network tags GCE accepts on one instance
+const MAX_GCE_LABELS: usize = 64;
+
+/// Whether `c` may appear in a GCE label key or value
+fn is_label_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-'
+}
+
+/// Checks labels against GCE's rules, describing every one GCE would reject.
+///
+/// Keys must be 1-63 characters of `[a-z0-9_-]` starting with a letter; values may be
+/// empty but are otherwise held to the same characters and length.
+///
+/// ```
+/// assert!(spotted_arms::utils::validate_gce_labels([("created-by", "spotted-arms")]).is_ok());
+/// assert!(spotted_arms::utils::validate_gce_labels([("Team", "infra")]).is_err());
+/// ```
+pub fn validate_gce_labels<'a>(
+    labels: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<(), Vec<String>> {
+    let mut problems = Vec::new();
+    let mut count = 0;
+    for (key, value) in labels {
+        count += 1;
+        if !key.starts_with(|c: char| c.is_ascii_lowercase()) {
+            problems.push(format!(
+                "label key `{key}` must start with a lowercase letter"
+            ));
+        }
+        if key.len() > 63 {
+            problems.push(format!("label key `{key}` is longer than 63 characters"));
+        }
+        if !key.chars().all(is_label_char) {
+            problems.push(format!(
+                "label key `{key}` may only contain lowercase letters, digits, `_` and `-`"
+            ));
+        }
+        if value.len() > 63 {
+            problems.push(format!(
+                "label `{key}` value `{value}` is longer than 63 characters"
+            ));
+        }
+        if !value.chars().all(is_label_char) {
+            problems.push(format!(
+                "label `{key}` value `{value}` may only contain lowercase letters, digits, `_` and `-`"
+            ));
+        }
+    }
+    if count > MAX_GCE_LABELS {
+        problems.push(format!(
+            "{count} labels exceed GCE's limit of {MAX_GCE_LABELS}"
+        ));
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+/// Checks network tags against GCE's rules, describing every one GCE would reject.
+///
+/// Tags follow RFC 1035: 1-63 characters of `[a-z0-9-]`, starting with a letter and not
+/// ending with a hyphen.
+pub fn validate_gce_tags<'a>(tags: impl IntoIterator<Item = &'a str>) -> Result<(), Vec<String>> {
+    let mut problems = Vec::new();
+    let mut count = 0;
+    for tag in tags {
+        count += 1;
+        if !is_resource_name(tag) {
+            problems.push(format!(
+                "network tag `{tag}` must be 1-63 lowercase letters, digits or `-`, starting with a letter and not ending with `-`"
+            ));
+        }
+    }
+    if count > MAX_GCE_LABELS {
+        problems.push(format!(
+            "{count} network tags exceed GCE's limit of {MAX_GCE_LABELS}"
+        ));
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+/// Resolves a boot disk snapshot to the partial URL GCE expects in `sourceSnapshot`.
+///
+/// Accepts a bare snapshot name, which is looked up in the instance's own project, or a
+/// `global/snapshots/<name>` or `projects/<project>/global/snapshots/<name>` resource path.
+///
+/// ```
+/// use spotted_arms::utils::snapshot_resource;
+///
+/// assert_eq!(snapshot_resource("golden-1").as_deref(), Ok("global/snapshots/golden-1"));
+/// assert_eq!(
+///     snapshot_resource("projects/images/global/snapshots/golden-1").as_deref(),
+///     Ok("projects/images/global/snapshots/golden-1")
+/// );
+/// assert!(snapshot_resource("Golden_1").is_err());
+/// ```
+pub fn snapshot_resource(snapshot: &str) -> Result<String, String> {
+    let parts = snapshot.split('/').collect::<Vec<_>>();
+    let valid = match parts.as_slice() {
+        [name] | ["global", "snapshots", name] => is_resource_name(name),
+        // project ids are 6-30 characters, but follow the same character rules
+        ["projects", project, "global", "snapshots", name] => {
+            is_resource_name(project) && is_resource_name(name)
+        }
+        _ => false,
+    };
+    if !valid {
+        return Err(format!(
+            "boot disk snapshot `{snapshot}` is not a snapshot name or `[projects/<project>/]global/snapshots/<name>` path"
+        ));
+    }
+    Ok(match parts.as_slice() {
+        [name] => format!("global/snapshots/{name}"),
+        _ => snapshot.to_string(),
+    })
+}
+
+/// Values a runner name pattern can refer to
+#[derive(Clone, Copy, Debug)]
+pub struct RunnerNameParts<'a> {
+    pub instance: &'a str,
+    pub zone: &'a str,
+    pub project: &'a str,
+    /// Repository `owner/name`
+    pub repository: &'a str,
+}
+
+/// Resolves `{instance}`, `{zone}`, `{project}`, `{repo}` and `{hostname}` placeholders
+/// in a runner name pattern.
+///
+/// `{hostname}` is the instance's internal DNS name, `<instance>.<zone>.c.<project>.internal`,
+/// and `{repo}` is the repository as an instance label value. GitHub runner names may only
+/// contain letters, digits, `-`, `_` and `.`.
+///
+/// ```rust
+/// use spotted_arms::utils::{RunnerNameParts, resolve_runner_name};
+///
+/// let parts = RunnerNameParts {
+///     instance: "gha-1-2",
+///     zone: "us-central1-a",
+///     project: "my-project",
+///     repository: "owner/repo",
+/// };
+/// let name = resolve_runner_name("{repo}-{instance}", &parts);
+/// assert_eq!(name.as_deref(), Ok("owner-repo-gha-1-2"));
+/// ```
+pub fn resolve_runner_name(pattern: &str, parts: &RunnerNameParts) -> Result<String, String> {
+    let hostname = format!(
+        "{}.{}.c.{}.internal",
+        parts.instance, parts.zone, parts.project
+    );
+    let repo = repo_label_value(parts.repository);
+    let resolved = fill_placeholders(pattern, |name| match name {
+        "instance" => Some(Some(parts.instance)),
+        "zone" => Some(Some(parts.zone)),
+        "project" => Some(Some(parts.project)),
+        "repo" => Some(Some(&repo)),
+        "hostname" => Some(Some(&hostname)),
+        _ => None,
+    })
+    .map_err(|e| e.describe("runner name", pattern, "nothing resolves"))?;
+
+    let valid = !resolved.is_empty()
+        && resolved
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(format!("resolved runner name `{resolved}` is not valid"));
+    }
+    Ok(resolved)
+}
+
+/// Why a pattern's placeholders couldn't be filled
+enum PlaceholderError {
+    Unterminated,
+    Unknown(String),
+    Unresolved(String),
+}
+
+impl PlaceholderError {
+    fn describe(self, kind: &str, pattern: &str, unresolved: &str) -> String {
+        match self {
+            Self::Unterminated => format!("unterminated placeholder in {kind} `{pattern}`"),
+            Self::Unknown(name) => {
+                format!("unknown placeholder `{{{name}}}` in {kind} `{pattern}`")
+            }
+            Self::Unresolved(placeholder) => {
+                format!("{unresolved} {placeholder} in {kind} `{pattern}`")
+            }
+        }
+    }
+}
+
+/// Replaces each `{name}` in `pattern` with `value(name)`, which is `None` for unknown
+/// placeholders and `Some(None)` for known ones without a value
+fn fill_placeholders<'a>(
+    pattern: &str,
+    value: impl Fn(&str) -> Option<Option<&'a str>>,
+) -> Result<String, PlaceholderError> {
+    let mut resolved = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        resolved.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or(PlaceholderError::Unterminated)?;
+        let name = &rest[start + 1..start + end];
+        let placeholder = &rest[start..=start + end];
+        let value = value(name).ok_or_else(|| PlaceholderError::Unknown(name.to_string()))?;
+        resolved
+            .push_str(value.ok_or_else(|| PlaceholderError::Unresolved(placeholder.to_string()))?);
+        rest = &rest[start + end + 1..];
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+/// Derives a GCE network tag from a repository `owner/name`, e.g. `gha-owner-repo`.
+///
+/// Network tags must comply with RFC1035, so anything outside `[a-z0-9]` becomes a
+/// single hyphen and the result is truncated to 63 characters. Returns `None` when
+/// nothing usable remains.
+pub fn repo_network_tag(full_name: &str) -> Option<String> {
+    let mut sanitized = String::with_capacity(full_name.len());
+    for c in full_name.chars().map(|c| c.to_ascii_lowercase()) {
+        if c.is_ascii_lowercase() || c.is_ascii_digit() {
+            sanitized.push(c);
+        } else if !sanitized.is_empty() && !sanitized.ends_with('-') {
+            sanitized.push('-');
+        }
+    }
+
+    let sanitized = sanitized.trim_end_matches('-');
+    if sanitized.is_empty() {
+        return None;
+    }
+
+    let tag = format!("gha-{sanitized}")
+        .chars()
+        .take(63)
+        .collect::<String>();
+
+    Some(tag.trim_end_matches('-').to_string())
+}
+
+/// Derives a GCE label value from a repository `owner/name`, e.g. `owner-repo`.
+pub fn repo_label_value(full_name: &str) -> String {
+    sanitize_label_value(full_name)
+}
+
+/// Reduces `raw` to a GCE label value.
+///
+/// Label values allow only `[a-z0-9_-]` and at most 63 characters, so other
+/// characters become hyphens and the result is truncated.
+pub fn sanitize_label_value(raw: &str) -> String {
+    raw.chars()
+        .map(|c| c.to_ascii_lowercase())
+        .map(|c| if is_label_char(c) { c } else { '-' })
+        .take(63)
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    /// Test template placeholders resolve from the region and job labels
+    #[test]
+    fn test_resolve_template_name() {
+        let labels = |ls: &[&str]| ls.iter().map(|l| l.to_string()).collect::<HashSet<_>>();
+        let arm = labels(&["self-hosted", "linux", "ARM64"]);
+
+        assert_eq!(
+            resolve_template_name("runner-{region}-{os}-{arch}", "us-central1", &arm).as_deref(),
+            Ok("runner-us-central1-linux-arm64")
+        );
+        assert_eq!(
+            resolve_template_name("plain-template", "us-central1", &arm).as_deref(),
+            Ok("plain-template")
+        );
+        assert_eq!(
+            resolve_template_name("runner-{arch}", "us-central1", &labels(&["X64"])).as_deref(),
+            Ok("runner-x64")
+        );
+
+        // unresolvable, unknown, or invalid results are rejected
+        assert!(
+            resolve_template_name("runner-{arch}", "us-central1", &labels(&["linux"])).is_err()
+        );
+        assert!(resolve_template_name("runner-{zone}", "us-central1", &arm).is_err());
+        assert!(resolve_template_name("runner-{arch", "us-central1", &arm).is_err());
+        assert!(resolve_template_name("Runner_{arch}", "us-central1", &arm).is_err());
+        assert!(
+            resolve_template_name(
+                &format!("{}-{{region}}", "r".repeat(60)),
+                "us-central1",
+                &arm
+            )
+            .is_err()
+        );
+    }
+
+    /// Test runner name placeholders resolve independently of the instance name
+    #[test]
+    fn test_resolve_runner_name() {
+        let parts = RunnerNameParts {
+            instance: "gha-1-2",
+            zone: "us-central1-a",
+            project: "my-project",
+            repository: "Owner/Repo",
+        };
+
+        assert_eq!(
+            resolve_runner_name("{instance}", &parts).as_deref(),
+            Ok("gha-1-2")
+        );
+        assert_eq!(
+            resolve_runner_name("{hostname}", &parts).as_deref(),
+            Ok("gha-1-2.us-central1-a.c.my-project.internal")
+        );
+        assert_eq!(
+            resolve_runner_name("{repo}_{instance}", &parts).as_deref(),
+            Ok("owner-repo_gha-1-2")
+        );
+
+        assert!(resolve_runner_name("{region}", &parts).is_err());
+        assert!(resolve_runner_name("{instance", &parts).is_err());
+        assert!(resolve_runner_name("runner {instance}", &parts).is_err());
+    }
+
+    /// Test label value derivation keeps underscores and replaces other characters
+    #[test]
+    fn test_repo_label_value_sanitization() {
+        assert_eq!(repo_label_value("Owner/My_Repo.Test"), "owner-my_repo-test");
+        assert_eq!(
+            repo_label_value(&format!("owner/{}", "a".repeat(100))).len(),
+            63
+        );
+    }
+
+    /// Test label validation against GCE's key, value and count limits
+    #[test]
+    fn test_validate_gce_labels() {
+        assert_eq!(
+            validate_gce_labels([
+                ("created-by", "spotted-arms"),
+                ("run_id", ""),
+                ("k8s", "v_1")
+            ]),
+            Ok(())
+        );
+
+        let long = "a".repeat(64);
+        let problems = validate_gce_labels([
+            ("Team", "infra"),
+            ("1st", "x"),
+            (long.as_str(), "ok"),
+            ("owner", "Jane Doe"),
+            ("repo", long.as_str()),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            problems,
+            vec![
+                "label key `Team` must start with a lowercase letter".to_string(),
+                "label key `Team` may only contain lowercase letters, digits, `_` and `-`"
+                    .to_string(),
+                "label key `1st` must start with a lowercase letter".to_string(),
+                format!("label key `{long}` is longer than 63 characters"),
+                "label `owner` value `Jane Doe` may only contain lowercase letters, digits, `_` and `-`"
+                    .to_string(),
+                format!("label `repo` value `{long}` is longer than 63 characters"),
+            ]
+        );
+
+        let keys = (0..65).map(|i| format!("k{i}")).collect::<Vec<_>>();
+        assert_eq!(
+            validate_gce_labels(keys.iter().map(|k| (k.as_str(), ""))),
+            Err(vec!["65 labels exceed GCE's limit of 64".to_string()])
+        );
+    }
+
+    /// Test network tag validation against GCE's naming rules
+    #[test]
+    fn test_validate_gce_tags() {
+        assert_eq!(
+            validate_gce_tags(["gha-owner-repo", "allow-ssh", "a"]),
+            Ok(())
+        );
+
+        let problems =
+            validate_gce_tags(["", "Web", "1st", "trailing-", &"a".repeat(64)]).unwrap_err();
+        assert_eq!(problems.len(), 5);
+        assert!(problems[1].starts_with("network tag `Web` must be"));
+    }
+
+    /// Test network tag derivation from repository names with uppercase and special characters
+    #[test]
+    fn test_repo_network_tag_sanitization() {
+        assert_eq!(
+            repo_network_tag("Owner-123/My_Repo.Test").as_deref(),
+            Some("gha-owner-123-my-repo-test")
+        );
+        assert_eq!(
+            repo_network_tag("ACME/--Weird@@Name--").as_deref(),
+            Some("gha-acme-weird-name")
+        );
+        assert_eq!(repo_network_tag("@@/__"), None);
+
+        let long = repo_network_tag(&format!("owner/{}", "a-".repeat(60))).unwrap();
+        assert!(long.len() <= 63);
+        assert!(!long.ends_with('-'));
+    }
+
     /// Test the string formatting logic directly with known values
     #[test]
     fn test_instance_name_format() {
@@ -143,6 +727,93 @@ mod tests {
         assert!(result.starts_with("gha-"));
     }
 
+    /// Jobs without their ids are refused a name rather than sharing `gha-0-0`
+    #[test]
+    fn test_instance_name_requires_ids() {
+        let payload = |job: Value| -> WorkflowJobWebhookEventPayload {
+            serde_json::from_value(serde_json::json!({"action": "queued", "workflow_job": job}))
+                .unwrap()
+        };
+
+        assert_eq!(
+            make_instance_name(&payload(serde_json::json!({"id": 42, "labels": []}))),
+            Err(NameError::MissingId("run_id"))
+        );
+        assert_eq!(
+            make_instance_name(&payload(
+                serde_json::json!({"id": "42", "run_id": 123, "labels": []})
+            )),
+            Err(NameError::MissingId("id"))
+        );
+    }
+
+    /// Names are RFC 1035 labels however they are truncated or filtered
+    #[test]
+    fn test_instance_name_is_rfc1035() {
+        // truncating at 63 characters lands right after a hyphen
+        let raw = format!("gha-{}-7", "1".repeat(58));
+        assert_eq!(raw.chars().nth(62), Some('-'));
+        let name = sanitize_instance_name(&raw);
+        assert_eq!(name, format!("gha-{}", "1".repeat(58)));
+
+        assert_eq!(sanitize_instance_name("gha--5-1-7"), "gha-5-1-7");
+        assert_eq!(sanitize_instance_name("gha-0-1--"), "gha-0-1");
+        assert_eq!(sanitize_instance_name("123-1-42"), "gha-123-1-42");
+        assert_eq!(sanitize_instance_name("--123-1-42"), "gha-123-1-42");
+
+        let long = sanitize_instance_name(&"9-".repeat(40));
+        assert!(long.len() <= 63);
+        assert!(long.starts_with("gha-9-"));
+        assert!(!long.ends_with('-'));
+    }
+
+    /// Reruns of a job get a different instance name than the attempt before
+    #[test]
+    fn test_instance_name_includes_run_attempt() {
+        let payload = |attempt: Option<i64>| -> WorkflowJobWebhookEventPayload {
+            let mut job = serde_json::json!({"id": 42, "run_id": 123, "labels": []});
+            if let Some(attempt) = attempt {
+                job["run_attempt"] = attempt.into();
+            }
+            serde_json::from_value(serde_json::json!({"action": "queued", "workflow_job": job}))
+                .unwrap()
+        };
+
+        assert_eq!(
+            make_instance_name(&payload(Some(1))).unwrap(),
+            "gha-123-1-42"
+        );
+        assert_eq!(
+            make_instance_name(&payload(Some(2))).unwrap(),
+            "gha-123-2-42"
+        );
+        assert_eq!(make_instance_name(&payload(None)).unwrap(), "gha-123-1-42");
+
+        let long = payload(Some(i64::MAX));
+        let name = make_instance_name(&long).unwrap();
+        assert!(name.len() <= 63);
+        assert!(name.starts_with("gha-123-"));
+    }
+
+    /// Names filtered down to almost nothing still come out valid and distinct
+    #[test]
+    fn test_instance_name_minimum_length() {
+        let name = sanitize_instance_name("!@#");
+        assert!(name.len() >= MIN_INSTANCE_NAME_LEN);
+        assert!(name.len() <= 63);
+        assert!(name.starts_with("gha-"));
+        assert!(
+            name.chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        );
+        assert_eq!(name, sanitize_instance_name("!@#"));
+        assert_ne!(name, sanitize_instance_name("$%^"));
+
+        assert!(sanitize_instance_name("7$").starts_with("gha-7-"));
+        assert!(sanitize_instance_name("ab").starts_with("ab-"));
+        assert_eq!(sanitize_instance_name("gha-1-2"), "gha-1-2");
+    }
+
     /// Test consistency of instance name generation for various inputs
     #[test]
     fn test_instance_name_generation_consistency() {
@@ -1,53 +1,444 @@
+use crate::clock::{Clock, SystemClock};
+use crate::config::{
+    CancelledBeforeStart, DEFAULT_DELIVERY_CACHE_SIZE, DEFAULT_DELIVERY_CACHE_TTL,
+    DEFAULT_MAX_SPAN_LABELS, LabelGroup,
+};
 use crate::instance::{create_instance, delete_instance};
-use crate::utils::make_instance_name;
+use crate::utils::{
+    batch_instance_names, machine_type_label, make_instance_name, resolve_template_name,
+};
+use axum::body::Bytes;
 use axum::extract::State;
 use axum::http::{HeaderMap, StatusCode};
-use axum::response::ErrorResponse;
+use axum::response::{ErrorResponse, IntoResponse, Json, Response};
 use axum_github_webhook_extract::GithubEvent;
-use octocrab::models::orgs::Organization;
 use octocrab::models::webhook_events::EventInstallation;
 use octocrab::models::webhook_events::payload::{
     WorkflowJobWebhookEventAction, WorkflowJobWebhookEventPayload,
 };
 use octocrab::models::{Author, Repository};
+use opentelemetry::trace::TraceContextExt;
 use serde::Deserialize;
-use std::collections::HashSet;
-use tracing::field;
-use tracing::{Instrument, Span, info, info_span, instrument};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{Instrument, Span, error, info, info_span, instrument, warn};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// How long GitHub is asked to wait before redelivering an event still being processed
+pub const IN_FLIGHT_DELIVERY_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+/// Recently handled `X-GitHub-Delivery` ids, so a redelivered event isn't processed twice.
+///
+/// Holds at most `capacity` ids, evicting the oldest first, and forgets each after `ttl`.
+/// Deliveries still being processed are tracked apart and only recorded once they succeed.
+#[derive(Debug)]
+pub struct SeenDeliveries {
+    capacity: usize,
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+    seen: Mutex<DeliveryLog>,
+}
+
+/// Delivery ids with when they were handled, the same in insertion order for eviction, and
+/// the ids being processed
+#[derive(Debug, Default)]
+struct DeliveryLog {
+    ids: HashMap<String, Instant>,
+    order: VecDeque<(String, Instant)>,
+    in_flight: HashSet<String>,
+}
+
+/// What [`SeenDeliveries::begin`] found for a delivery id
+#[derive(Debug)]
+pub enum DeliveryStatus<'a> {
+    /// Not seen before; process it and [`InFlightDelivery::complete`] it on success
+    New(InFlightDelivery<'a>),
+    /// Another request is processing the same delivery
+    InFlight,
+    /// Already handled within the TTL
+    Seen,
+}
 
-const REQUIRED_LABELS: &[&str] = &["linux", "self-hosted", "ARM64"];
+/// A delivery being processed; dropping it without completing, e.g. on failure or when the
+/// request is cancelled, lets a redelivery be processed
+#[derive(Debug)]
+pub struct InFlightDelivery<'a> {
+    deliveries: &'a SeenDeliveries,
+    id: String,
+}
 
-/// Checks if the job has the required labels for GCP ARM64 runners
-fn has_required_labels<'a>(labels: impl IntoIterator<Item = &'a String>) -> bool {
+impl InFlightDelivery<'_> {
+    /// Records the delivery as handled, so redeliveries are acknowledged without processing
+    pub fn complete(self) {
+        let now = self.deliveries.clock.now();
+        let mut log = self.deliveries.log();
+        log.ids.insert(self.id.clone(), now);
+        log.order.push_back((self.id.clone(), now));
+    }
+}
+
+impl Drop for InFlightDelivery<'_> {
+    fn drop(&mut self) {
+        self.deliveries.log().in_flight.remove(&self.id);
+    }
+}
+
+impl Default for SeenDeliveries {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_DELIVERY_CACHE_SIZE,
+            DEFAULT_DELIVERY_CACHE_TTL,
+            Arc::new(SystemClock),
+        )
+    }
+}
+
+impl SeenDeliveries {
+    pub fn new(capacity: usize, ttl: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            capacity,
+            ttl,
+            clock,
+            seen: Mutex::default(),
+        }
+    }
+
+    fn log(&self) -> std::sync::MutexGuard<'_, DeliveryLog> {
+        self.seen.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Starts processing the delivery unless it was handled within the TTL or is in flight
+    pub fn begin(&self, delivery: &str) -> DeliveryStatus<'_> {
+        let now = self.clock.now();
+        let mut log = self.log();
+        let DeliveryLog {
+            ids: seen,
+            order,
+            in_flight,
+        } = &mut *log;
+
+        // the queue is in insertion order, so expired and excess ids are at its front;
+        // entries left behind by a re-seen id no longer match the map
+        while let Some((id, at)) = order.front() {
+            if now.duration_since(*at) < self.ttl && seen.len() < self.capacity {
+                break;
+            }
+            if seen.get(id) == Some(at) {
+                seen.remove(id);
+            }
+            order.pop_front();
+        }
+
+        if seen
+            .get(delivery)
+            .is_some_and(|at| now.duration_since(*at) < self.ttl)
+        {
+            return DeliveryStatus::Seen;
+        }
+        if !in_flight.insert(delivery.to_string()) {
+            return DeliveryStatus::InFlight;
+        }
+        DeliveryStatus::New(InFlightDelivery {
+            deliveries: self,
+            id: delivery.to_string(),
+        })
+    }
+}
+
+/// Checks if the job has every label of any configured label group or, without groups, every
+/// label the fleet requires
+fn has_required_labels<'a>(
+    required: &[String],
+    groups: &[LabelGroup],
+    labels: impl IntoIterator<Item = &'a String>,
+) -> bool {
     let labels = labels
         .into_iter()
         .map(String::as_ref)
         .collect::<HashSet<&str>>();
+    let has_all = |required: &[String]| {
+        required
+            .iter()
+            .all(|required| labels.contains(required.as_str()))
+    };
 
-    REQUIRED_LABELS
-        .iter()
-        .all(|&required| labels.contains(required))
+    if groups.is_empty() {
+        has_all(required)
+    } else {
+        groups.iter().any(|group| has_all(&group.labels))
+    }
+}
+
+/// Whether a completed job was cancelled before any runner was assigned to it
+fn cancelled_before_start(workflow_job: &Value) -> bool {
+    workflow_job.get("conclusion").and_then(Value::as_str) == Some("cancelled")
+        && workflow_job
+            .get("runner_id")
+            .and_then(Value::as_u64)
+            .unwrap_or_default()
+            == 0
+        && workflow_job
+            .get("runner_name")
+            .and_then(Value::as_str)
+            .is_none_or(str::is_empty)
+}
+
+/// Renders the job's labels for its span, sorted and capped at `max` with a `+N more` suffix
+/// so pathological payloads don't bloat traces
+fn span_labels(labels: &HashSet<String>, max: usize) -> String {
+    let mut sorted = labels.iter().map(String::as_str).collect::<Vec<_>>();
+    sorted.sort_unstable();
+    let shown = &sorted[..max.min(sorted.len())];
+    match sorted.len() - shown.len() {
+        0 => format!("{shown:?}"),
+        more => format!("{shown:?} +{more} more"),
+    }
+}
+
+/// Collects the job's string labels.
+///
+/// Non-string entries in a malformed payload are skipped with a warning rather than
+/// coerced to empty strings, which could otherwise satisfy a label check by accident.
+fn job_labels(workflow_job: &Value) -> HashSet<String> {
+    let entries = workflow_job
+        .get("labels")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    let mut labels = HashSet::with_capacity(entries.len());
+    for entry in entries {
+        match entry.as_str() {
+            Some(label) => {
+                labels.insert(label.to_string());
+            }
+            None => {
+                warn!(label = %entry, "Skipping non-string job label");
+                crate::metrics::registry().increment("spotted_arms_invalid_labels_total", &[]);
+            }
+        }
+    }
+    labels
+}
+
+/// Counts an event skipped without provisioning, by reason
+fn ignored(reason: &'static str) -> Decision {
+    crate::metrics::registry()
+        .increment("spotted_arms_ignored_events_total", &[("reason", reason)]);
+    Decision::Ignored(reason)
+}
+
+/// Organization a webhook was delivered for, present on org-scoped deliveries.
+///
+/// Only the login is read so that trimmed org objects still deserialize.
+#[derive(Clone, Debug, Deserialize)]
+pub struct WebhookOrganization {
+    pub login: String,
 }
 
+/// A `workflow_job` delivery from either a repository or an organization webhook
 #[derive(Deserialize)]
 pub struct WorkflowJobWebhook {
     pub _sender: Option<Author>,
     pub repository: Repository,
-    pub _organization: Option<Organization>,
+    #[serde(default)]
+    pub organization: Option<WebhookOrganization>,
     pub _installation: Option<EventInstallation>,
     #[serde(flatten)]
     pub payload: WorkflowJobWebhookEventPayload,
 }
 
+impl WorkflowJobWebhook {
+    /// Fills in the repository's `full_name` when the delivery omits it, from the
+    /// repository owner or, for org-scoped deliveries, the organization
+    fn normalize_repository(&mut self) {
+        if self.repository.full_name.is_some() {
+            return;
+        }
+
+        let owner = self
+            .repository
+            .owner
+            .as_ref()
+            .map(|owner| owner.login.as_str())
+            .or(self.organization.as_ref().map(|org| org.login.as_str()));
+        self.repository.full_name = owner.map(|owner| format!("{owner}/{}", self.repository.name));
+    }
+}
+
+/// The parsed fields of a webhook that drive provisioning, returned in echo mode
+fn echo_fields(
+    body: &WorkflowJobWebhook,
+    event_type: &str,
+    delivery: Option<&str>,
+    labels: &HashSet<String>,
+    required_labels: &[String],
+    label_groups: &[LabelGroup],
+) -> Value {
+    let workflow_job = &body.payload.workflow_job;
+    let mut labels = labels.iter().collect::<Vec<_>>();
+    labels.sort();
+
+    json!({
+        "event": event_type,
+        "delivery": delivery,
+        "action": body.payload.action,
+        "job_id": workflow_job.get("id"),
+        "run_id": workflow_job.get("run_id"),
+        "run_attempt": workflow_job.get("run_attempt"),
+        "labels": labels,
+        "has_required_labels": has_required_labels(required_labels, label_groups, labels.iter().copied()),
+        "repository": body.repository.full_name,
+        "repository_url": body.repository.url,
+        "organization": body.organization.as_ref().map(|org| &org.login),
+        "instance_name": make_instance_name(&body.payload).ok(),
+    })
+}
+
+/// Terminal outcome of a webhook that was handled without error
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Decision {
+    Created,
+    Deleted,
+    /// Left running for `--linger-on-failure`, with its delete scheduled
+    DeleteScheduled,
+    /// Dropped without touching GCE, for the given reason
+    Ignored(&'static str),
+    Echoed,
+}
+
+impl Decision {
+    fn as_str(self) -> &'static str {
+        match self {
+            Decision::Created => "created",
+            Decision::Deleted => "deleted",
+            Decision::DeleteScheduled => "delete_scheduled",
+            Decision::Ignored(_) => "ignored",
+            Decision::Echoed => "echoed",
+        }
+    }
+}
+
+/// The job a webhook is about, as written to the decision log
+fn decision_subject(body: &WorkflowJobWebhook) -> Value {
+    let workflow_job = &body.payload.workflow_job;
+    json!({
+        "action": body.payload.action,
+        "repository": body.repository.full_name,
+        "run_id": workflow_job.get("run_id"),
+        "run_attempt": workflow_job.get("run_attempt"),
+        "job_id": workflow_job.get("id"),
+        "labels": workflow_job.get("labels"),
+        "instance_name": make_instance_name(&body.payload).ok(),
+    })
+}
+
 /// Handles incoming GitHub workflow job webhook events
-#[instrument(skip_all, fields(body, event, delivery, labels), err(Debug))]
+#[instrument(
+    skip_all,
+    fields(body, event, delivery, labels, organization, decision, otel.status_code),
+    err(Debug)
+)]
 pub async fn handle_workflow_job_event(
     headers: HeaderMap,
     State(state): State<crate::server::AppState>,
-    GithubEvent(body): GithubEvent<WorkflowJobWebhook>,
-) -> Result<(), ErrorResponse> {
+    GithubEvent(mut body): GithubEvent<WorkflowJobWebhook>,
+) -> Result<Response, ErrorResponse> {
+    let span = Span::current();
+    let deliveries = state.deliveries.clone();
+    let decision_log = state.decision_log.clone();
+    let clock = state.clock.clone();
+    let delivery = headers
+        .get("X-GitHub-Delivery")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+
+    body.normalize_repository();
+    let subject = decision_log.is_enabled().then(|| decision_subject(&body));
+
+    let status = delivery.as_deref().map(|id| (id, deliveries.begin(id)));
+    let outcome = match status {
+        Some((id, DeliveryStatus::Seen)) => {
+            span.record("delivery", id);
+            info!(delivery = id, "Ignoring already seen delivery");
+            Ok((().into_response(), ignored("duplicate_delivery")))
+        }
+        // acknowledging it would lose the job should the first attempt fail
+        Some((id, DeliveryStatus::InFlight)) => {
+            span.record("delivery", id);
+            info!(
+                delivery = id,
+                "Delivery still being processed; asking for a retry"
+            );
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(
+                    axum::http::header::RETRY_AFTER,
+                    IN_FLIGHT_DELIVERY_RETRY_AFTER.as_secs().to_string(),
+                )],
+                "delivery already in progress",
+            )
+                .into())
+        }
+        Some((_, DeliveryStatus::New(in_flight))) => {
+            let outcome = process_workflow_job_event(headers, state, body).await;
+            // a failed delivery isn't recorded, so GitHub's redelivery is processed
+            if outcome.is_ok() {
+                in_flight.complete();
+            }
+            outcome
+        }
+        None => process_workflow_job_event(headers, state, body).await,
+    };
+
+    // every terminal path is labelled so trace-based SLOs can tell outcomes apart
+    match &outcome {
+        Ok((_, decision)) => {
+            span.record("decision", decision.as_str());
+            span.record("otel.status_code", "OK");
+        }
+        Err(_) => {
+            span.record("decision", "failed");
+            span.record("otel.status_code", "ERROR");
+        }
+    }
+
+    if let Some(mut entry) = subject {
+        entry["timestamp"] = humantime::format_rfc3339_millis(clock.system_now())
+            .to_string()
+            .into();
+        entry["delivery"] = delivery.into();
+        match &outcome {
+            Ok((_, decision)) => {
+                entry["decision"] = decision.as_str().into();
+                if let Decision::Ignored(reason) = decision {
+                    entry["reason"] = (*reason).into();
+                }
+            }
+            Err(_) => entry["decision"] = "failed".into(),
+        }
+        decision_log.record(&entry);
+    }
+
+    outcome.map(|(response, _)| response)
+}
+
+async fn process_workflow_job_event(
+    headers: HeaderMap,
+    state: crate::server::AppState,
+    body: WorkflowJobWebhook,
+) -> Result<(Response, Decision), ErrorResponse> {
+    let received_at = state.clock.system_now();
     let span = Span::current();
 
+    span.record(
+        "organization",
+        body.organization.as_ref().map(|org| org.login.as_str()),
+    );
+
     let event_type = headers
         .get("X-GitHub-Event")
         .ok_or((StatusCode::BAD_REQUEST, "missing X-GitHub-Event header"))
@@ -66,81 +457,395 @@ pub async fn handle_workflow_job_event(
 
     if event_type != "workflow_job" {
         info!(event_type, "Ignoring non-workflow_job event");
-        return Ok(());
+        return Ok((().into_response(), ignored("event_type")));
     }
 
-    let workflow_job = &body.payload.workflow_job;
-    let labels = &workflow_job
-        .get("labels")
-        .unwrap_or_default()
-        .as_array()
-        .unwrap_or(&vec![])
-        .iter()
-        .map(|v| v.as_str().unwrap_or_default().to_string())
-        .collect::<HashSet<_>>();
+    let labels = &job_labels(&body.payload.workflow_job);
+
+    span.record(
+        "labels",
+        span_labels(
+            labels,
+            state
+                .config
+                .max_span_labels
+                .unwrap_or(DEFAULT_MAX_SPAN_LABELS),
+        ),
+    );
 
-    span.record("labels", field::debug(labels));
+    // the signature is already verified; report what was parsed rather than provisioning
+    if state.config.echo_mode {
+        info!("Echoing parsed webhook instead of provisioning");
+        return Ok((
+            Json(echo_fields(
+                &body,
+                event_type,
+                delivery,
+                labels,
+                &state.required_labels,
+                &state.config.label_groups,
+            ))
+            .into_response(),
+            Decision::Echoed,
+        ));
+    }
 
-    // Check if the job has required labels before creating instance
-    if !has_required_labels(labels) {
+    // Jobs without the required labels never get an instance, so neither their queued nor
+    // their completed events touch GCE
+    if !has_required_labels(&state.required_labels, &state.config.label_groups, labels) {
         info!(
             job.labels = ?labels,
-            required.labels = ?REQUIRED_LABELS,
+            required.labels = ?state.required_labels,
+            label_groups = ?state.config.label_groups.iter().map(|g| &g.name).collect::<Vec<_>>(),
             "Ignoring job without required labels",
         );
-        return Ok(());
+        return Ok((().into_response(), ignored("missing_labels")));
+    }
+
+    // like missing labels, a disallowed repository never gets an instance to delete
+    if !state.config.repo_visibility.allows(body.repository.private) {
+        info!(
+            private = body.repository.private,
+            policy = ?state.config.repo_visibility,
+            "Ignoring job from a repository whose visibility isn't allowed",
+        );
+        return Ok((().into_response(), ignored("repo_visibility")));
     }
 
-    let instance_name = make_instance_name(&body.payload);
+    // Both create and delete must target the same project
+    let project_id = state
+        .config
+        .select_project(labels, &state.project_id)
+        .map(str::to_string)
+        .map_err(|e| {
+            tracing::error!(job.labels = ?labels, error = e, "Rejecting job with invalid project label");
+            (StatusCode::BAD_REQUEST, e)
+        })?;
+
+    let instance_name = make_instance_name(&body.payload).map_err(|e| {
+        tracing::error!(error = %e, "Rejecting job without its ids");
+        (StatusCode::BAD_REQUEST, e.to_string())
+    })?;
 
     let span = info_span!("workflow_job_event",
         action = ?body.payload.action,
         instance_name = %instance_name
     );
 
+    // link a job's completion back to the trace that created its runner
+    let workflow_job = &body.payload.workflow_job;
+    if let (Some(run_id), Some(job_id)) = (
+        workflow_job.get("run_id").and_then(Value::as_u64),
+        workflow_job.get("id").and_then(Value::as_u64),
+    ) {
+        match body.payload.action {
+            WorkflowJobWebhookEventAction::Queued => {
+                let created = span.context().span().span_context().clone();
+                state.job_traces.record((run_id, job_id), created);
+            }
+            WorkflowJobWebhookEventAction::Completed => {
+                if let Some(created) = state.job_traces.take((run_id, job_id)) {
+                    span.add_link(created);
+                }
+            }
+            _ => {}
+        }
+    }
+
     async move {
         match body.payload.action {
             WorkflowJobWebhookEventAction::Queued => {
-                info!("Processing queued workflow job");
-                create_instance(
-                    state.compute_client.as_ref(),
-                    state.github_client.as_ref(),
-                    &state.project_id,
-                    &state.region,
-                    &state.token,
-                    &state.instance_template,
-                    instance_name.as_str(),
-                    &body,
-                )
-                .await
+                // held until the runner is created, so its completion can't delete mid-insert
+                let _lock = state.instance_locks.lock(&instance_name).await;
+                if !state.config.provisioning_open(received_at) {
+                    info!("Ignoring job queued outside the provisioning schedule");
+                    return Ok(ignored("outside_schedule"));
+                }
+                let Some((instance_template, count)) =
+                    state.config.select_route(labels, &state.instance_template)
+                else {
+                    info!(job.labels = ?labels, "Ignoring job without a matching route");
+                    return Ok(ignored("no_route"));
+                };
+                let instance_template =
+                    resolve_template_name(instance_template, &state.region, labels).map_err(
+                        |e| {
+                            tracing::error!(error = e, "Rejecting job with unresolvable template");
+                            Box::new(ErrorResponse::from((StatusCode::BAD_REQUEST, e)))
+                        },
+                    )?;
+                let machine_type = machine_type_label(labels).map_err(|e| {
+                    tracing::error!(error = e, "Rejecting job with invalid machine type label");
+                    Box::new(ErrorResponse::from((StatusCode::BAD_REQUEST, e)))
+                })?;
+                let instance_names = batch_instance_names(&instance_name, count);
+
+                let repository = body.repository.full_name.as_deref().unwrap_or_default();
+                for (reserved, name) in instance_names.iter().enumerate() {
+                    if !state.repo_quotas.try_reserve(repository, name) {
+                        instance_names[..reserved]
+                            .iter()
+                            .for_each(|name| state.repo_quotas.release(name));
+                        info!(
+                            repository,
+                            "Rejecting job over the repository instance quota"
+                        );
+                        return Err(Box::new(
+                            (
+                                StatusCode::TOO_MANY_REQUESTS,
+                                "repository instance quota exceeded",
+                            )
+                                .into(),
+                        ));
+                    }
+                }
+
+                info!(instance_template, count, "Processing queued workflow job");
+                let github_token = match state.token.token().await {
+                    Ok(token) => token,
+                    Err(e) => {
+                        error!(?e, "Failed to get a GitHub token");
+                        instance_names
+                            .iter()
+                            .for_each(|name| state.repo_quotas.release(name));
+                        return Err(Box::new(
+                            (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                "github token unavailable",
+                            )
+                                .into(),
+                        ));
+                    }
+                };
+                for (created, name) in instance_names.iter().enumerate() {
+                    let Err(e) = create_instance(
+                        state.compute_client.as_ref(),
+                        state.github_client.as_ref(),
+                        &project_id,
+                        &state.region,
+                        &state.zones,
+                        &github_token,
+                        &instance_template,
+                        machine_type,
+                        &state.config,
+                        name,
+                        &body,
+                        received_at,
+                        state.metadata_transform.as_ref(),
+                        &state.instance_limit,
+                    )
+                    .await
+                    else {
+                        continue;
+                    };
+
+                    // GitHub redelivers the failed job, which creates the whole batch again,
+                    // so nothing this attempt created is left running
+                    instance_names[created..]
+                        .iter()
+                        .for_each(|name| state.repo_quotas.release(name));
+                    for name in &instance_names[..created] {
+                        warn!(instance_name = name, "Rolling back batch instance");
+                        // a failed rollback is logged by delete_instance and left to the
+                        // reaper or quota reconciliation
+                        if delete_instance(
+                            state.compute_client.as_ref(),
+                            &project_id,
+                            &state.region,
+                            &state.zones,
+                            name,
+                            &body,
+                            &state.config,
+                            &state.instance_limit,
+                        )
+                        .await
+                        .is_ok()
+                        {
+                            state.repo_quotas.release(name);
+                        }
+                    }
+                    return Err(e);
+                }
+                Ok(Decision::Created)
             }
             WorkflowJobWebhookEventAction::Completed => {
-                info!("Processing completed workflow job");
-                delete_instance(
-                    state.compute_client.as_ref(),
-                    &state.project_id,
-                    &state.region,
-                    instance_name.as_str(),
-                    &body,
-                )
-                .await
+                if state.config.on_cancelled_before_start == CancelledBeforeStart::Skip
+                    && cancelled_before_start(&body.payload.workflow_job)
+                {
+                    info!("Skipping deletion for a job cancelled before it started");
+                    return Ok(ignored("cancelled_before_start"));
+                }
+                let lock = state.instance_locks.lock(&instance_name).await;
+                // the same route resolves on completion, so the whole batch is deleted
+                let count = state
+                    .config
+                    .select_route(labels, &state.instance_template)
+                    .map_or(1, |(_, count)| count);
+
+                let instance_names = batch_instance_names(&instance_name, count);
+
+                let failed = body
+                    .payload
+                    .workflow_job
+                    .get("conclusion")
+                    .and_then(Value::as_str)
+                    == Some("failure");
+                if let Some(delay) = state.config.linger_on_failure.filter(|_| failed) {
+                    info!(
+                        count,
+                        ?delay,
+                        "Lingering before deleting failed workflow job"
+                    );
+                    let lingering = info_span!("lingering_delete", ?delay);
+                    let state = state.clone();
+                    drop(lock);
+                    state.lingering_deletes.clone().schedule(
+                        delay,
+                        async move {
+                            let _lock = state.instance_locks.lock(&instance_name).await;
+                            for name in instance_names {
+                                // failures are logged by delete_instance and the reaper or
+                                // quota reconciliation catches up with what is left
+                                if delete_instance(
+                                    state.compute_client.as_ref(),
+                                    &project_id,
+                                    &state.region,
+                                    &state.zones,
+                                    &name,
+                                    &body,
+                                    &state.config,
+                                    &state.instance_limit,
+                                )
+                                .await
+                                .is_ok()
+                                {
+                                    state.repo_quotas.release(&name);
+                                }
+                            }
+                        }
+                        .instrument(lingering),
+                    );
+                    return Ok(Decision::DeleteScheduled);
+                }
+
+                info!(count, "Processing completed workflow job");
+                // one failed delete doesn't keep the rest of the batch running; the first
+                // failure is returned so GitHub redelivers
+                let mut failure = None;
+                for name in instance_names {
+                    match delete_instance(
+                        state.compute_client.as_ref(),
+                        &project_id,
+                        &state.region,
+                        &state.zones,
+                        &name,
+                        &body,
+                        &state.config,
+                        &state.instance_limit,
+                    )
+                    .await
+                    {
+                        Ok(()) => state.repo_quotas.release(&name),
+                        Err(e) => {
+                            failure.get_or_insert(e);
+                        }
+                    }
+                }
+                match failure {
+                    Some(e) => Err(e),
+                    None => Ok(Decision::Deleted),
+                }
             }
             _ => {
                 info!(?body.payload.action, "Ignoring workflow job event");
-                Ok(())
+                Ok(ignored("action"))
             }
         }
     }
     .instrument(span)
     .await
+    .map(|decision| {
+        // 202 tells whoever reads the delivery that the runner is still up
+        let status = match decision {
+            Decision::DeleteScheduled => StatusCode::ACCEPTED,
+            _ => StatusCode::OK,
+        };
+        (status.into_response(), decision)
+    })
     .map_err(|e| *e)
 }
 
+/// Handles workflow job webhooks without verifying their signature.
+///
+/// Only mounted when signature verification is explicitly disabled for trusted networks.
+pub async fn handle_unverified_workflow_job_event(
+    headers: HeaderMap,
+    state: State<crate::server::AppState>,
+    body: Bytes,
+) -> Result<Response, ErrorResponse> {
+    let body = serde_json::from_slice(&body).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("invalid webhook payload: {e}"),
+        )
+    })?;
+
+    handle_workflow_job_event(headers, state, GithubEvent(body)).await
+}
+
 #[cfg(test)]
 mod tests {
+    use super::{DeliveryStatus, SeenDeliveries};
+    use crate::clock::FakeClock;
     use octocrab::models::webhook_events::payload::{
         WorkflowJobWebhookEventAction, WorkflowJobWebhookEventPayload,
     };
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn seen_deliveries_expire_and_evict_the_oldest() {
+        let clock = Arc::new(FakeClock::new(SystemTime::now()));
+        let seen = SeenDeliveries::new(2, Duration::from_secs(60), clock.clone());
+        let handle = |id| match seen.begin(id) {
+            DeliveryStatus::New(in_flight) => {
+                in_flight.complete();
+                true
+            }
+            DeliveryStatus::InFlight | DeliveryStatus::Seen => false,
+        };
+
+        assert!(handle("a"));
+        assert!(!handle("a"));
+
+        // past capacity, the oldest id is forgotten first
+        assert!(handle("b"));
+        assert!(handle("c"));
+        assert!(!handle("c"));
+        assert!(handle("a"));
+
+        clock.advance(Duration::from_secs(61));
+        assert!(handle("c"));
+    }
+
+    #[test]
+    fn in_flight_deliveries_are_only_seen_once_completed() {
+        let clock = Arc::new(FakeClock::new(SystemTime::now()));
+        let seen = SeenDeliveries::new(2, Duration::from_secs(60), clock);
+
+        let DeliveryStatus::New(first) = seen.begin("a") else {
+            panic!("new delivery");
+        };
+        assert!(matches!(seen.begin("a"), DeliveryStatus::InFlight));
+
+        // a failed attempt is dropped without completing, so a redelivery is processed
+        drop(first);
+        let DeliveryStatus::New(retry) = seen.begin("a") else {
+            panic!("failed delivery is forgotten");
+        };
+        retry.complete();
+        assert!(matches!(seen.begin("a"), DeliveryStatus::Seen));
+    }
 
     #[test]
     fn parse_log_payload_as_workflow_job_event() {
@@ -0,0 +1,595 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use common::{MockCompute, delivery_id, queued_payload, signed_webhook_request, test_state};
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn webhook_sheds_requests_past_concurrency_limit() {
+    let compute = Arc::new(MockCompute {
+        insert_delay: Duration::from_millis(500),
+        ..Default::default()
+    });
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            max_concurrent_requests: Some(1),
+            ..Default::default()
+        },
+    );
+    let app = spotted_arms::server::create_app(state);
+
+    let body = serde_json::to_vec(&queued_payload(&["self-hosted", "linux", "ARM64"])).unwrap();
+
+    let first = tokio::spawn(app.clone().oneshot(signed_webhook_request(&body)));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let second = app.oneshot(signed_webhook_request(&body)).await.unwrap();
+
+    assert_eq!(second.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+    assert_eq!(compute.inserts.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn serves_on_ipv4_loopback() {
+    let listener = spotted_arms::server::bind_listener("127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = spotted_arms::server::create_app(test_state(
+        Arc::default(),
+        Arc::default(),
+        Default::default(),
+    ));
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let body = reqwest::get(format!("http://{addr}/ping"))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(body, "pong");
+}
+
+fn unsigned_webhook_request(body: &[u8]) -> axum::http::Request<axum::body::Body> {
+    axum::http::Request::post("/webhook")
+        .header("X-GitHub-Event", "workflow_job")
+        .header("X-GitHub-Delivery", delivery_id())
+        .header("Content-Type", "application/json")
+        .body(axum::body::Body::from(body.to_vec()))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn unsigned_webhook_accepted_only_without_signature_verification() {
+    let body = serde_json::to_vec(&queued_payload(&["self-hosted", "linux", "ARM64"])).unwrap();
+
+    for (no_signature_verification, expected) in
+        [(true, StatusCode::OK), (false, StatusCode::BAD_REQUEST)]
+    {
+        let compute = Arc::new(MockCompute::default());
+        let app = spotted_arms::server::create_app(test_state(
+            compute.clone(),
+            Arc::default(),
+            spotted_arms::config::Config {
+                no_signature_verification,
+                ..Default::default()
+            },
+        ));
+
+        let res = app.oneshot(unsigned_webhook_request(&body)).await.unwrap();
+        assert_eq!(res.status(), expected);
+        assert_eq!(
+            compute.inserts.lock().unwrap().len(),
+            usize::from(no_signature_verification)
+        );
+    }
+}
+
+struct FailingGather;
+
+impl spotted_arms::metrics::Gather for FailingGather {
+    fn gather(&self) -> Result<String, spotted_arms::metrics::MetricsError> {
+        Err(spotted_arms::metrics::MetricsError::Poisoned)
+    }
+}
+
+#[tokio::test]
+async fn metrics_gather_error_returns_500_without_affecting_ping() {
+    let mut state = test_state(Arc::default(), Arc::default(), Default::default());
+    state.metrics = &FailingGather;
+    let app = spotted_arms::server::create_app(state);
+
+    let get = |uri| {
+        axum::http::Request::get(uri)
+            .body(axum::body::Body::empty())
+            .unwrap()
+    };
+
+    let res = app.clone().oneshot(get("/metrics")).await.unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert!(String::from_utf8_lossy(&body).contains("failed to gather metrics"));
+
+    let res = app.oneshot(get("/ping")).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn malformed_traceparent_is_counted() {
+    let registry = spotted_arms::metrics::registry();
+    let malformed = || registry.counter("spotted_arms_malformed_traceparent_total", &[]);
+    let app = spotted_arms::server::create_app(test_state(
+        Arc::default(),
+        Arc::default(),
+        Default::default(),
+    ));
+    let ping = |traceparent| {
+        axum::http::Request::get("/ping")
+            .header("traceparent", traceparent)
+            .body(axum::body::Body::empty())
+            .unwrap()
+    };
+
+    let before = malformed();
+    let res = app
+        .clone()
+        .oneshot(ping(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(malformed(), before);
+
+    let res = app.oneshot(ping("00-not-a-traceparent-01")).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(malformed(), before + 1);
+}
+
+#[tokio::test]
+async fn config_validate_reports_without_applying() {
+    let validate = |contents: &str, name: &str| {
+        let path = std::env::temp_dir().join(format!(
+            "spotted-arms-validate-{name}-{}.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, contents).unwrap();
+
+        let mut state = test_state(Arc::default(), Arc::default(), Default::default());
+        state.config_file = Some(Arc::new(path));
+        let app = spotted_arms::server::create_app(state);
+        async move {
+            let res = app
+                .oneshot(
+                    axum::http::Request::post("/config/validate")
+                        .body(axum::body::Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let status = res.status();
+            let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            )
+        }
+    };
+
+    let (status, report) = validate(r#"{"max_concurrent_requests": 4}"#, "valid").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["valid"], true);
+
+    let (status, report) = validate(
+        r#"{"max_concurrent_requests": 0, "jit_rate_limit": 2.0}"#,
+        "invalid",
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(report["valid"], false);
+    assert!(
+        report["error"]
+            .as_str()
+            .unwrap()
+            .contains("max_concurrent_requests must be at least 1")
+    );
+
+    let (status, report) = validate("{not json", "unparsable").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(report["valid"], false);
+
+    // a typo'd key would otherwise be silently ignored
+    let (status, report) = validate(r#"{"max_concurent_requests": 4}"#, "unknown-key").await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(
+        report["error"]
+            .as_str()
+            .unwrap()
+            .contains("unknown field `max_concurent_requests`"),
+        "{report}"
+    );
+}
+
+#[tokio::test]
+async fn admin_routes_require_the_admin_token_when_set() {
+    let mut state = test_state(Arc::default(), Arc::default(), Default::default());
+    state.admin_token = Some(Arc::new("admin-secret".to_string()));
+    let app = spotted_arms::server::create_app(state);
+
+    let request = |method: &str, uri: &str, authorization: Option<&str>| {
+        let mut request = axum::http::Request::builder().method(method).uri(uri);
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        request.body(axum::body::Body::empty()).unwrap()
+    };
+
+    for (method, uri) in [
+        ("POST", "/config/validate"),
+        ("GET", "/reaper/preview"),
+        ("GET", "/runners/dead-letters"),
+    ] {
+        for authorization in [None, Some("Bearer wrong"), Some("admin-secret")] {
+            let res = app
+                .clone()
+                .oneshot(request(method, uri, authorization))
+                .await
+                .unwrap();
+            assert_eq!(
+                res.status(),
+                StatusCode::UNAUTHORIZED,
+                "{uri} {authorization:?}"
+            );
+        }
+    }
+
+    // with the token the handlers run: no config file, and no reaper TTL
+    let res = app
+        .clone()
+        .oneshot(request(
+            "POST",
+            "/config/validate",
+            Some("Bearer admin-secret"),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = app
+        .clone()
+        .oneshot(request(
+            "GET",
+            "/reaper/preview",
+            Some("Bearer admin-secret"),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    // the webhook and probes stay unauthenticated
+    let res = app.oneshot(request("GET", "/ping", None)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn dead_lettered_runners_are_listed() {
+    let state = test_state(Arc::default(), Arc::default(), Default::default());
+    let repo_url: reqwest::Url = "https://api.github.com/repos/owner/repo".parse().unwrap();
+    state.dead_letters.record(&repo_url, 7);
+    let app = spotted_arms::server::create_app(state);
+
+    let res = app
+        .oneshot(
+            axum::http::Request::get("/runners/dead-letters")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+        serde_json::json!({"runners": [{"repository": repo_url, "runner_id": 7}]})
+    );
+}
+
+#[tokio::test]
+async fn oversized_webhook_body_rejected_before_signature_check() {
+    let github = Arc::new(common::MockGithub::default());
+    let state = test_state(
+        Arc::default(),
+        github.clone(),
+        spotted_arms::config::Config {
+            max_webhook_body_bytes: Some(1024),
+            ..Default::default()
+        },
+    );
+    let app = spotted_arms::server::create_app(state);
+
+    // a bad signature would be a 400 had the body been hashed
+    let body = vec![b' '; 4096];
+    let oversized = axum::http::Request::post("/webhook")
+        .header("X-GitHub-Event", "workflow_job")
+        .header("X-Hub-Signature-256", format!("sha256={}", "00".repeat(32)))
+        .header("Content-Length", body.len())
+        .body(axum::body::Body::from(body))
+        .unwrap();
+    let res = app.clone().oneshot(oversized).await.unwrap();
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let body = serde_json::to_vec(&queued_payload(&["self-hosted", "linux", "ARM64"])).unwrap();
+    assert!(body.len() <= 1024);
+    let res = app.oneshot(signed_webhook_request(&body)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(github.labels.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn echo_mode_returns_parsed_fields_without_provisioning() {
+    let compute = Arc::new(MockCompute::default());
+    let github = Arc::new(common::MockGithub::default());
+    let state = test_state(
+        compute.clone(),
+        github.clone(),
+        spotted_arms::config::Config {
+            echo_mode: true,
+            ..Default::default()
+        },
+    );
+    let app = spotted_arms::server::create_app(state);
+
+    let body = serde_json::to_vec(&queued_payload(&["self-hosted", "linux", "ARM64"])).unwrap();
+    let res = app
+        .clone()
+        .oneshot(signed_webhook_request(&body))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let echoed: serde_json::Value = serde_json::from_slice(
+        &axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(echoed["event"], "workflow_job");
+    assert_eq!(echoed["action"], "queued");
+    assert_eq!(echoed["job_id"], 7);
+    assert_eq!(
+        echoed["labels"],
+        serde_json::json!(["ARM64", "linux", "self-hosted"])
+    );
+    assert_eq!(echoed["has_required_labels"], true);
+    assert_eq!(echoed["repository"], "owner/repo");
+    assert!(compute.inserts.lock().unwrap().is_empty());
+    assert!(github.labels.lock().unwrap().is_empty());
+
+    // the signature is still verified first
+    let mut forged = signed_webhook_request(&body);
+    forged.headers_mut().insert(
+        "X-Hub-Signature-256",
+        format!("sha256={}", "00".repeat(32)).parse().unwrap(),
+    );
+    let res = app.oneshot(forged).await.unwrap();
+    assert_ne!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test(start_paused = true)]
+async fn request_and_operation_timeouts_are_distinct() {
+    use gcloud_sdk::google_rest_apis::compute_v1::operation::Status;
+    use spotted_arms::config::{Config, ExistingInstance};
+
+    // the existing instance's deletion never finishes, so replacing it waits out a timeout
+    let app = |request_timeout, operation_timeout| {
+        let compute = Arc::new(MockCompute {
+            delete_operation: common::operation(Status::Running),
+            ..Default::default()
+        });
+        compute.instances.lock().unwrap().push(
+            gcloud_sdk::google_rest_apis::compute_v1::Instance {
+                name: Some("gha-11-1-7".to_string()),
+                ..Default::default()
+            },
+        );
+        compute
+            .operations
+            .lock()
+            .unwrap()
+            .extend((0..100).map(|_| Ok(common::operation(Status::Running))));
+        spotted_arms::server::create_app(test_state(
+            compute,
+            Arc::default(),
+            Config {
+                on_existing_instance: Some(ExistingInstance::Replace),
+                request_timeout: Some(request_timeout),
+                operation_timeout: Some(operation_timeout),
+                ..Default::default()
+            },
+        ))
+    };
+    let body = serde_json::to_vec(&queued_payload(&["self-hosted", "linux", "ARM64"])).unwrap();
+
+    // the operation gives up first and the handler reports it
+    let started = tokio::time::Instant::now();
+    let res = app(Duration::from_secs(60), Duration::from_secs(5))
+        .oneshot(signed_webhook_request(&body))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(started.elapsed() < Duration::from_secs(60));
+
+    // the request gives up first while the operation is still being waited on
+    let started = tokio::time::Instant::now();
+    let res = app(Duration::from_secs(3), Duration::from_secs(120))
+        .oneshot(signed_webhook_request(&body))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert_eq!(started.elapsed(), Duration::from_secs(3));
+}
+
+#[tokio::test(start_paused = true)]
+async fn ready_only_after_warmup() {
+    let compute = Arc::new(MockCompute {
+        template_delay: Duration::from_secs(2),
+        ..Default::default()
+    });
+    let mut state = test_state(
+        compute,
+        Arc::default(),
+        spotted_arms::config::Config {
+            warmup_template: true,
+            ..Default::default()
+        },
+    );
+    state.readiness = Arc::new(spotted_arms::server::Readiness::warming());
+    let app = spotted_arms::server::create_app(state.clone());
+    let ready = || async {
+        app.clone()
+            .oneshot(
+                axum::http::Request::get("/ready")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+            .status()
+    };
+
+    assert_eq!(ready().await, StatusCode::SERVICE_UNAVAILABLE);
+    let warmup = tokio::spawn(async move { spotted_arms::server::warm_up(&state).await });
+
+    // still fetching the template
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(ready().await, StatusCode::SERVICE_UNAVAILABLE);
+
+    warmup.await.unwrap();
+    assert_eq!(ready().await, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn ready_checks_gcp_and_github() {
+    use common::MockGithub;
+    use serde_json::{Value, json};
+    use spotted_arms::compute::ComputeError;
+    use spotted_arms::github::GithubError;
+
+    let compute = Arc::new(MockCompute::default());
+    let github = Arc::new(MockGithub::default());
+    let app = spotted_arms::server::create_app(test_state(
+        compute.clone(),
+        github.clone(),
+        Default::default(),
+    ));
+    let ready = || async {
+        let res = app
+            .clone()
+            .oneshot(
+                axum::http::Request::get("/ready")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = res.status();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice::<Value>(&body).unwrap())
+    };
+
+    let (status, body) = ready().await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["checks"], json!({ "gcp": "ok", "github": "ok" }));
+
+    *compute.template_error.lock().unwrap() =
+        Some(ComputeError::PermissionDenied("no credentials".into()));
+    let (status, body) = ready().await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body["checks"]["gcp"]["error"].is_string());
+    assert_eq!(body["checks"]["github"], "ok");
+
+    *github.token_error.lock().unwrap() = Some(GithubError::Other("bad credentials".into()));
+    let (status, body) = ready().await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["checks"]["gcp"], "ok");
+    assert!(body["checks"]["github"]["error"].is_string());
+
+    // the ping liveness probe never touches either dependency
+    *compute.template_error.lock().unwrap() = Some(ComputeError::NotFound);
+    let res = app
+        .clone()
+        .oneshot(
+            axum::http::Request::get("/ping")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(compute.template_error.lock().unwrap().is_some());
+}
+
+#[tokio::test]
+async fn missing_instance_template_fails_validation() {
+    use spotted_arms::compute::ComputeError;
+
+    let compute = Arc::new(MockCompute::default());
+    let mut state = test_state(compute.clone(), Arc::default(), Default::default());
+    assert_eq!(state.validate().await, Ok(()));
+
+    *compute.template_error.lock().unwrap() = Some(ComputeError::NotFound);
+    let err = state.validate().await.unwrap_err();
+    assert!(err.contains("does not exist"), "{err}");
+    assert!(err.contains("template"), "{err}");
+
+    // a templated name resolves per job, so there's nothing to read yet
+    state.instance_template = Arc::new("runner-{arch}".to_string());
+    *compute.template_error.lock().unwrap() = Some(ComputeError::NotFound);
+    assert_eq!(state.validate().await, Ok(()));
+}
+
+#[tokio::test]
+async fn health_check_echoes_headers_only_when_debugging() {
+    for debug_health in [false, true] {
+        let state = test_state(
+            Arc::default(),
+            Arc::default(),
+            spotted_arms::config::Config {
+                debug_health,
+                ..Default::default()
+            },
+        );
+        let res = spotted_arms::server::create_app(state)
+            .oneshot(
+                axum::http::Request::get("/health_check")
+                    .header("X-Hub-Signature-256", "sha256=secret")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(health["status"], "ok");
+        assert_eq!(health["version"], env!("CARGO_PKG_VERSION"));
+        assert!(health["git_sha"].is_string());
+        assert_eq!(
+            health["headers"]["x-hub-signature-256"].as_str(),
+            debug_health.then_some("sha256=secret")
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use clap::{CommandFactory, FromArgMatches};
+use spotted_arms::cli::{Cli, Command};
+
+fn parse(args: &[&str]) -> (Cli, clap::ArgMatches) {
+    let matches = Cli::command()
+        .try_get_matches_from(std::iter::once("spotted-arms").chain(args.iter().copied()))
+        .unwrap();
+    let cli = Cli::from_arg_matches(&matches).unwrap();
+    (cli, matches)
+}
+
+#[tokio::test]
+async fn print_config_reflects_overrides_and_redacts_secrets() {
+    let config_file = std::env::temp_dir().join(format!(
+        "spotted-arms-print-config-{}.json",
+        std::process::id()
+    ));
+    std::fs::write(
+        &config_file,
+        r#"{"jit_labels": ["from-file"], "repo_network_tags": true, "max_concurrent_requests": 4}"#,
+    )
+    .unwrap();
+
+    let (cli, matches) = parse(&[
+        "--github-credentials",
+        r#"{"token":"ghp_supersecret","secret":"hook-secret"}"#,
+        "--instance-template",
+        "runner-template",
+        "--project-id",
+        "my-project",
+        "--zone",
+        "us-central1-b",
+        "--config-file",
+        config_file.to_str().unwrap(),
+        "--jit-labels",
+        "ARM64,gpu",
+        "print-config",
+    ]);
+    assert!(matches!(cli.command, Some(Command::PrintConfig)));
+
+    let effective = cli.effective_config(&matches).await.unwrap();
+    let printed = serde_json::to_value(&effective).unwrap();
+    std::fs::remove_file(&config_file).unwrap();
+
+    // CLI flags win over the file, file values fill in the rest
+    assert_eq!(printed["jit_labels"], serde_json::json!(["ARM64", "gpu"]));
+    assert_eq!(printed["repo_network_tags"], true);
+    assert_eq!(printed["max_concurrent_requests"], 4);
+    assert_eq!(printed["project_id"], "my-project");
+    assert_eq!(printed["region"], "us-central1");
+    assert_eq!(printed["instance_template"], "runner-template");
+
+    assert_eq!(printed["github_credentials"], "<redacted>");
+    let text = printed.to_string();
+    assert!(!text.contains("ghp_supersecret"));
+    assert!(!text.contains("hook-secret"));
+}
+
+#[test]
+fn bind_address_defaults_to_dual_stack_and_accepts_ipv4() {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    let (cli, _) = parse(&[]);
+    assert_eq!(cli.bind_address, IpAddr::from(Ipv6Addr::UNSPECIFIED));
+
+    let (cli, _) = parse(&["--bind-address", "127.0.0.1"]);
+    assert_eq!(cli.bind_address, IpAddr::from(Ipv4Addr::LOCALHOST));
+
+    let (cli, _) = parse(&["--bind-address", "[::1]"]);
+    assert_eq!(cli.bind_address, IpAddr::from(Ipv6Addr::LOCALHOST));
+
+    assert!(
+        Cli::command()
+            .try_get_matches_from(["spotted-arms", "--bind-address", "localhost"])
+            .is_err()
+    );
+}
+
+#[test]
+fn zones_override_is_comma_separated_and_empty_by_default() {
+    let (cli, _) = parse(&[]);
+    assert!(cli.zones.is_empty());
+
+    let (cli, _) = parse(&["--zones", "us-central1-a,us-central1-c"]);
+    assert_eq!(cli.zones, vec!["us-central1-a", "us-central1-c"]);
+}
+
+#[test]
+fn max_run_duration_defaults_to_six_hours() {
+    use std::time::Duration;
+
+    let (cli, _) = parse(&[]);
+    assert_eq!(cli.config.max_run_duration, None);
+    assert_eq!(
+        cli.config.max_run_duration(),
+        Some(Duration::from_secs(6 * 60 * 60))
+    );
+
+    let (cli, _) = parse(&["--max-run-duration", "90m"]);
+    assert_eq!(
+        cli.config.max_run_duration(),
+        Some(Duration::from_secs(90 * 60))
+    );
+
+    let (cli, _) = parse(&["--max-runner-lifetime", "2h"]);
+    assert_eq!(
+        cli.config.max_run_duration(),
+        Some(Duration::from_secs(2 * 60 * 60))
+    );
+
+    let (cli, _) = parse(&["--max-run-duration", "0s"]);
+    assert_eq!(cli.config.max_run_duration(), None);
+}
+
+#[tokio::test]
+async fn check_stops_at_the_first_failing_step() {
+    let (cli, matches) = parse(&[
+        "--github-credentials",
+        "not json",
+        "--instance-template",
+        "runner-template",
+        "--project-id",
+        "my-project",
+        "--zone",
+        "us-central1-b",
+        "check",
+    ]);
+    assert!(matches!(cli.command, Some(Command::Check)));
+
+    let report = cli.check(&matches).await;
+    let steps = report
+        .iter()
+        .map(|o| (o.name, o.result.is_ok()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        steps,
+        [
+            ("configuration", true),
+            ("project", true),
+            ("credentials", false)
+        ]
+    );
+    assert_eq!(report[1].result.as_deref(), Ok("my-project in us-central1"));
+    assert!(report[2].to_string().starts_with("❌ credentials: "));
+
+    let (cli, _) = parse(&["serve"]);
+    assert!(matches!(cli.command, Some(Command::Serve)));
+}
+
+#[tokio::test]
+async fn credentials_file_wins_and_reports_what_is_wrong_with_it() {
+    use spotted_arms::server::GithubCredentials;
+
+    let path = std::env::temp_dir().join(format!(
+        "spotted-arms-credentials-{}.json",
+        std::process::id()
+    ));
+    let (cli, matches) = parse(&[
+        "--github-credentials",
+        r#"{"token":"from-flag","secret":"s"}"#,
+        "--github-credentials-file",
+        path.to_str().unwrap(),
+        "--instance-template",
+        "runner-template",
+        "--project-id",
+        "my-project",
+        "--zone",
+        "us-central1-b",
+        "check",
+    ]);
+    assert_eq!(
+        cli.github_credentials_source(),
+        Some(GithubCredentials::File(path.clone()))
+    );
+    let report = cli.check(&matches).await;
+    let err = report[2].result.as_ref().unwrap_err();
+    assert!(
+        err.contains("failed to read GitHub credentials file"),
+        "{err}"
+    );
+    assert!(err.contains(path.to_str().unwrap()), "{err}");
+
+    std::fs::write(&path, r#"{"token":"ghp_x"}"#).unwrap();
+    let report = cli.check(&matches).await;
+    std::fs::remove_file(&path).unwrap();
+    let err = report[2].result.as_ref().unwrap_err();
+    assert!(err.contains("invalid GitHub credentials JSON in"), "{err}");
+    assert!(!err.contains("ghp_x"), "{err}");
+
+    let (cli, _) = parse(&["--github-credentials", r#"{"secret":"s"}"#]);
+    assert_eq!(
+        cli.github_credentials_source(),
+        Some(GithubCredentials::Json(r#"{"secret":"s"}"#.to_string()))
+    );
+}
+
+#[test]
+fn credentials_secret_wins_over_file_and_json() {
+    use spotted_arms::server::GithubCredentials;
+
+    let secret = "projects/my-project/secrets/github/versions/latest";
+    let (cli, _) = parse(&[
+        "--github-credentials",
+        r#"{"token":"from-flag","secret":"s"}"#,
+        "--github-credentials-file",
+        "/run/secrets/github.json",
+        "--github-credentials-secret",
+        secret,
+    ]);
+    assert_eq!(
+        cli.github_credentials_source(),
+        Some(GithubCredentials::Secret(secret.to_string()))
+    );
+
+    let err = Cli::command()
+        .try_get_matches_from(["spotted-arms", "--github-credentials-secret", "github"])
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("projects/<project>/secrets"),
+        "{err}"
+    );
+}
@@ -0,0 +1,365 @@
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::http::HeaderMap;
+use axum_github_webhook_extract::GithubToken;
+use gcloud_sdk::google_rest_apis::compute_v1;
+use gcloud_sdk::google_rest_apis::compute_v1::instances_api::{
+    ComputePeriodInstancesPeriodDeleteParams, ComputePeriodInstancesPeriodGetParams,
+    ComputePeriodInstancesPeriodInsertParams, ComputePeriodInstancesPeriodListParams,
+};
+use gcloud_sdk::google_rest_apis::compute_v1::region_instance_templates_api::ComputePeriodRegionInstanceTemplatesPeriodGetParams;
+use gcloud_sdk::google_rest_apis::compute_v1::zone_operations_api::ComputePeriodZoneOperationsPeriodGetParams;
+use spotted_arms::clock::SystemClock;
+use spotted_arms::compute::{ComputeApi, ComputeError};
+use spotted_arms::config::Config;
+use spotted_arms::github::{GithubApi, GithubError, JitConfig, StaticToken};
+use spotted_arms::quota::{InstanceLimit, RepoQuotas};
+use spotted_arms::server::AppState;
+use spotted_arms::webhook::WorkflowJobWebhook;
+
+pub const SECRET: &str = "secret";
+
+/// Compute mock that records every call and succeeds unless told otherwise.
+#[derive(Default)]
+pub struct MockCompute {
+    pub inserts: Mutex<Vec<ComputePeriodInstancesPeriodInsertParams>>,
+    /// The `max_run_duration` passed with each insert
+    pub max_run_durations: Mutex<Vec<Option<Duration>>>,
+    pub deletes: Mutex<Vec<ComputePeriodInstancesPeriodDeleteParams>>,
+    pub insert_delay: Duration,
+    /// Instance template returned by template gets
+    pub template: compute_v1::InstanceTemplate,
+    /// How long each template get takes
+    pub template_delay: Duration,
+    /// Error returned by the next template get instead of succeeding
+    pub template_error: Mutex<Option<ComputeError>>,
+    /// Error returned by the next insert instead of succeeding
+    pub insert_error: Mutex<Option<ComputeError>>,
+    /// Operation returned by inserts
+    pub insert_operation: compute_v1::Operation,
+    /// Scripted zone operation poll results; `DONE` once exhausted
+    pub operations: Mutex<VecDeque<Result<compute_v1::Operation, ComputeError>>>,
+    pub operation_polls: Mutex<Vec<ComputePeriodZoneOperationsPeriodGetParams>>,
+    /// Existing instances, returned by list calls for their zone and by get calls by name
+    pub instances: Mutex<Vec<compute_v1::Instance>>,
+    /// Operation returned by deletes
+    pub delete_operation: compute_v1::Operation,
+    /// How long each delete call takes
+    pub delete_delay: Duration,
+    pub deletes_in_flight: Arc<AtomicUsize>,
+    /// Most delete calls seen running at once
+    pub max_deletes_in_flight: Arc<AtomicUsize>,
+    /// How long each list call takes
+    pub list_delay: Duration,
+    pub lists_in_flight: Arc<AtomicUsize>,
+    /// Most list calls seen running at once
+    pub max_lists_in_flight: Arc<AtomicUsize>,
+    /// Scripted list pages returned before falling back to `instances`
+    pub list_pages: Mutex<VecDeque<Result<compute_v1::InstanceList, ComputeError>>>,
+    pub lists: Mutex<Vec<ComputePeriodInstancesPeriodListParams>>,
+}
+
+impl ComputeApi for MockCompute {
+    fn compute_region_instance_templates_get(
+        &self,
+        _params: ComputePeriodRegionInstanceTemplatesPeriodGetParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::InstanceTemplate, ComputeError>> + Send>>
+    {
+        let template = self.template.clone();
+        let error = self.template_error.lock().unwrap().take();
+        let delay = self.template_delay;
+        Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            error.map_or(Ok(template), Err)
+        })
+    }
+
+    fn compute_instances_insert(
+        &self,
+        params: ComputePeriodInstancesPeriodInsertParams,
+        max_run_duration: Option<Duration>,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>> {
+        self.inserts.lock().unwrap().push(params);
+        self.max_run_durations
+            .lock()
+            .unwrap()
+            .push(max_run_duration);
+        let delay = self.insert_delay;
+        let error = self.insert_error.lock().unwrap().take();
+        let operation = self.insert_operation.clone();
+        Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            error.map_or(Ok(operation), Err)
+        })
+    }
+
+    fn compute_instances_get(
+        &self,
+        params: ComputePeriodInstancesPeriodGetParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Instance, ComputeError>> + Send>> {
+        let found = self
+            .instances
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|i| i.name.as_deref() == Some(params.instance.as_str()))
+            .cloned();
+        Box::pin(async move { found.ok_or(ComputeError::NotFound) })
+    }
+
+    fn compute_instances_delete(
+        &self,
+        params: ComputePeriodInstancesPeriodDeleteParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>> {
+        self.deletes.lock().unwrap().push(params);
+        let operation = self.delete_operation.clone();
+        let delay = self.delete_delay;
+        let (in_flight, max_in_flight) = (
+            self.deletes_in_flight.clone(),
+            self.max_deletes_in_flight.clone(),
+        );
+        Box::pin(async move {
+            let running = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            max_in_flight.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(delay).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(operation)
+        })
+    }
+
+    fn compute_zone_operations_get(
+        &self,
+        params: ComputePeriodZoneOperationsPeriodGetParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::Operation, ComputeError>> + Send>> {
+        self.operation_polls.lock().unwrap().push(params);
+        let next = self
+            .operations
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| Ok(operation(compute_v1::operation::Status::Done)));
+        Box::pin(async move { next })
+    }
+
+    fn compute_instances_list(
+        &self,
+        params: ComputePeriodInstancesPeriodListParams,
+    ) -> Pin<Box<dyn Future<Output = Result<compute_v1::InstanceList, ComputeError>> + Send>> {
+        self.lists.lock().unwrap().push(params.clone());
+        if let Some(page) = self.list_pages.lock().unwrap().pop_front() {
+            return Box::pin(async move { page });
+        }
+        let items = self
+            .instances
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|i| i.zone.as_deref() == Some(params.zone.as_str()))
+            .cloned()
+            .collect();
+        let delay = self.list_delay;
+        let (in_flight, max_in_flight) = (
+            self.lists_in_flight.clone(),
+            self.max_lists_in_flight.clone(),
+        );
+        Box::pin(async move {
+            let running = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            max_in_flight.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(delay).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(compute_v1::InstanceList {
+                items: Some(items),
+                ..Default::default()
+            })
+        })
+    }
+}
+
+/// A zone operation in the given state, as GCE returns them
+pub fn operation(status: compute_v1::operation::Status) -> compute_v1::Operation {
+    compute_v1::Operation {
+        name: Some("operation-1".to_string()),
+        zone: Some(
+            "https://www.googleapis.com/compute/v1/projects/test-project/zones/us-central1-a"
+                .to_string(),
+        ),
+        status: Some(status),
+        ..Default::default()
+    }
+}
+
+/// GitHub mock that records the repository, name and labels each runner is registered with.
+#[derive(Default)]
+pub struct MockGithub {
+    pub labels: Mutex<Vec<Vec<String>>>,
+    pub repo_urls: Mutex<Vec<reqwest::Url>>,
+    pub runner_names: Mutex<Vec<String>>,
+    /// Ids of deregistered runners
+    pub deleted_runners: Mutex<Vec<u64>>,
+    /// Returned once by the next token check
+    pub token_error: Mutex<Option<GithubError>>,
+}
+
+impl GithubApi for MockGithub {
+    fn generate_jit_config(
+        &self,
+        repo_url: &reqwest::Url,
+        _github_token: &str,
+        runner_name: &str,
+        labels: &[String],
+    ) -> Pin<Box<dyn Future<Output = Result<JitConfig, GithubError>> + Send>> {
+        self.repo_urls.lock().unwrap().push(repo_url.clone());
+        self.runner_names
+            .lock()
+            .unwrap()
+            .push(runner_name.to_string());
+        let mut labels_seen = self.labels.lock().unwrap();
+        labels_seen.push(labels.to_vec());
+        // runners are numbered in registration order
+        let runner_id = labels_seen.len() as u64;
+        let runner_name = runner_name.to_string();
+        Box::pin(async move {
+            Ok(JitConfig {
+                runner_id,
+                runner_name,
+                encoded: "encoded".to_string(),
+            })
+        })
+    }
+
+    fn delete_runner(
+        &self,
+        _repo_url: &reqwest::Url,
+        _github_token: &str,
+        runner_id: u64,
+    ) -> Pin<Box<dyn Future<Output = Result<(), GithubError>> + Send>> {
+        self.deleted_runners.lock().unwrap().push(runner_id);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn find_runner(
+        &self,
+        _repo_url: &reqwest::Url,
+        _github_token: &str,
+        _runner_name: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<u64>, GithubError>> + Send>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    fn check_token(
+        &self,
+        _github_token: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<(), GithubError>> + Send>> {
+        let error = self.token_error.lock().unwrap().take();
+        Box::pin(async move { error.map_or(Ok(()), Err) })
+    }
+}
+
+/// Serves `router` on a loopback port, returning its base URL, e.g. `http://127.0.0.1:1234/`
+pub async fn serve(router: axum::Router) -> reqwest::Url {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+
+    format!("http://{addr}/").parse().unwrap()
+}
+
+/// A fresh `X-GitHub-Delivery` id, so events sharing a state aren't dropped as redeliveries
+pub fn delivery_id() -> String {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    format!("test-delivery-{}", NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+pub fn workflow_job_headers() -> HeaderMap {
+    let mut h = HeaderMap::new();
+    h.insert("X-GitHub-Event", "workflow_job".parse().unwrap());
+    h.insert("X-GitHub-Delivery", delivery_id().parse().unwrap());
+    h
+}
+
+pub fn test_state(compute: Arc<MockCompute>, github: Arc<MockGithub>, config: Config) -> AppState {
+    AppState {
+        compute_client: compute,
+        github_client: github,
+        project_id: Arc::new("test-project".to_string()),
+        region: Arc::new("us-central1".to_string()),
+        zones: Arc::default(),
+        required_labels: Arc::new(config.required_labels()),
+        secret: GithubToken(Arc::new(SECRET.into())),
+        token: Arc::new(StaticToken("token".into())),
+        instance_template: Arc::new("template".into()),
+        repo_quotas: Arc::new(RepoQuotas::new(&config, Arc::new(SystemClock))),
+        instance_limit: Arc::new(InstanceLimit::new(config.max_instances)),
+        config: Arc::new(config),
+        metrics: spotted_arms::metrics::registry(),
+        config_file: None,
+        admin_token: None,
+        job_traces: Arc::default(),
+        metadata_transform: Arc::new(spotted_arms::instance::NoopMetadataTransform),
+        dead_letters: Arc::default(),
+        clock: Arc::new(SystemClock),
+        lingering_deletes: Arc::default(),
+        decision_log: Arc::default(),
+        deliveries: Arc::default(),
+        instance_locks: Arc::default(),
+        readiness: Arc::default(),
+    }
+}
+
+pub fn workflow_job_payload(action: &str, labels: &[&str]) -> serde_json::Value {
+    repo_workflow_job_payload(action, "owner/repo", 7, labels)
+}
+
+/// A workflow job payload for job `job_id` of repository `full_name`
+pub fn repo_workflow_job_payload(
+    action: &str,
+    full_name: &str,
+    job_id: i64,
+    labels: &[&str],
+) -> serde_json::Value {
+    serde_json::json!({
+        "action": action,
+        "workflow_job": {"id": job_id, "run_id": 11, "labels": labels},
+        "repository": {
+            "id": 1,
+            "name": full_name.rsplit('/').next(),
+            "url": format!("https://api.github.com/repos/{full_name}"),
+            "full_name": full_name
+        }
+    })
+}
+
+pub fn queued_payload(labels: &[&str]) -> serde_json::Value {
+    workflow_job_payload("queued", labels)
+}
+
+pub fn queued_event(labels: &[&str]) -> WorkflowJobWebhook {
+    serde_json::from_value(queued_payload(labels)).unwrap()
+}
+
+pub fn completed_event(labels: &[&str]) -> WorkflowJobWebhook {
+    serde_json::from_value(workflow_job_payload("completed", labels)).unwrap()
+}
+
+/// Builds a signed `POST /webhook` request as GitHub would deliver it.
+pub fn signed_webhook_request(body: &[u8]) -> axum::http::Request<axum::body::Body> {
+    let signature = hmac_sha256::HMAC::mac(body, SECRET.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+
+    axum::http::Request::post("/webhook")
+        .header("X-GitHub-Event", "workflow_job")
+        .header("X-GitHub-Delivery", delivery_id())
+        .header("X-Hub-Signature-256", format!("sha256={signature}"))
+        .header("Content-Type", "application/json")
+        .body(axum::body::Body::from(body.to_vec()))
+        .unwrap()
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use common::MockCompute;
+use gcloud_sdk::google_rest_apis::compute_v1::instances_api::ComputePeriodInstancesPeriodDeleteParams;
+use spotted_arms::compute::{ComputeApi, DeleteLimitedCompute};
+
+#[tokio::test(start_paused = true)]
+async fn deletes_beyond_the_cap_queue() {
+    let compute = Arc::new(MockCompute {
+        delete_delay: Duration::from_secs(1),
+        ..Default::default()
+    });
+    let limited = Arc::new(DeleteLimitedCompute::new(compute.clone(), 2));
+    let in_flight =
+        || spotted_arms::metrics::registry().gauge("spotted_arms_instance_deletes_in_flight", &[]);
+
+    let deletes = (0..5)
+        .map(|i| {
+            tokio::spawn(limited.compute_instances_delete(
+                ComputePeriodInstancesPeriodDeleteParams {
+                    project: "test-project".to_string(),
+                    zone: "us-central1-a".to_string(),
+                    instance: format!("gha-{i}"),
+                    ..Default::default()
+                },
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(compute.deletes_in_flight.load(Ordering::SeqCst), 2);
+    assert_eq!(in_flight(), 2);
+
+    for delete in deletes {
+        assert!(delete.await.unwrap().is_ok());
+    }
+    assert_eq!(compute.deletes.lock().unwrap().len(), 5);
+    assert_eq!(compute.max_deletes_in_flight.load(Ordering::SeqCst), 2);
+    assert_eq!(in_flight(), 0);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::{MockCompute, MockGithub, completed_event, queued_event, test_state};
+use spotted_arms::compute::DryRunCompute;
+use spotted_arms::github::DryRunGithub;
+
+#[tokio::test]
+async fn dry_run_neither_creates_nor_deletes_instances() {
+    let compute = Arc::new(MockCompute::default());
+    let github = Arc::new(MockGithub::default());
+    let mut state = test_state(compute.clone(), github.clone(), Default::default());
+    state.compute_client = Arc::new(DryRunCompute::new(compute.clone()));
+    state.github_client = Arc::new(DryRunGithub::new(github.clone()));
+
+    for event in [
+        queued_event(&["self-hosted", "linux", "ARM64"]),
+        completed_event(&["self-hosted", "linux", "ARM64"]),
+    ] {
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            common::workflow_job_headers(),
+            axum::extract::State(state.clone()),
+            axum_github_webhook_extract::GithubEvent(event),
+        )
+        .await;
+        assert!(res.is_ok());
+    }
+
+    // the template is still read, but nothing is written to GCE or GitHub
+    assert!(compute.inserts.lock().unwrap().is_empty());
+    assert!(compute.deletes.lock().unwrap().is_empty());
+    assert!(github.labels.lock().unwrap().is_empty());
+    assert!(github.deleted_runners.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn dry_run_operations_are_already_done() {
+    use gcloud_sdk::google_rest_apis::compute_v1;
+    use gcloud_sdk::google_rest_apis::compute_v1::instances_api::ComputePeriodInstancesPeriodInsertParams;
+    use spotted_arms::compute::ComputeApi;
+
+    let compute = Arc::new(MockCompute::default());
+    let dry_run = DryRunCompute::new(compute.clone());
+
+    let operation = dry_run
+        .compute_instances_insert(
+            ComputePeriodInstancesPeriodInsertParams {
+                project: "test-project".to_string(),
+                zone: "us-central1-a".to_string(),
+                instance: Some(compute_v1::Instance {
+                    name: Some("gha-1-1-1".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            None,
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(operation.status, Some(compute_v1::operation::Status::Done));
+    assert_eq!(
+        operation.zone.as_deref(),
+        Some("projects/test-project/zones/us-central1-a")
+    );
+    assert!(compute.inserts.lock().unwrap().is_empty());
+}
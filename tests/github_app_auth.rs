@@ -0,0 +1,88 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use axum::Json;
+use axum::extract::Path;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use spotted_arms::clock::{Clock, FakeClock};
+use spotted_arms::github::{AppInstallationToken, TokenProvider};
+
+const PRIVATE_KEY: &str = include_str!("fixtures/github-app-key.pem");
+
+/// GitHub's installation token lifetime
+const TOKEN_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// A clock on a whole second, as `expires_at` is
+fn clock() -> Arc<FakeClock> {
+    Arc::new(FakeClock::new(
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+    ))
+}
+
+/// Serves a GitHub-like installation token endpoint on loopback, minting tokens that expire
+/// an hour after `clock` reads; returns the API base URL and a count of exchanges
+async fn mock_github(clock: Arc<FakeClock>) -> (reqwest::Url, Arc<AtomicUsize>) {
+    let exchanges = Arc::new(AtomicUsize::new(0));
+    let counter = exchanges.clone();
+    let app = axum::Router::new().route(
+        "/app/installations/{installation}/access_tokens",
+        post(
+            move |Path(installation): Path<u64>, headers: HeaderMap| async move {
+                let bearer = headers["authorization"].to_str().unwrap();
+                // a JWT: header, claims and signature
+                assert_eq!(
+                    bearer.strip_prefix("Bearer ").unwrap().split('.').count(),
+                    3
+                );
+                assert_eq!(installation, 42);
+                let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                (
+                    StatusCode::CREATED,
+                    Json(serde_json::json!({
+                        "token": format!("ghs_{n}"),
+                        "expires_at": humantime::format_rfc3339_seconds(
+                            clock.system_now() + TOKEN_LIFETIME
+                        )
+                        .to_string(),
+                    })),
+                )
+            },
+        ),
+    );
+    (common::serve(app).await, exchanges)
+}
+
+#[tokio::test]
+async fn installation_token_is_cached_until_near_expiry() {
+    let clock = clock();
+    let (api_base, exchanges) = mock_github(clock.clone()).await;
+    let provider = AppInstallationToken::new(api_base, 7, 42, PRIVATE_KEY, clock.clone()).unwrap();
+
+    assert_eq!(provider.token().await.unwrap(), "ghs_1");
+    clock.advance(Duration::from_secs(50 * 60));
+    assert_eq!(provider.token().await.unwrap(), "ghs_1");
+    assert_eq!(exchanges.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn nearly_expired_installation_token_is_replaced() {
+    let clock = clock();
+    let (api_base, exchanges) = mock_github(clock.clone()).await;
+    let provider = AppInstallationToken::new(api_base, 7, 42, PRIVATE_KEY, clock.clone()).unwrap();
+
+    assert_eq!(provider.token().await.unwrap(), "ghs_1");
+    // inside the five minute refresh margin
+    clock.advance(Duration::from_secs(56 * 60));
+    assert_eq!(provider.token().await.unwrap(), "ghs_2");
+    assert_eq!(exchanges.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn invalid_private_key_is_rejected() {
+    let api_base = "https://api.github.com".parse().unwrap();
+    assert!(AppInstallationToken::new(api_base, 7, 42, "not a key", clock()).is_err());
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::get;
+use spotted_arms::github::{GithubApi, GithubClient};
+
+/// Serves `GET /rate_limit`, succeeding only for requests carrying `x-test-client`
+async fn mock_github() -> reqwest::Url {
+    let app = axum::Router::new().route(
+        "/rate_limit",
+        get(|headers: HeaderMap| async move {
+            if headers.contains_key("x-test-client") {
+                StatusCode::OK
+            } else {
+                StatusCode::UNAUTHORIZED
+            }
+        }),
+    );
+    common::serve(app).await
+}
+
+#[tokio::test]
+async fn injected_client_sends_the_requests() {
+    let api = mock_github().await;
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("x-test-client", "1".parse().unwrap());
+    let client = reqwest::Client::builder()
+        .default_headers(headers)
+        .build()
+        .unwrap();
+
+    assert!(
+        GithubClient::with_base_url(api.clone())
+            .check_token("token")
+            .await
+            .is_err()
+    );
+    assert!(
+        GithubClient::with_base_url(api)
+            .with_client(client)
+            .check_token("token")
+            .await
+            .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn deregistration_failures_are_classified() {
+    use axum::extract::Path;
+    use axum::routing::delete;
+    use spotted_arms::github::GithubError;
+
+    // the runner id picks the answer
+    let app = axum::Router::new().route(
+        "/repos/owner/repo/actions/runners/{runner_id}",
+        delete(|Path(runner_id): Path<u16>| async move {
+            let mut headers = HeaderMap::new();
+            if runner_id == 403 {
+                headers.insert("x-ratelimit-remaining", "0".parse().unwrap());
+            }
+            (StatusCode::from_u16(runner_id).unwrap(), headers)
+        }),
+    );
+    let api = common::serve(app).await;
+    let repo_url = api.join("repos/owner/repo").unwrap();
+    let client = GithubClient::with_base_url(api);
+
+    for (runner_id, transient) in [(429, true), (403, true), (502, true), (401, false)] {
+        let err = client
+            .delete_runner(&repo_url, "token", runner_id)
+            .await
+            .unwrap_err();
+        assert_eq!(err.is_transient(), transient, "{runner_id}: {err}");
+    }
+    assert!(matches!(
+        client.delete_runner(&repo_url, "token", 429).await,
+        Err(GithubError::RateLimited)
+    ));
+}
@@ -0,0 +1,62 @@
+mod common;
+
+use axum::Json;
+use axum::http::StatusCode;
+use axum::routing::post;
+use spotted_arms::github::{GithubApi, GithubClient, JitConfig};
+
+/// Serves a GitHub-like JIT config endpoint on loopback, returning the repository API URL
+async fn mock_github(status: StatusCode) -> reqwest::Url {
+    let app = axum::Router::new().route(
+        "/repos/owner/repo/actions/runners/generate-jitconfig",
+        post(move || async move {
+            (
+                status,
+                Json(serde_json::json!({
+                    "runner": {"id": 23, "name": "runner-1", "runner_group_id": 1},
+                    "encoded_jit_config": "encoded",
+                })),
+            )
+        }),
+    );
+    common::serve(app).await.join("repos/owner/repo").unwrap()
+}
+
+#[tokio::test]
+async fn jit_config_latency_and_status_are_recorded() {
+    let registry = spotted_arms::metrics::registry();
+    let histogram = |status| {
+        registry.histogram_count(
+            "spotted_arms_github_request_duration_seconds",
+            &[("operation", "generate_jit_config"), ("status", status)],
+        )
+    };
+    let (created, failed) = (histogram("201"), histogram("500"));
+
+    let created_url = mock_github(StatusCode::CREATED).await;
+    let ok = GithubClient::with_base_url(created_url.clone())
+        .generate_jit_config(&created_url, "token", "runner", &[])
+        .await;
+    let failed_url = mock_github(StatusCode::INTERNAL_SERVER_ERROR).await;
+    let err = GithubClient::with_base_url(failed_url.clone())
+        .generate_jit_config(&failed_url, "token", "runner", &[])
+        .await;
+
+    assert_eq!(
+        ok.unwrap(),
+        JitConfig {
+            runner_id: 23,
+            runner_name: "runner-1".to_string(),
+            encoded: "encoded".to_string(),
+        }
+    );
+    assert!(err.is_err());
+    assert_eq!(histogram("201"), created + 1);
+    assert_eq!(histogram("500"), failed + 1);
+    assert!(
+        registry
+            .render()
+            .unwrap()
+            .contains("# TYPE spotted_arms_github_request_duration_seconds histogram")
+    );
+}
@@ -1,88 +1,21 @@
+mod common;
+
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
-use axum::http::HeaderMap;
-use axum_github_webhook_extract::GithubToken;
+use axum::http::StatusCode;
+use common::{
+    MockCompute, MockGithub, completed_event, queued_event, repo_workflow_job_payload, test_state,
+    workflow_job_headers, workflow_job_payload,
+};
 use serde_json::Deserializer;
-
-struct MockCompute;
-
-struct MockGithub;
-
-impl spotted_arms::compute::ComputeApi for MockCompute {
-    fn compute_region_instance_templates_get(
-        &self,
-        _params: gcloud_sdk::google_rest_apis::compute_v1::region_instance_templates_api::ComputePeriodRegionInstanceTemplatesPeriodGetParams,
-    ) -> std::pin::Pin<
-        Box<
-            dyn std::future::Future<
-                    Output = Result<
-                        gcloud_sdk::google_rest_apis::compute_v1::InstanceTemplate,
-                        spotted_arms::compute::ComputeError,
-                    >,
-                > + Send,
-        >,
-    > {
-        Box::pin(async { Err(spotted_arms::compute::ComputeError::Other("unused".into())) })
-    }
-
-    fn compute_instances_insert(
-        &self,
-        _params: gcloud_sdk::google_rest_apis::compute_v1::instances_api::ComputePeriodInstancesPeriodInsertParams,
-    ) -> std::pin::Pin<
-        Box<
-            dyn std::future::Future<
-                    Output = Result<
-                        gcloud_sdk::google_rest_apis::compute_v1::Operation,
-                        spotted_arms::compute::ComputeError,
-                    >,
-                > + Send,
-        >,
-    > {
-        Box::pin(async { Err(spotted_arms::compute::ComputeError::Other("unused".into())) })
-    }
-
-    fn compute_instances_delete(
-        &self,
-        _params: gcloud_sdk::google_rest_apis::compute_v1::instances_api::ComputePeriodInstancesPeriodDeleteParams,
-    ) -> std::pin::Pin<
-        Box<
-            dyn std::future::Future<
-                    Output = Result<
-                        gcloud_sdk::google_rest_apis::compute_v1::Operation,
-                        spotted_arms::compute::ComputeError,
-                    >,
-                > + Send,
-        >,
-    > {
-        Box::pin(async { Err(spotted_arms::compute::ComputeError::NotFound) })
-    }
-}
-
-impl spotted_arms::github::GithubApi for MockGithub {
-    fn generate_jit_config(
-        &self,
-        _repo_url: &reqwest::Url,
-        _github_token: &str,
-        _runner_name: &str,
-        _labels: &[String],
-    ) -> std::pin::Pin<
-        Box<
-            dyn std::future::Future<Output = Result<String, spotted_arms::github::GithubError>>
-                + Send,
-        >,
-    > {
-        Box::pin(async { Err(spotted_arms::github::GithubError::Other("unused".into())) })
-    }
-}
+use spotted_arms::clock::{FakeClock, SystemClock};
+use spotted_arms::config::NoRoute;
 
 #[tokio::test]
 async fn handle_completed_event_deletes_instance() {
-    let headers = {
-        let mut h = HeaderMap::new();
-        h.insert("X-GitHub-Event", "workflow_job".parse().unwrap());
-        h.insert("X-GitHub-Delivery", "test-delivery".parse().unwrap());
-        h
-    };
+    let headers = workflow_job_headers();
 
     // Load completed payload
     let body_str = include_str!("fixtures/completed-payload.json");
@@ -90,15 +23,7 @@ async fn handle_completed_event_deletes_instance() {
     let body: spotted_arms::webhook::WorkflowJobWebhook =
         serde_path_to_error::deserialize(&mut de).unwrap();
 
-    let state = spotted_arms::server::AppState {
-        compute_client: Arc::new(MockCompute),
-        github_client: Arc::new(MockGithub),
-        project_id: Arc::new("test-project".to_string()),
-        region: Arc::new("us-central1".to_string()),
-        secret: GithubToken(Arc::new("secret".into())),
-        token: Arc::new("token".into()),
-        instance_template: Arc::new("template".into()),
-    };
+    let state = test_state(Arc::default(), Arc::default(), Default::default());
 
     let res = spotted_arms::webhook::handle_workflow_job_event(
         headers,
@@ -109,3 +34,2226 @@ async fn handle_completed_event_deletes_instance() {
 
     assert!(res.is_ok());
 }
+
+#[tokio::test]
+async fn handle_queued_event_forwards_only_allow_listed_labels() {
+    let github = Arc::new(MockGithub::default());
+    let state = test_state(
+        Arc::default(),
+        github.clone(),
+        spotted_arms::config::Config {
+            jit_labels: Some(vec!["ARM64".to_string(), "gpu".to_string()]),
+            ..Default::default()
+        },
+    );
+
+    let body = queued_event(&["self-hosted", "linux", "ARM64", "gpu", "custom-extra"]);
+
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(body),
+    )
+    .await;
+
+    assert!(res.is_ok());
+    assert_eq!(
+        *github.labels.lock().unwrap(),
+        vec![vec!["ARM64".to_string(), "gpu".to_string()]]
+    );
+}
+
+fn routed_config(on_no_route: spotted_arms::config::NoRoute) -> spotted_arms::config::Config {
+    spotted_arms::config::Config {
+        routes: vec!["gpu=gpu-template".parse().unwrap()],
+        on_no_route,
+        ..Default::default()
+    }
+}
+
+async fn handle_queued(state: spotted_arms::server::AppState, labels: &[&str]) {
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(queued_event(labels)),
+    )
+    .await;
+
+    assert!(res.is_ok());
+}
+
+fn inserted_templates(compute: &MockCompute) -> Vec<String> {
+    compute
+        .inserts
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|p| p.source_instance_template.clone())
+        .collect()
+}
+
+#[tokio::test]
+async fn routed_job_uses_route_template() {
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        routed_config(NoRoute::Ignore),
+    );
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64", "gpu"]).await;
+
+    assert_eq!(
+        inserted_templates(&compute),
+        vec!["projects/test-project/regions/us-central1/instanceTemplates/gpu-template"]
+    );
+}
+
+#[tokio::test]
+async fn each_runner_type_boots_from_its_first_matching_route() {
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            required_labels: Some(vec!["self-hosted".to_string(), "linux".to_string()]),
+            routes: [
+                "gpu,ARM64=arm-gpu-template",
+                "ARM64=arm-template",
+                "X64=x64-template",
+            ]
+            .iter()
+            .map(|r| r.parse().unwrap())
+            .collect(),
+            ..Default::default()
+        },
+    );
+
+    handle_queued(state.clone(), &["self-hosted", "linux", "ARM64"]).await;
+    handle_queued(state.clone(), &["self-hosted", "linux", "X64"]).await;
+    handle_queued(state, &["self-hosted", "linux", "ARM64", "gpu"]).await;
+
+    let prefix = "projects/test-project/regions/us-central1/instanceTemplates/";
+    assert_eq!(
+        inserted_templates(&compute),
+        ["arm-template", "x64-template", "arm-gpu-template"].map(|t| format!("{prefix}{t}"))
+    );
+}
+
+#[tokio::test]
+async fn jobs_matching_any_label_group_boot_from_its_template() {
+    let compute = Arc::new(MockCompute::default());
+    let github = Arc::new(MockGithub::default());
+    let config = serde_json::from_value::<spotted_arms::config::Config>(serde_json::json!({
+        "label_groups": [
+            { "name": "arm64", "labels": ["self-hosted", "linux", "ARM64"] },
+            { "name": "gpu", "labels": ["self-hosted", "linux", "gpu"], "template": "gpu-template" },
+        ],
+    }))
+    .unwrap();
+    let state = test_state(compute.clone(), github.clone(), config);
+
+    handle_queued(state.clone(), &["self-hosted", "linux", "ARM64"]).await;
+    handle_queued(state.clone(), &["self-hosted", "linux", "gpu"]).await;
+    handle_queued(state, &["self-hosted", "windows", "X64"]).await;
+
+    let prefix = "projects/test-project/regions/us-central1/instanceTemplates/";
+    assert_eq!(
+        inserted_templates(&compute),
+        ["template", "gpu-template"].map(|t| format!("{prefix}{t}"))
+    );
+    assert_eq!(github.labels.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn decisions_are_written_as_ndjson() {
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let buffer = Buffer::default();
+    let mut state = test_state(Arc::default(), Arc::default(), Default::default());
+    state.decision_log = Arc::new(spotted_arms::telemetry::DecisionLog::new(buffer.clone()));
+
+    handle_queued(state.clone(), &["self-hosted", "linux", "ARM64"]).await;
+    handle_queued(state, &["self-hosted", "windows"]).await;
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let entries = output
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(entries.len(), 2);
+
+    let created = &entries[0];
+    assert_eq!(created["decision"], "created");
+    assert_eq!(created["action"], "queued");
+    assert!(created["repository"].is_string());
+    assert!(created["run_id"].is_u64());
+    assert!(created["job_id"].is_u64());
+    assert!(created["instance_name"].is_string());
+    assert!(created["delivery"].is_string());
+    assert!(humantime::parse_rfc3339(created["timestamp"].as_str().unwrap()).is_ok());
+    assert!(created.get("reason").is_none());
+
+    assert_eq!(entries[1]["decision"], "ignored");
+    assert_eq!(entries[1]["reason"], "missing_labels");
+    assert_eq!(
+        entries[1]["labels"],
+        serde_json::json!(["self-hosted", "windows"])
+    );
+}
+
+#[tokio::test]
+async fn repo_visibility_policy_filters_public_and_private_repos() {
+    use spotted_arms::config::RepoVisibility;
+
+    for (policy, private, provisioned) in [
+        (RepoVisibility::All, Some(false), true),
+        (RepoVisibility::All, Some(true), true),
+        (RepoVisibility::All, None, true),
+        (RepoVisibility::Private, Some(true), true),
+        (RepoVisibility::Private, Some(false), false),
+        (RepoVisibility::Private, None, false),
+        (RepoVisibility::Public, Some(false), true),
+        (RepoVisibility::Public, Some(true), false),
+    ] {
+        let compute = Arc::new(MockCompute::default());
+        let state = test_state(
+            compute.clone(),
+            Arc::default(),
+            spotted_arms::config::Config {
+                repo_visibility: policy,
+                ..Default::default()
+            },
+        );
+
+        let mut payload = common::queued_payload(&["self-hosted", "linux", "ARM64"]);
+        if let Some(private) = private {
+            payload["repository"]["private"] = private.into();
+        }
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state),
+            axum_github_webhook_extract::GithubEvent(serde_json::from_value(payload).unwrap()),
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(
+            compute.inserts.lock().unwrap().len(),
+            usize::from(provisioned),
+            "{policy:?} with private={private:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn machine_label_overrides_the_template_machine_type() {
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(compute.clone(), Arc::default(), Default::default());
+
+    handle_queued(state.clone(), &["self-hosted", "linux", "ARM64"]).await;
+    handle_queued(
+        state,
+        &["self-hosted", "linux", "ARM64", "machine:n2-standard-8"],
+    )
+    .await;
+
+    let inserts = compute.inserts.lock().unwrap();
+    let machine_type = |i: usize| {
+        inserts[i]
+            .instance
+            .as_ref()
+            .and_then(|instance| instance.machine_type.clone())
+    };
+    assert_eq!(machine_type(0), None);
+    assert_eq!(
+        machine_type(1),
+        Some(format!(
+            "zones/{}/machineTypes/n2-standard-8",
+            inserts[1].zone
+        ))
+    );
+}
+
+#[tokio::test]
+async fn malformed_machine_label_is_rejected() {
+    use axum::response::IntoResponse;
+
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(compute.clone(), Arc::default(), Default::default());
+
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(queued_event(&[
+            "self-hosted",
+            "linux",
+            "ARM64",
+            "machine:../n2-standard-8",
+        ])),
+    )
+    .await;
+
+    assert_eq!(res.into_response().status(), StatusCode::BAD_REQUEST);
+    assert!(compute.inserts.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn allow_listed_template_label_overrides_routes() {
+    for (label, template) in [
+        ("template=pinned-template", "pinned-template"),
+        ("template=unlisted-template", "gpu-template"),
+    ] {
+        let compute = Arc::new(MockCompute::default());
+        let state = test_state(
+            compute.clone(),
+            Arc::default(),
+            spotted_arms::config::Config {
+                label_templates: vec!["pinned-template".to_string()],
+                ..routed_config(NoRoute::Ignore)
+            },
+        );
+
+        handle_queued(state, &["self-hosted", "linux", "ARM64", "gpu", label]).await;
+
+        assert_eq!(
+            inserted_templates(&compute),
+            vec![format!(
+                "projects/test-project/regions/us-central1/instanceTemplates/{template}"
+            )]
+        );
+    }
+}
+
+#[tokio::test]
+async fn unrouted_job_falls_back_to_default_template() {
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        routed_config(NoRoute::Default),
+    );
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    assert_eq!(
+        inserted_templates(&compute),
+        vec!["projects/test-project/regions/us-central1/instanceTemplates/template"]
+    );
+}
+
+#[tokio::test]
+async fn unrouted_job_is_ignored_when_configured() {
+    let compute = Arc::new(MockCompute::default());
+    let github = Arc::new(MockGithub::default());
+    let state = test_state(
+        compute.clone(),
+        github.clone(),
+        routed_config(NoRoute::Ignore),
+    );
+
+    let ignored = || {
+        spotted_arms::metrics::registry().counter(
+            "spotted_arms_ignored_events_total",
+            &[("reason", "no_route")],
+        )
+    };
+    let before = ignored();
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    assert!(compute.inserts.lock().unwrap().is_empty());
+    assert!(github.labels.lock().unwrap().is_empty());
+    assert!(ignored() > before);
+}
+
+#[tokio::test]
+async fn project_label_selects_project_for_create_and_delete() {
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            label_projects: vec!["team-a=gcp-team-a".parse().unwrap()],
+            ..Default::default()
+        },
+    );
+    let labels = ["self-hosted", "linux", "ARM64", "project=team-a"];
+
+    for event in [queued_event(&labels), completed_event(&labels)] {
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state.clone()),
+            axum_github_webhook_extract::GithubEvent(event),
+        )
+        .await;
+        assert!(res.is_ok());
+    }
+
+    let inserts = compute.inserts.lock().unwrap();
+    let deletes = compute.deletes.lock().unwrap();
+    assert_eq!(inserts.len(), 1);
+    assert_eq!(inserts[0].project, "gcp-team-a");
+    assert_eq!(deletes.len(), 1);
+    assert_eq!(deletes[0].project, "gcp-team-a");
+    assert_eq!(
+        deletes[0].instance,
+        inserts[0].instance.as_ref().unwrap().name.clone().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn unlisted_project_label_is_rejected() {
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(compute.clone(), Arc::default(), Default::default());
+
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(queued_event(&[
+            "self-hosted",
+            "linux",
+            "ARM64",
+            "project=team-b",
+        ])),
+    )
+    .await;
+
+    assert!(res.is_err());
+    assert!(compute.inserts.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn jit_generation_is_throttled_past_rate_limit() {
+    use axum::response::IntoResponse;
+
+    let compute = Arc::new(MockCompute::default());
+    let mut state = test_state(compute.clone(), Arc::default(), Default::default());
+    state.github_client = Arc::new(spotted_arms::github::RateLimitedGithub::new(
+        Arc::new(MockGithub::default()),
+        2.0,
+        Arc::new(SystemClock),
+    ));
+
+    let throttled =
+        || spotted_arms::metrics::registry().counter("spotted_arms_jit_throttled_total", &[]);
+    let before = throttled();
+
+    let mut statuses = Vec::new();
+    for _ in 0..3 {
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state.clone()),
+            axum_github_webhook_extract::GithubEvent(queued_event(&[
+                "self-hosted",
+                "linux",
+                "ARM64",
+            ])),
+        )
+        .await;
+        statuses.push(res.into_response().status());
+    }
+
+    assert_eq!(
+        statuses,
+        vec![
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::TOO_MANY_REQUESTS
+        ]
+    );
+    assert_eq!(compute.inserts.lock().unwrap().len(), 2);
+    assert!(throttled() > before);
+}
+
+async fn handle_repo_job(
+    state: &spotted_arms::server::AppState,
+    action: &str,
+    repository: &str,
+    job_id: i64,
+) -> StatusCode {
+    use axum::response::IntoResponse;
+
+    let body = serde_json::from_value(repo_workflow_job_payload(
+        action,
+        repository,
+        job_id,
+        &["self-hosted", "linux", "ARM64"],
+    ))
+    .unwrap();
+
+    spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state.clone()),
+        axum_github_webhook_extract::GithubEvent(body),
+    )
+    .await
+    .into_response()
+    .status()
+}
+
+#[tokio::test]
+async fn repo_over_quota_is_rejected_while_others_proceed() {
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            max_instances_per_repo: Some(1),
+            repo_instance_limits: vec!["owner/big=2".parse().unwrap()],
+            ..Default::default()
+        },
+    );
+
+    let statuses = [
+        handle_repo_job(&state, "queued", "owner/repo", 1).await,
+        handle_repo_job(&state, "queued", "owner/repo", 2).await,
+        handle_repo_job(&state, "queued", "owner/other", 3).await,
+        handle_repo_job(&state, "queued", "owner/big", 4).await,
+        handle_repo_job(&state, "queued", "owner/big", 5).await,
+        // redelivery of a job already counted is not rejected
+        handle_repo_job(&state, "queued", "owner/repo", 1).await,
+    ];
+
+    assert_eq!(
+        statuses,
+        [
+            StatusCode::OK,
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::OK,
+            StatusCode::OK,
+        ]
+    );
+    assert_eq!(compute.inserts.lock().unwrap().len(), 5);
+
+    // completing a job frees its slot
+    assert_eq!(
+        handle_repo_job(&state, "completed", "owner/repo", 1).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        handle_repo_job(&state, "queued", "owner/repo", 2).await,
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn reconcile_counts_labeled_instances() {
+    let compute = Arc::new(MockCompute::default());
+    let instance = |name: &str, zone: &str| gcloud_sdk::google_rest_apis::compute_v1::Instance {
+        name: Some(name.to_string()),
+        zone: Some(zone.to_string()),
+        labels: Some([("repository".to_string(), "owner-repo".to_string())].into()),
+        ..Default::default()
+    };
+    *compute.instances.lock().unwrap() = vec![
+        instance("gha-1-1", "us-central1-a"),
+        instance("gha-1-2", "us-central1-b"),
+    ];
+
+    let quotas = spotted_arms::quota::RepoQuotas::new(
+        &spotted_arms::config::Config {
+            max_instances_per_repo: Some(2),
+            ..Default::default()
+        },
+        Arc::new(SystemClock),
+    );
+    assert!(quotas.try_reserve("owner/repo", "gha-stale"));
+
+    spotted_arms::quota::reconcile(
+        compute.as_ref(),
+        &quotas,
+        &["test-project".to_string()],
+        &["us-central1-a", "us-central1-b"],
+        4,
+        Duration::from_secs(60),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(quotas.in_use("owner/repo"), 2);
+    assert!(!quotas.try_reserve("owner/repo", "gha-1-3"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn reconcile_lists_zones_within_the_concurrency_bound() {
+    let compute = Arc::new(MockCompute {
+        list_delay: Duration::from_secs(1),
+        ..Default::default()
+    });
+    let quotas = spotted_arms::quota::RepoQuotas::new(
+        &spotted_arms::config::Config::default(),
+        Arc::new(SystemClock),
+    );
+    let zones = ["a", "b", "c", "d", "e"].map(|z| format!("us-central1-{z}"));
+    let zones: Vec<&str> = zones.iter().map(String::as_str).collect();
+
+    let started = tokio::time::Instant::now();
+    spotted_arms::quota::reconcile(
+        compute.as_ref(),
+        &quotas,
+        &["test-project".to_string()],
+        &zones,
+        2,
+        Duration::from_secs(60),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        compute.max_lists_in_flight.load(Ordering::SeqCst),
+        2,
+        "never more than two zones listed at once"
+    );
+    // five one-second lists, two at a time
+    assert_eq!(started.elapsed(), Duration::from_secs(3));
+}
+
+#[tokio::test(start_paused = true)]
+async fn reconcile_gives_up_after_its_timeout() {
+    let compute = Arc::new(MockCompute {
+        list_delay: Duration::from_secs(30),
+        ..Default::default()
+    });
+    let quotas = spotted_arms::quota::RepoQuotas::new(
+        &spotted_arms::config::Config::default(),
+        Arc::new(SystemClock),
+    );
+
+    let result = spotted_arms::quota::reconcile(
+        compute.as_ref(),
+        &quotas,
+        &["test-project".to_string()],
+        &["us-central1-a"],
+        4,
+        Duration::from_secs(5),
+    )
+    .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn pooled_route_creates_and_deletes_a_batch() {
+    let compute = Arc::new(MockCompute::default());
+    let github = Arc::new(MockGithub::default());
+    let state = test_state(
+        compute.clone(),
+        github.clone(),
+        spotted_arms::config::Config {
+            routes: vec!["matrix=pool-template*3".parse().unwrap()],
+            ..Default::default()
+        },
+    );
+    let labels = ["self-hosted", "linux", "ARM64", "matrix"];
+
+    for event in [queued_event(&labels), completed_event(&labels)] {
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state.clone()),
+            axum_github_webhook_extract::GithubEvent(event),
+        )
+        .await;
+        assert!(res.is_ok());
+    }
+
+    let created = compute
+        .inserts
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|p| p.instance.as_ref().unwrap().name.clone().unwrap())
+        .collect::<Vec<_>>();
+    let deleted = compute
+        .deletes
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|p| p.instance.clone())
+        .collect::<Vec<_>>();
+
+    assert_eq!(created, vec!["gha-11-1-7", "gha-11-1-7-1", "gha-11-1-7-2"]);
+    assert_eq!(deleted, created);
+    assert_eq!(github.labels.lock().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn failed_batch_instance_rolls_back_its_siblings() {
+    use gcloud_sdk::google_rest_apis::compute_v1::operation::Status;
+
+    let compute = Arc::new(MockCompute {
+        insert_operation: common::operation(Status::Running),
+        ..Default::default()
+    });
+    compute.operations.lock().unwrap().extend([
+        Ok(common::operation(Status::Done)),
+        Ok(common::operation(Status::Done)),
+        Err(spotted_arms::compute::ComputeError::Other(
+            "backend unavailable".to_string(),
+        )),
+    ]);
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            routes: vec!["matrix=pool-template*3".parse().unwrap()],
+            max_instances_per_repo: Some(10),
+            ..Default::default()
+        },
+    );
+
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state.clone()),
+        axum_github_webhook_extract::GithubEvent(queued_event(&[
+            "self-hosted",
+            "linux",
+            "ARM64",
+            "matrix",
+        ])),
+    )
+    .await;
+    assert!(res.is_err());
+
+    let deleted = compute
+        .deletes
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|p| p.instance.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(deleted, ["gha-11-1-7", "gha-11-1-7-1"]);
+    assert_eq!(state.repo_quotas.in_use("owner/repo"), 0);
+    assert_eq!(state.instance_limit.in_use(), 0);
+}
+
+#[tokio::test]
+async fn permission_denied_insert_is_reported() {
+    use axum::response::IntoResponse;
+
+    let compute = Arc::new(MockCompute {
+        insert_error: std::sync::Mutex::new(Some(
+            spotted_arms::compute::ComputeError::PermissionDenied(
+                "Required 'compute.instances.create' permission".to_string(),
+            ),
+        )),
+        ..Default::default()
+    });
+    let state = test_state(compute.clone(), Arc::default(), Default::default());
+
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(queued_event(&["self-hosted", "linux", "ARM64"])),
+    )
+    .await
+    .into_response();
+
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body = String::from_utf8_lossy(&body);
+    assert!(body.contains("permission denied"), "{body}");
+    assert!(body.contains("compute.instances.create"), "{body}");
+}
+
+#[tokio::test]
+async fn transient_template_get_failure_retries_whole_create_once() {
+    use axum::response::IntoResponse;
+    use spotted_arms::instance::CreateStage;
+
+    for (create_retry_on, expected) in [
+        (vec![CreateStage::TemplateGet], StatusCode::OK),
+        (vec![], StatusCode::INTERNAL_SERVER_ERROR),
+    ] {
+        let compute = Arc::new(MockCompute {
+            template_error: std::sync::Mutex::new(Some(
+                spotted_arms::compute::ComputeError::Other("backend unavailable".to_string()),
+            )),
+            ..Default::default()
+        });
+        let github = Arc::new(MockGithub::default());
+        let state = test_state(
+            compute.clone(),
+            github.clone(),
+            spotted_arms::config::Config {
+                create_retry_on,
+                ..Default::default()
+            },
+        );
+
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state),
+            axum_github_webhook_extract::GithubEvent(queued_event(&[
+                "self-hosted",
+                "linux",
+                "ARM64",
+            ])),
+        )
+        .await;
+
+        assert_eq!(res.into_response().status(), expected);
+        assert_eq!(
+            compute.inserts.lock().unwrap().len(),
+            usize::from(expected == StatusCode::OK)
+        );
+        // the runner registered on the failed attempt is reused, not registered again
+        assert_eq!(github.labels.lock().unwrap().len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn existing_instance_check_skips_or_creates() {
+    use spotted_arms::config::ExistingInstance;
+
+    for exists in [true, false] {
+        let compute = Arc::new(MockCompute::default());
+        if exists {
+            compute.instances.lock().unwrap().push(
+                gcloud_sdk::google_rest_apis::compute_v1::Instance {
+                    name: Some("gha-11-1-7".to_string()),
+                    ..Default::default()
+                },
+            );
+        }
+        let github = Arc::new(MockGithub::default());
+        let state = test_state(
+            compute.clone(),
+            github.clone(),
+            spotted_arms::config::Config {
+                on_existing_instance: Some(ExistingInstance::Skip),
+                ..Default::default()
+            },
+        );
+
+        handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+        let inserts = usize::from(!exists);
+        assert_eq!(compute.inserts.lock().unwrap().len(), inserts);
+        // no runner is registered for a skipped instance
+        assert_eq!(github.labels.lock().unwrap().len(), inserts);
+    }
+}
+
+#[tokio::test]
+async fn received_at_is_annotated_when_enabled() {
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            annotate_received_at: true,
+            ..Default::default()
+        },
+    );
+
+    let before = std::time::SystemTime::now();
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    let inserts = compute.inserts.lock().unwrap();
+    let items = inserts[0]
+        .instance
+        .as_ref()
+        .and_then(|i| i.metadata.as_ref())
+        .and_then(|m| m.items.clone())
+        .unwrap_or_default();
+    let received_at = items
+        .iter()
+        .find(|i| i.key.as_deref() == Some(spotted_arms::instance::RECEIVED_AT_METADATA_KEY))
+        .and_then(|i| i.value.as_deref())
+        .expect("gh-received-at metadata");
+
+    let received_at = humantime::parse_rfc3339(received_at).unwrap();
+    assert!(received_at + std::time::Duration::from_millis(1) >= before);
+    assert!(received_at <= std::time::SystemTime::now());
+}
+
+#[tokio::test]
+async fn guest_attributes_are_enabled_when_configured() {
+    let compute = Arc::new(MockCompute {
+        template: gcloud_sdk::google_rest_apis::compute_v1::InstanceTemplate {
+            properties: Some(Box::new(
+                gcloud_sdk::google_rest_apis::compute_v1::InstanceProperties {
+                    metadata: Some(Box::new(
+                        gcloud_sdk::google_rest_apis::compute_v1::Metadata {
+                            items: Some(vec![
+                                gcloud_sdk::google_rest_apis::compute_v1::MetadataItemsInner {
+                                    key: Some("enable-guest-attributes".to_string()),
+                                    value: Some("FALSE".to_string()),
+                                },
+                            ]),
+                            ..Default::default()
+                        },
+                    )),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            enable_guest_attributes: true,
+            ..Default::default()
+        },
+    );
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    let inserts = compute.inserts.lock().unwrap();
+    let items = inserts[0]
+        .instance
+        .as_ref()
+        .and_then(|i| i.metadata.as_ref())
+        .and_then(|m| m.items.clone())
+        .unwrap_or_default();
+    let values = items
+        .iter()
+        .filter(|i| i.key.as_deref() == Some(spotted_arms::instance::GUEST_ATTRIBUTES_METADATA_KEY))
+        .filter_map(|i| i.value.as_deref())
+        .collect::<Vec<_>>();
+    assert_eq!(values, ["TRUE"]);
+    assert!(items.iter().any(|i| i.key.as_deref() == Some("JIT_CONFIG")));
+}
+
+#[tokio::test]
+async fn project_ssh_keys_are_blocked_when_configured() {
+    use gcloud_sdk::google_rest_apis::compute_v1;
+    use spotted_arms::instance::BLOCK_PROJECT_SSH_KEYS_METADATA_KEY;
+
+    for block_project_ssh_keys in [false, true] {
+        let compute = Arc::new(MockCompute {
+            template: compute_v1::InstanceTemplate {
+                properties: Some(Box::new(compute_v1::InstanceProperties {
+                    metadata: Some(Box::new(compute_v1::Metadata {
+                        items: Some(vec![compute_v1::MetadataItemsInner {
+                            key: Some(BLOCK_PROJECT_SSH_KEYS_METADATA_KEY.to_string()),
+                            value: Some("false".to_string()),
+                        }]),
+                        ..Default::default()
+                    })),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let state = test_state(
+            compute.clone(),
+            Arc::default(),
+            spotted_arms::config::Config {
+                block_project_ssh_keys,
+                ..Default::default()
+            },
+        );
+
+        handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+        let inserts = compute.inserts.lock().unwrap();
+        let values = inserts[0]
+            .instance
+            .as_ref()
+            .and_then(|i| i.metadata.as_ref())
+            .and_then(|m| m.items.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|i| i.key.as_deref() == Some(BLOCK_PROJECT_SSH_KEYS_METADATA_KEY))
+            .filter_map(|i| i.value)
+            .collect::<Vec<_>>();
+        let expected = if block_project_ssh_keys {
+            "true"
+        } else {
+            "false"
+        };
+        assert_eq!(values, [expected]);
+    }
+}
+
+#[tokio::test]
+async fn jit_endpoint_is_built_from_owner_and_name_for_html_urls() {
+    let github = Arc::new(MockGithub::default());
+    let state = test_state(Arc::default(), github.clone(), Default::default());
+
+    let mut payload = common::queued_payload(&["self-hosted", "linux", "ARM64"]);
+    payload["repository"]["url"] = "https://github.com/owner/repo".into();
+
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(serde_json::from_value(payload).unwrap()),
+    )
+    .await;
+
+    assert!(res.is_ok());
+    assert_eq!(
+        github
+            .repo_urls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|u| u.as_str())
+            .collect::<Vec<_>>(),
+        vec!["https://api.github.com/repos/owner/repo"]
+    );
+}
+
+#[tokio::test]
+async fn template_placeholders_resolve_per_job() {
+    let compute = Arc::new(MockCompute::default());
+    let mut state = test_state(compute.clone(), Arc::default(), Default::default());
+    state.instance_template = Arc::new("runner-{region}-{os}-{arch}".into());
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    assert_eq!(
+        inserted_templates(&compute),
+        vec![
+            "projects/test-project/regions/us-central1/instanceTemplates/runner-us-central1-linux-arm64"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn max_run_duration_sets_delete_scheduling_over_template() {
+    use gcloud_sdk::google_rest_apis::compute_v1;
+    use gcloud_sdk::google_rest_apis::compute_v1::scheduling::{
+        InstanceTerminationAction, ProvisioningModel,
+    };
+
+    let compute = Arc::new(MockCompute {
+        template: compute_v1::InstanceTemplate {
+            properties: Some(Box::new(compute_v1::InstanceProperties {
+                scheduling: Some(Box::new(compute_v1::Scheduling {
+                    provisioning_model: Some(ProvisioningModel::Spot),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            })),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            max_run_duration: Some(std::time::Duration::from_secs(2 * 3600)),
+            ..Default::default()
+        },
+    );
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    let inserts = compute.inserts.lock().unwrap();
+    let scheduling = inserts[0]
+        .instance
+        .as_ref()
+        .and_then(|i| i.scheduling.as_ref())
+        .expect("instance scheduling");
+    assert_eq!(
+        scheduling.instance_termination_action,
+        Some(Some(InstanceTerminationAction::Delete))
+    );
+    assert_eq!(scheduling.provisioning_model, Some(ProvisioningModel::Spot));
+    assert_eq!(
+        *compute.max_run_durations.lock().unwrap(),
+        [Some(std::time::Duration::from_secs(2 * 3600))]
+    );
+}
+
+#[tokio::test]
+async fn zero_max_run_duration_keeps_the_template_scheduling() {
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            max_run_duration: Some(std::time::Duration::ZERO),
+            ..Default::default()
+        },
+    );
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    let inserts = compute.inserts.lock().unwrap();
+    assert!(inserts[0].instance.as_ref().unwrap().scheduling.is_none());
+    assert_eq!(*compute.max_run_durations.lock().unwrap(), [None]);
+}
+
+#[tokio::test]
+async fn spot_label_launches_a_spot_instance() {
+    use gcloud_sdk::google_rest_apis::compute_v1::scheduling::{
+        InstanceTerminationAction, OnHostMaintenance, ProvisioningModel,
+    };
+
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(compute.clone(), Arc::default(), Default::default());
+
+    handle_queued(state.clone(), &["self-hosted", "linux", "ARM64"]).await;
+    handle_queued(state, &["self-hosted", "linux", "ARM64", "spot"]).await;
+
+    let inserts = compute.inserts.lock().unwrap();
+    let scheduling = |i: usize| {
+        inserts[i]
+            .instance
+            .as_ref()
+            .and_then(|instance| instance.scheduling.clone())
+    };
+    let standard = scheduling(0).expect("run duration scheduling");
+    assert_eq!(
+        standard.provisioning_model, None,
+        "non-spot jobs keep the template's provisioning model"
+    );
+    let spot = scheduling(1).expect("spot scheduling");
+    assert_eq!(spot.provisioning_model, Some(ProvisioningModel::Spot));
+    assert_eq!(
+        spot.instance_termination_action,
+        Some(Some(InstanceTerminationAction::Delete))
+    );
+    assert_eq!(spot.automatic_restart, Some(false));
+    assert_eq!(spot.on_host_maintenance, Some(OnHostMaintenance::Terminate));
+}
+
+#[tokio::test]
+async fn boot_disk_snapshot_replaces_the_template_boot_image() {
+    use gcloud_sdk::google_rest_apis::compute_v1;
+
+    let disk = |boot, image: &str| compute_v1::AttachedDisk {
+        boot: Some(boot),
+        initialize_params: Some(Box::new(compute_v1::AttachedDiskInitializeParams {
+            source_image: Some(image.to_string()),
+            disk_size_gb: Some("50".to_string()),
+            ..Default::default()
+        })),
+        ..Default::default()
+    };
+    let compute = Arc::new(MockCompute {
+        template: compute_v1::InstanceTemplate {
+            properties: Some(Box::new(compute_v1::InstanceProperties {
+                disks: Some(vec![disk(true, "ubuntu"), disk(false, "scratch")]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let config = spotted_arms::config::Config {
+        boot_disk_snapshot: Some("golden-1".to_string()),
+        ..Default::default()
+    };
+    let state = test_state(compute.clone(), Arc::default(), config);
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    let inserts = compute.inserts.lock().unwrap();
+    let disks = inserts[0].instance.as_ref().unwrap().disks.clone().unwrap();
+    let params = |i: usize| disks[i].initialize_params.clone().unwrap();
+    assert_eq!(
+        params(0).source_snapshot.as_deref(),
+        Some("global/snapshots/golden-1")
+    );
+    assert_eq!(params(0).source_image, None);
+    assert_eq!(params(0).disk_size_gb.as_deref(), Some("50"));
+    assert_eq!(params(1).source_image.as_deref(), Some("scratch"));
+    assert_eq!(params(1).source_snapshot, None);
+}
+
+#[tokio::test]
+async fn created_instances_are_labeled_with_job_metadata() {
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(compute.clone(), Arc::default(), Default::default());
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    let inserts = compute.inserts.lock().unwrap();
+    let labels = inserts[0]
+        .instance
+        .as_ref()
+        .unwrap()
+        .labels
+        .clone()
+        .unwrap();
+    let label = |key| labels.get(key).map(String::as_str);
+    assert_eq!(
+        label(spotted_arms::instance::CREATED_BY_LABEL),
+        Some(spotted_arms::instance::CREATED_BY)
+    );
+    assert_eq!(label("repository"), Some("owner-repo"));
+    assert_eq!(label("run_id"), Some("11"));
+    assert_eq!(label("job_id"), Some("7"));
+}
+
+#[tokio::test]
+async fn created_instances_carry_the_schema_version() {
+    use spotted_arms::instance::{SCHEMA_LABEL, SCHEMA_VERSION};
+
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(compute.clone(), Arc::default(), Default::default());
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    let inserts = compute.inserts.lock().unwrap();
+    let labels = inserts[0]
+        .instance
+        .as_ref()
+        .unwrap()
+        .labels
+        .as_ref()
+        .unwrap();
+    assert_eq!(
+        labels.get(SCHEMA_LABEL).map(String::as_str),
+        Some(SCHEMA_VERSION)
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn failed_job_lingers_before_its_runner_is_deleted() {
+    let compute = Arc::new(MockCompute::default());
+    let config = spotted_arms::config::Config {
+        linger_on_failure: Some(Duration::from_secs(30 * 60)),
+        ..Default::default()
+    };
+    let state = test_state(compute.clone(), Arc::default(), config);
+    let complete = |conclusion: &str| {
+        let mut payload =
+            common::workflow_job_payload("completed", &["self-hosted", "linux", "ARM64"]);
+        payload["workflow_job"]["conclusion"] = conclusion.into();
+        spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state.clone()),
+            axum_github_webhook_extract::GithubEvent(serde_json::from_value(payload).unwrap()),
+        )
+    };
+    let deletes = || compute.deletes.lock().unwrap().len();
+
+    let status = |res: Result<axum::response::Response, _>| res.unwrap().status();
+
+    assert_eq!(status(complete("success").await), StatusCode::OK);
+    assert_eq!(deletes(), 1, "successful jobs are deleted immediately");
+
+    assert_eq!(status(complete("failure").await), StatusCode::ACCEPTED);
+    tokio::time::sleep(Duration::from_secs(29 * 60)).await;
+    assert_eq!(deletes(), 1, "failed jobs linger");
+    tokio::time::sleep(Duration::from_secs(61)).await;
+    assert_eq!(deletes(), 2);
+
+    // shutdown deletes lingering runners without waiting out their delay
+    assert_eq!(status(complete("failure").await), StatusCode::ACCEPTED);
+    assert_eq!(state.lingering_deletes.pending(), 1);
+    state.lingering_deletes.flush().await;
+    assert_eq!(deletes(), 3);
+}
+
+#[tokio::test]
+async fn template_labels_are_merged_into_the_runner_registration() {
+    use gcloud_sdk::google_rest_apis::compute_v1;
+
+    let compute = Arc::new(MockCompute {
+        template: compute_v1::InstanceTemplate {
+            properties: Some(Box::new(compute_v1::InstanceProperties {
+                metadata: Some(Box::new(compute_v1::Metadata {
+                    items: Some(vec![compute_v1::MetadataItemsInner {
+                        key: Some("runner-labels".to_string()),
+                        value: Some("gpu, LINUX ,large,".to_string()),
+                    }]),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            })),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let github = Arc::new(MockGithub::default());
+    let config = spotted_arms::config::Config {
+        template_labels_key: Some("runner-labels".to_string()),
+        ..Default::default()
+    };
+    let state = test_state(compute, github.clone(), config);
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    assert_eq!(
+        *github.labels.lock().unwrap(),
+        vec![vec!["self-hosted", "linux", "ARM64", "gpu", "large"]]
+    );
+}
+
+#[tokio::test]
+async fn job_without_ids_is_rejected() {
+    use axum::response::IntoResponse;
+
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(compute.clone(), Arc::default(), Default::default());
+    let mut payload = common::queued_payload(&["self-hosted", "linux", "ARM64"]);
+    payload["workflow_job"]
+        .as_object_mut()
+        .unwrap()
+        .remove("run_id");
+
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(serde_json::from_value(payload).unwrap()),
+    )
+    .await;
+
+    assert_eq!(res.into_response().status(), StatusCode::BAD_REQUEST);
+    assert!(compute.inserts.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn org_scoped_delivery_provisions_for_its_repository() {
+    let org_payload: serde_json::Value =
+        serde_json::from_str(include_str!("fixtures/org-queued-payload.json")).unwrap();
+
+    // a repository trimmed of its owner and full name still resolves through the organization
+    let mut trimmed = org_payload.clone();
+    let repository = trimmed["repository"].as_object_mut().unwrap();
+    repository.remove("full_name");
+    repository.remove("owner");
+
+    for payload in [org_payload, trimmed] {
+        let compute = Arc::new(MockCompute::default());
+        let github = Arc::new(MockGithub::default());
+        let state = test_state(compute.clone(), github.clone(), Default::default());
+
+        let payload = payload.to_string();
+        let mut de = Deserializer::from_str(&payload);
+        let body: spotted_arms::webhook::WorkflowJobWebhook =
+            serde_path_to_error::deserialize(&mut de).unwrap();
+        assert_eq!(body.organization.as_ref().unwrap().login, "acme");
+
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state),
+            axum_github_webhook_extract::GithubEvent(body),
+        )
+        .await;
+        assert!(res.is_ok());
+
+        assert_eq!(
+            github.repo_urls.lock().unwrap()[0].as_str(),
+            "https://api.github.com/repos/acme/widgets"
+        );
+        let inserts = compute.inserts.lock().unwrap();
+        let labels = inserts[0]
+            .instance
+            .as_ref()
+            .and_then(|i| i.labels.clone())
+            .unwrap_or_default();
+        assert_eq!(
+            labels.get(spotted_arms::instance::REPOSITORY_LABEL),
+            Some(&spotted_arms::utils::repo_label_value("acme/widgets"))
+        );
+    }
+}
+
+#[tokio::test]
+async fn runner_name_follows_its_own_scheme() {
+    let compute = Arc::new(MockCompute::default());
+    let github = Arc::new(MockGithub::default());
+    let state = test_state(
+        compute.clone(),
+        github.clone(),
+        spotted_arms::config::Config {
+            runner_name: Some("{repo}-{hostname}".to_string()),
+            ..Default::default()
+        },
+    );
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    let inserts = compute.inserts.lock().unwrap();
+    let instance_name = inserts[0]
+        .instance
+        .as_ref()
+        .and_then(|i| i.name.clone())
+        .unwrap();
+    let zone = &inserts[0].zone;
+    assert_eq!(
+        *github.runner_names.lock().unwrap(),
+        vec![format!(
+            "owner-repo-{instance_name}.{zone}.c.test-project.internal"
+        )]
+    );
+}
+
+#[tokio::test]
+async fn metadata_transform_runs_before_insert() {
+    use gcloud_sdk::google_rest_apis::compute_v1;
+
+    let compute = Arc::new(MockCompute::default());
+    let mut state = test_state(compute.clone(), Arc::default(), Default::default());
+    state.metadata_transform = Arc::new(
+        |instance_name: &str,
+         event: &spotted_arms::webhook::WorkflowJobWebhook,
+         metadata: &mut Vec<compute_v1::MetadataItemsInner>| {
+            metadata.push(compute_v1::MetadataItemsInner {
+                key: Some("runner-owner".to_string()),
+                value: Some(format!(
+                    "{}:{instance_name}",
+                    event.repository.full_name.as_deref().unwrap_or_default()
+                )),
+            });
+        },
+    );
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    let inserts = compute.inserts.lock().unwrap();
+    let instance = inserts[0].instance.as_ref().unwrap();
+    let items = instance
+        .metadata
+        .as_ref()
+        .and_then(|m| m.items.clone())
+        .unwrap_or_default();
+    let keys = items
+        .iter()
+        .filter_map(|i| i.key.as_deref())
+        .collect::<Vec<_>>();
+    assert_eq!(keys, vec!["JIT_CONFIG", "runner-owner"]);
+    assert_eq!(
+        items[1].value,
+        Some(format!("owner/repo:{}", instance.name.as_deref().unwrap()))
+    );
+}
+
+#[tokio::test]
+async fn non_string_labels_are_skipped_not_coerced() {
+    let invalid =
+        || spotted_arms::metrics::registry().counter("spotted_arms_invalid_labels_total", &[]);
+    let before = invalid();
+
+    let compute = Arc::new(MockCompute::default());
+    let github = Arc::new(MockGithub::default());
+    let state = test_state(compute.clone(), github.clone(), Default::default());
+
+    let mut payload = common::queued_payload(&[]);
+    payload["workflow_job"]["labels"] =
+        serde_json::json!(["self-hosted", {"name": "gpu"}, 42, "linux", "ARM64"]);
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(serde_json::from_value(payload).unwrap()),
+    )
+    .await;
+
+    assert!(res.is_ok());
+    assert_eq!(compute.inserts.lock().unwrap().len(), 1);
+    let mut labels = github.labels.lock().unwrap()[0].clone();
+    labels.sort();
+    assert_eq!(labels, vec!["ARM64", "linux", "self-hosted"]);
+    assert!(invalid() >= before + 2);
+}
+
+fn deletions(result: &str) -> u64 {
+    spotted_arms::metrics::registry().counter(
+        "spotted_arms_instance_deletions_total",
+        &[("result", result)],
+    )
+}
+
+#[tokio::test(start_paused = true)]
+async fn confirmed_delete_waits_for_its_operation() {
+    use gcloud_sdk::google_rest_apis::compute_v1::operation::Status;
+
+    let compute = Arc::new(MockCompute {
+        delete_operation: common::operation(Status::Pending),
+        ..Default::default()
+    });
+    compute.operations.lock().unwrap().extend([
+        Ok(common::operation(Status::Running)),
+        Ok(common::operation(Status::Done)),
+    ]);
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            confirm_deletes: true,
+            ..Default::default()
+        },
+    );
+    let confirmed = deletions("confirmed");
+
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(completed_event(&[
+            "self-hosted",
+            "linux",
+            "ARM64",
+        ])),
+    )
+    .await;
+
+    assert!(res.is_ok());
+    assert_eq!(compute.operation_polls.lock().unwrap().len(), 2);
+    assert_eq!(deletions("confirmed"), confirmed + 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn confirmed_delete_still_running_at_timeout_counts_as_pending() {
+    use gcloud_sdk::google_rest_apis::compute_v1::operation::Status;
+
+    let compute = Arc::new(MockCompute {
+        delete_operation: common::operation(Status::Pending),
+        ..Default::default()
+    });
+    compute
+        .operations
+        .lock()
+        .unwrap()
+        .extend((0..100).map(|_| Ok(common::operation(Status::Running))));
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            confirm_deletes: true,
+            operation_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        },
+    );
+    let pending = deletions("pending");
+
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(completed_event(&[
+            "self-hosted",
+            "linux",
+            "ARM64",
+        ])),
+    )
+    .await;
+
+    assert!(res.is_ok());
+    assert_eq!(compute.deletes.lock().unwrap().len(), 1);
+    assert_eq!(deletions("pending"), pending + 1);
+}
+
+#[tokio::test]
+async fn completed_event_without_required_labels_skips_compute() {
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(compute.clone(), Arc::default(), Default::default());
+
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(common::completed_event(&[
+            "self-hosted",
+            "linux",
+            "X64",
+        ])),
+    )
+    .await;
+
+    assert!(res.is_ok());
+    assert!(compute.deletes.lock().unwrap().is_empty());
+    assert!(compute.inserts.lock().unwrap().is_empty());
+    assert!(compute.operation_polls.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn configured_required_labels_replace_the_defaults() {
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            required_labels: Some(vec!["self-hosted".to_string(), "gpu".to_string()]),
+            ..Default::default()
+        },
+    );
+
+    handle_queued(state.clone(), &["self-hosted", "gpu"]).await;
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    assert_eq!(compute.inserts.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn zones_override_replaces_built_in_zones() {
+    let compute = Arc::new(MockCompute::default());
+    let mut state = test_state(compute.clone(), Arc::default(), Default::default());
+    state.zones = Arc::new(vec!["us-central1-c".to_string()]);
+
+    for job_id in 1..=5 {
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state.clone()),
+            axum_github_webhook_extract::GithubEvent(
+                serde_json::from_value(common::repo_workflow_job_payload(
+                    "queued",
+                    "owner/repo",
+                    job_id,
+                    &["self-hosted", "linux", "ARM64"],
+                ))
+                .unwrap(),
+            ),
+        )
+        .await;
+        assert!(res.is_ok());
+    }
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(common::completed_event(&[
+            "self-hosted",
+            "linux",
+            "ARM64",
+        ])),
+    )
+    .await;
+    assert!(res.is_ok());
+
+    let inserts = compute.inserts.lock().unwrap();
+    assert_eq!(inserts.len(), 5);
+    assert!(inserts.iter().all(|i| i.zone == "us-central1-c"));
+    assert_eq!(compute.deletes.lock().unwrap()[0].zone, "us-central1-c");
+}
+
+#[tokio::test]
+async fn completed_span_links_to_queued_trace() {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    let guard = tracing::subscriber::set_default(subscriber);
+
+    let labels = ["self-hosted", "linux", "ARM64"];
+    let state = test_state(Arc::default(), Arc::default(), Default::default());
+    handle_queued(state.clone(), &labels).await;
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(common::completed_event(&labels)),
+    )
+    .await;
+    assert!(res.is_ok());
+
+    drop(guard);
+    provider.force_flush().unwrap();
+    let spans = exporter.get_finished_spans().unwrap();
+    let events = spans
+        .iter()
+        .filter(|s| s.name == "workflow_job_event")
+        .collect::<Vec<_>>();
+    assert_eq!(events.len(), 2);
+
+    let (queued, completed) = (events[0], events[1]);
+    assert_ne!(
+        queued.span_context.trace_id(),
+        completed.span_context.trace_id()
+    );
+    assert_eq!(completed.links.links.len(), 1);
+    assert_eq!(completed.links.links[0].span_context, queued.span_context);
+    assert!(queued.links.links.is_empty());
+}
+
+#[tokio::test]
+async fn exhausted_zone_falls_back_to_the_next() {
+    use spotted_arms::compute::ComputeError;
+
+    let compute = Arc::new(MockCompute::default());
+    *compute.insert_error.lock().unwrap() = Some(ComputeError::ResourceExhausted(
+        "ZONE_RESOURCE_POOL_EXHAUSTED".to_string(),
+    ));
+    let mut state = test_state(compute.clone(), Arc::default(), Default::default());
+    state.zones = Arc::new(vec![
+        "us-central1-a".to_string(),
+        "us-central1-b".to_string(),
+        "us-central1-c".to_string(),
+    ]);
+
+    for event in [
+        queued_event(&["self-hosted", "linux", "ARM64"]),
+        completed_event(&["self-hosted", "linux", "ARM64"]),
+    ] {
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state.clone()),
+            axum_github_webhook_extract::GithubEvent(event),
+        )
+        .await;
+        assert!(res.is_ok());
+    }
+
+    // the deterministic zone first, then the first of the others
+    let inserts = compute.inserts.lock().unwrap();
+    assert_eq!(inserts.len(), 2);
+    let fallback = state
+        .zones
+        .iter()
+        .find(|&zone| *zone != inserts[0].zone)
+        .unwrap();
+    assert_eq!(&inserts[1].zone, fallback);
+}
+
+#[tokio::test]
+async fn exhaustion_in_the_last_zone_fails_the_job() {
+    use axum::response::IntoResponse;
+    use spotted_arms::compute::ComputeError;
+
+    let compute = Arc::new(MockCompute::default());
+    *compute.insert_error.lock().unwrap() =
+        Some(ComputeError::QuotaExceeded("QUOTA_EXCEEDED".to_string()));
+    let mut state = test_state(compute.clone(), Arc::default(), Default::default());
+    state.zones = Arc::new(vec!["us-central1-a".to_string()]);
+
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(queued_event(&["self-hosted", "linux", "ARM64"])),
+    )
+    .await;
+
+    assert_eq!(
+        res.into_response().status(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(compute.inserts.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn compute_failures_map_to_http_statuses() {
+    use axum::response::IntoResponse;
+    use spotted_arms::compute::ComputeError;
+    use std::time::Duration;
+
+    let cases = [
+        (
+            ComputeError::RateLimited {
+                retry_after: Some(Duration::from_millis(1500)),
+            },
+            StatusCode::TOO_MANY_REQUESTS,
+            Some("2"),
+        ),
+        (
+            ComputeError::RateLimited { retry_after: None },
+            StatusCode::TOO_MANY_REQUESTS,
+            None,
+        ),
+        (
+            ComputeError::PermissionDenied("denied".to_string()),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            None,
+        ),
+        (
+            ComputeError::Other("boom".to_string()),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            None,
+        ),
+    ];
+
+    for (error, status, retry_after) in cases {
+        let compute = Arc::new(MockCompute::default());
+        *compute.insert_error.lock().unwrap() = Some(error);
+        let state = test_state(compute, Arc::default(), Default::default());
+
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state),
+            axum_github_webhook_extract::GithubEvent(queued_event(&[
+                "self-hosted",
+                "linux",
+                "ARM64",
+            ])),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), status);
+        assert_eq!(
+            res.headers()
+                .get(axum::http::header::RETRY_AFTER)
+                .map(|v| v.to_str().unwrap()),
+            retry_after
+        );
+    }
+}
+
+#[tokio::test]
+async fn configured_error_status_replaces_the_quota_default() {
+    use axum::response::IntoResponse;
+    use spotted_arms::compute::ComputeError;
+
+    for (error_statuses, status) in [
+        (vec![], StatusCode::SERVICE_UNAVAILABLE),
+        (vec!["quota-exceeded=200".parse().unwrap()], StatusCode::OK),
+    ] {
+        let compute = Arc::new(MockCompute::default());
+        *compute.insert_error.lock().unwrap() =
+            Some(ComputeError::QuotaExceeded("CPUS".to_string()));
+        let mut state = test_state(
+            compute,
+            Arc::default(),
+            spotted_arms::config::Config {
+                error_statuses,
+                ..Default::default()
+            },
+        );
+        // a single zone, so there is nowhere to fall back to
+        state.zones = Arc::new(vec!["us-central1-a".to_string()]);
+
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state),
+            axum_github_webhook_extract::GithubEvent(queued_event(&[
+                "self-hosted",
+                "linux",
+                "ARM64",
+            ])),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), status);
+    }
+}
+
+#[tokio::test]
+async fn ignored_event_records_its_decision() {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    let guard = tracing::subscriber::set_default(subscriber);
+
+    let state = test_state(Arc::default(), Arc::default(), Default::default());
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(queued_event(&["ubuntu-latest"])),
+    )
+    .await;
+    assert!(res.is_ok());
+
+    drop(guard);
+    provider.force_flush().unwrap();
+    let spans = exporter.get_finished_spans().unwrap();
+    let root = spans
+        .iter()
+        .find(|s| s.name == "handle_workflow_job_event")
+        .expect("root span");
+    let attribute = |key: &str| {
+        root.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.as_str().into_owned())
+    };
+    assert_eq!(attribute("decision").as_deref(), Some("ignored"));
+    assert_eq!(root.status, opentelemetry::trace::Status::Ok);
+}
+
+#[tokio::test]
+async fn large_label_sets_are_truncated_on_the_span() {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    let guard = tracing::subscriber::set_default(subscriber);
+
+    let state = test_state(
+        Arc::default(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            max_span_labels: Some(3),
+            ..Default::default()
+        },
+    );
+    let labels = (0..50).map(|i| format!("label-{i:02}")).collect::<Vec<_>>();
+    let labels = labels.iter().map(String::as_str).collect::<Vec<_>>();
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(queued_event(&labels)),
+    )
+    .await;
+    assert!(res.is_ok());
+
+    drop(guard);
+    provider.force_flush().unwrap();
+    let spans = exporter.get_finished_spans().unwrap();
+    let root = spans
+        .iter()
+        .find(|s| s.name == "handle_workflow_job_event")
+        .expect("root span");
+    let recorded = root
+        .attributes
+        .iter()
+        .find(|kv| kv.key.as_str() == "labels")
+        .map(|kv| kv.value.as_str().into_owned());
+    assert_eq!(
+        recorded.as_deref(),
+        Some(r#"["label-00", "label-01", "label-02"] +47 more"#)
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn insert_waits_for_its_operation() {
+    use axum::response::IntoResponse;
+    use gcloud_sdk::google_rest_apis::compute_v1;
+    use gcloud_sdk::google_rest_apis::compute_v1::operation::Status;
+
+    let failed = compute_v1::Operation {
+        error: Some(Box::new(compute_v1::OperationError {
+            errors: Some(vec![
+                compute_v1::ManagedInstanceLastAttemptErrorsErrorsInner {
+                    code: Some("RESOURCE_NOT_READY".to_string()),
+                    message: Some("disk not ready".to_string()),
+                    ..Default::default()
+                },
+            ]),
+        })),
+        ..common::operation(Status::Done)
+    };
+
+    for (polled, status) in [
+        (common::operation(Status::Done), StatusCode::OK),
+        (failed, StatusCode::INTERNAL_SERVER_ERROR),
+    ] {
+        let compute = Arc::new(MockCompute {
+            insert_operation: common::operation(Status::Running),
+            ..Default::default()
+        });
+        compute.operations.lock().unwrap().push_back(Ok(polled));
+        let state = test_state(compute.clone(), Arc::default(), Default::default());
+
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state),
+            axum_github_webhook_extract::GithubEvent(queued_event(&[
+                "self-hosted",
+                "linux",
+                "ARM64",
+            ])),
+        )
+        .await
+        .into_response();
+
+        assert_eq!(res.status(), status);
+        let polls = compute.operation_polls.lock().unwrap();
+        assert_eq!(polls.len(), 1);
+        assert_eq!(polls[0].operation, "operation-1");
+    }
+}
+
+#[tokio::test]
+async fn failed_insert_deregisters_the_runner() {
+    use spotted_arms::compute::ComputeError;
+
+    for fail in [true, false] {
+        let compute = Arc::new(MockCompute::default());
+        if fail {
+            *compute.insert_error.lock().unwrap() = Some(ComputeError::Other("boom".to_string()));
+        }
+        let github = Arc::new(MockGithub::default());
+        let state = test_state(compute, github.clone(), Default::default());
+
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state),
+            axum_github_webhook_extract::GithubEvent(queued_event(&[
+                "self-hosted",
+                "linux",
+                "ARM64",
+            ])),
+        )
+        .await;
+
+        assert_eq!(res.is_err(), fail);
+        let deregistered = if fail { vec![1] } else { vec![] };
+        assert_eq!(*github.deleted_runners.lock().unwrap(), deregistered);
+    }
+}
+
+/// Monday 2024-01-01 at `hour`:00 UTC, plus `days`
+fn monday(days: u64, hour: u64) -> std::time::SystemTime {
+    std::time::UNIX_EPOCH + Duration::from_secs(1_704_067_200 + days * 86_400 + hour * 3_600)
+}
+
+fn scheduled_state(
+    compute: Arc<MockCompute>,
+    at: std::time::SystemTime,
+) -> spotted_arms::server::AppState {
+    let mut state = test_state(
+        compute,
+        Arc::default(),
+        spotted_arms::config::Config {
+            provision_schedule: vec!["Mon-Fri 08:00-18:00".parse().unwrap()],
+            ..Default::default()
+        },
+    );
+    state.clock = Arc::new(FakeClock::new(at));
+    state
+}
+
+#[tokio::test]
+async fn queued_job_inside_the_schedule_is_provisioned() {
+    let compute = Arc::new(MockCompute::default());
+    let state = scheduled_state(compute.clone(), monday(0, 10));
+
+    handle_queued(state, &["self-hosted", "linux", "ARM64"]).await;
+
+    assert_eq!(compute.inserts.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn queued_job_outside_the_schedule_is_ignored_but_completion_cleans_up() {
+    let compute = Arc::new(MockCompute::default());
+    let state = scheduled_state(compute.clone(), monday(5, 10));
+    let labels = ["self-hosted", "linux", "ARM64"];
+    let registry = spotted_arms::metrics::registry();
+    let ignored = registry.counter(
+        "spotted_arms_ignored_events_total",
+        &[("reason", "outside_schedule")],
+    );
+
+    handle_queued(state.clone(), &labels).await;
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(completed_event(&labels)),
+    )
+    .await;
+
+    assert!(res.is_ok());
+    assert!(compute.inserts.lock().unwrap().is_empty());
+    assert_eq!(compute.deletes.lock().unwrap().len(), 1);
+    assert_eq!(
+        registry.counter(
+            "spotted_arms_ignored_events_total",
+            &[("reason", "outside_schedule")]
+        ),
+        ignored + 1
+    );
+}
+
+#[tokio::test]
+async fn redelivered_webhook_is_processed_once() {
+    let compute = Arc::new(MockCompute::default());
+    *compute.insert_error.lock().unwrap() = Some(spotted_arms::compute::ComputeError::Other(
+        "backend unavailable".to_string(),
+    ));
+    let state = test_state(compute.clone(), Arc::default(), Default::default());
+    let headers = workflow_job_headers();
+    let deliver = || {
+        spotted_arms::webhook::handle_workflow_job_event(
+            headers.clone(),
+            axum::extract::State(state.clone()),
+            axum_github_webhook_extract::GithubEvent(queued_event(&[
+                "self-hosted",
+                "linux",
+                "ARM64",
+            ])),
+        )
+    };
+
+    // a failed delivery is forgotten so GitHub's redelivery gets another attempt
+    assert!(deliver().await.is_err());
+    assert!(deliver().await.is_ok());
+    assert_eq!(compute.inserts.lock().unwrap().len(), 2);
+
+    // once handled, the same delivery id is acknowledged without provisioning again
+    assert!(deliver().await.is_ok());
+    assert_eq!(compute.inserts.lock().unwrap().len(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn redelivery_while_the_first_attempt_runs_is_retried_later() {
+    use axum::response::IntoResponse;
+
+    let compute = Arc::new(MockCompute {
+        insert_delay: Duration::from_secs(5),
+        ..Default::default()
+    });
+    *compute.insert_error.lock().unwrap() = Some(spotted_arms::compute::ComputeError::Other(
+        "backend unavailable".to_string(),
+    ));
+    let state = test_state(compute.clone(), Arc::default(), Default::default());
+    let headers = workflow_job_headers();
+    let deliver = || {
+        spotted_arms::webhook::handle_workflow_job_event(
+            headers.clone(),
+            axum::extract::State(state.clone()),
+            axum_github_webhook_extract::GithubEvent(queued_event(&[
+                "self-hosted",
+                "linux",
+                "ARM64",
+            ])),
+        )
+    };
+
+    let first = tokio::spawn(deliver());
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // not acknowledged, since the first attempt may still fail
+    let res = deliver().await.into_response();
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(res.headers().contains_key(axum::http::header::RETRY_AFTER));
+
+    assert!(first.await.unwrap().is_err());
+    assert!(deliver().await.is_ok());
+    assert_eq!(compute.inserts.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn invalid_instance_labels_are_rejected_before_insert() {
+    use axum::response::IntoResponse;
+    use gcloud_sdk::google_rest_apis::compute_v1;
+
+    let compute = Arc::new(MockCompute {
+        template: compute_v1::InstanceTemplate {
+            properties: Some(Box::new(compute_v1::InstanceProperties {
+                labels: Some([("Team".to_string(), "infra".to_string())].into()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let state = test_state(compute.clone(), Arc::default(), Default::default());
+
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(queued_event(&["self-hosted", "linux", "ARM64"])),
+    )
+    .await;
+
+    assert_eq!(res.into_response().status(), StatusCode::BAD_REQUEST);
+    assert!(compute.inserts.lock().unwrap().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn completion_racing_creation_waits_for_the_insert() {
+    let compute = Arc::new(MockCompute {
+        insert_delay: Duration::from_secs(10),
+        ..Default::default()
+    });
+    let state = test_state(compute.clone(), Arc::default(), Default::default());
+    let labels = ["self-hosted", "linux", "ARM64"];
+    let deliver = |event| {
+        spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state.clone()),
+            axum_github_webhook_extract::GithubEvent(event),
+        )
+    };
+
+    let queued = tokio::spawn(deliver(queued_event(&labels)));
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let completed = tokio::spawn(deliver(completed_event(&labels)));
+
+    // an unrelated job's runner is deleted without waiting on the insert
+    let other = serde_json::from_value(repo_workflow_job_payload(
+        "completed",
+        "owner/repo",
+        8,
+        &labels,
+    ))
+    .unwrap();
+    assert!(deliver(other).await.is_ok());
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let deleted = |compute: &MockCompute| {
+        compute
+            .deletes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|d| d.instance.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(deleted(&compute), vec!["gha-11-1-8"]);
+
+    assert!(queued.await.unwrap().is_ok());
+    assert!(completed.await.unwrap().is_ok());
+    assert_eq!(deleted(&compute), vec!["gha-11-1-8", "gha-11-1-7"]);
+}
+
+#[tokio::test]
+async fn job_cancelled_before_start_skips_the_delete() {
+    use spotted_arms::config::CancelledBeforeStart;
+
+    let registry = spotted_arms::metrics::registry();
+    let skipped = || {
+        registry.counter(
+            "spotted_arms_ignored_events_total",
+            &[("reason", "cancelled_before_start")],
+        )
+    };
+    let mut payload = workflow_job_payload("completed", &["self-hosted", "linux", "ARM64"]);
+    payload["workflow_job"]["conclusion"] = "cancelled".into();
+    payload["workflow_job"]["runner_id"] = serde_json::Value::Null;
+    payload["workflow_job"]["runner_name"] = serde_json::Value::Null;
+
+    for (policy, deletes) in [
+        (CancelledBeforeStart::Delete, 1),
+        (CancelledBeforeStart::Skip, 0),
+    ] {
+        let compute = Arc::new(MockCompute::default());
+        let state = test_state(
+            compute.clone(),
+            Arc::default(),
+            spotted_arms::config::Config {
+                on_cancelled_before_start: policy,
+                ..Default::default()
+            },
+        );
+        let before = skipped();
+
+        let res = spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state),
+            axum_github_webhook_extract::GithubEvent(
+                serde_json::from_value(payload.clone()).unwrap(),
+            ),
+        )
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(compute.deletes.lock().unwrap().len(), deletes, "{policy:?}");
+        assert_eq!(skipped(), before + (1 - deletes) as u64, "{policy:?}");
+    }
+}
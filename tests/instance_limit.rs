@@ -0,0 +1,52 @@
+mod common;
+
+use std::sync::Arc;
+
+use axum::http::{StatusCode, header};
+use axum::response::IntoResponse;
+use common::{MockCompute, repo_workflow_job_payload, test_state, workflow_job_headers};
+
+#[tokio::test(start_paused = true)]
+async fn jobs_past_the_instance_limit_are_turned_away_until_a_runner_is_deleted() {
+    let compute = Arc::new(MockCompute::default());
+    let state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            max_instances: Some(1),
+            ..Default::default()
+        },
+    );
+    let deliver = |action: &str, job_id: i64| {
+        let payload = repo_workflow_job_payload(
+            action,
+            "owner/repo",
+            job_id,
+            &["self-hosted", "linux", "ARM64"],
+        );
+        spotted_arms::webhook::handle_workflow_job_event(
+            workflow_job_headers(),
+            axum::extract::State(state.clone()),
+            axum_github_webhook_extract::GithubEvent(serde_json::from_value(payload).unwrap()),
+        )
+    };
+    let in_use = || spotted_arms::metrics::registry().gauge("spotted_arms_instances_in_use", &[]);
+
+    assert!(deliver("queued", 1).await.is_ok());
+    assert_eq!(state.instance_limit.in_use(), 1);
+    assert_eq!(in_use(), 1);
+
+    let rejected = deliver("queued", 2).await.into_response();
+    assert_eq!(rejected.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(rejected.headers().contains_key(header::RETRY_AFTER));
+    assert_eq!(compute.inserts.lock().unwrap().len(), 1);
+
+    // a redelivery of the job already holding the permit isn't turned away
+    assert!(deliver("queued", 1).await.is_ok());
+
+    assert!(deliver("completed", 1).await.is_ok());
+    assert_eq!(state.instance_limit.in_use(), 0);
+    assert!(deliver("queued", 2).await.is_ok());
+    assert_eq!(compute.inserts.lock().unwrap().len(), 3);
+    assert_eq!(in_use(), 1);
+}
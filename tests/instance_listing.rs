@@ -0,0 +1,123 @@
+mod common;
+
+use std::time::Duration;
+
+use common::MockCompute;
+use gcloud_sdk::google_rest_apis::compute_v1;
+use spotted_arms::compute::{ComputeApi, ComputeError};
+
+fn page(names: &[&str], next_page_token: Option<&str>) -> compute_v1::InstanceList {
+    compute_v1::InstanceList {
+        items: Some(
+            names
+                .iter()
+                .map(|name| compute_v1::Instance {
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                })
+                .collect(),
+        ),
+        next_page_token: next_page_token.map(str::to_string),
+        ..Default::default()
+    }
+}
+
+fn names(instances: &[compute_v1::Instance]) -> Vec<&str> {
+    instances.iter().filter_map(|i| i.name.as_deref()).collect()
+}
+
+#[tokio::test(start_paused = true)]
+async fn failed_later_page_is_retried_keeping_earlier_pages() {
+    let compute = MockCompute::default();
+    compute.list_pages.lock().unwrap().extend([
+        Ok(page(&["gha-1", "gha-2"], Some("page-2"))),
+        Err(ComputeError::Unavailable(
+            "503 Service Unavailable".to_string(),
+        )),
+        Ok(page(&["gha-3"], None)),
+    ]);
+
+    let started = tokio::time::Instant::now();
+    let instances = compute
+        .list_instances("test-project", "us-central1-a", "labels.repository:*")
+        .await
+        .unwrap();
+
+    assert_eq!(names(&instances), ["gha-1", "gha-2", "gha-3"]);
+    let lists = compute.lists.lock().unwrap();
+    let tokens = lists
+        .iter()
+        .map(|l| l.page_token.as_deref())
+        .collect::<Vec<_>>();
+    assert_eq!(tokens, [None, Some("page-2"), Some("page-2")]);
+    assert_eq!(started.elapsed(), Duration::from_secs(1));
+}
+
+#[tokio::test(start_paused = true)]
+async fn page_failing_past_its_retries_fails_the_listing() {
+    let compute = MockCompute::default();
+    compute.list_pages.lock().unwrap().extend(
+        std::iter::once(Ok(page(&["gha-1"], Some("page-2")))).chain((0..3).map(|_| {
+            Err(ComputeError::Unavailable(
+                "503 Service Unavailable".to_string(),
+            ))
+        })),
+    );
+
+    let err = compute
+        .list_instances("test-project", "us-central1-a", "labels.repository:*")
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, ComputeError::Unavailable(_)), "{err:?}");
+    assert_eq!(compute.lists.lock().unwrap().len(), 4);
+}
+
+#[tokio::test(start_paused = true)]
+async fn permanent_page_errors_are_not_retried() {
+    for permanent in [
+        ComputeError::PermissionDenied("compute.instances.list".to_string()),
+        ComputeError::Other("400 Bad Request: invalid filter".to_string()),
+    ] {
+        let compute = MockCompute::default();
+        compute
+            .list_pages
+            .lock()
+            .unwrap()
+            .extend([Ok(page(&["gha-1"], Some("page-2"))), Err(permanent)]);
+
+        let err = compute
+            .list_instances("test-project", "us-central1-a", "labels.repository:*")
+            .await
+            .unwrap_err();
+
+        assert!(
+            matches!(
+                err,
+                ComputeError::PermissionDenied(_) | ComputeError::Other(_)
+            ),
+            "{err:?}"
+        );
+        assert_eq!(compute.lists.lock().unwrap().len(), 2);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn rate_limited_page_waits_for_its_retry_after() {
+    let compute = MockCompute::default();
+    compute.list_pages.lock().unwrap().extend([
+        Err(ComputeError::RateLimited {
+            retry_after: Some(Duration::from_secs(7)),
+        }),
+        Ok(page(&["gha-1"], None)),
+    ]);
+
+    let started = tokio::time::Instant::now();
+    let instances = compute
+        .list_instances("test-project", "us-central1-a", "labels.repository:*")
+        .await
+        .unwrap();
+
+    assert_eq!(names(&instances), ["gha-1"]);
+    assert_eq!(started.elapsed(), Duration::from_secs(7));
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::Json;
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::{delete, get, post};
+use common::{MockCompute, queued_event, test_state, workflow_job_headers};
+use spotted_arms::github::{GithubApi, GithubClient, GithubError};
+
+/// Id of the runner already registered as `gha-11-1-7`
+const STALE_RUNNER_ID: u64 = 99;
+
+/// Serves a JIT config endpoint answering its first request with `422` and `message`, and
+/// later ones with a registration, alongside runner listing and deletion; returns the API
+/// base URL and the ids of deleted runners
+async fn mock_github(message: &'static str) -> (reqwest::Url, Arc<Mutex<Vec<u64>>>) {
+    let rejected = Arc::new(AtomicBool::new(false));
+    let deleted = Arc::new(Mutex::new(Vec::new()));
+    let deletions = deleted.clone();
+    let app = axum::Router::new()
+        .route(
+            "/repos/owner/repo/actions/runners/generate-jitconfig",
+            post(move || async move {
+                if rejected.swap(true, Ordering::SeqCst) {
+                    return (
+                        StatusCode::CREATED,
+                        Json(serde_json::json!({
+                            "runner": {"id": 100, "name": "gha-11-1-7"},
+                            "encoded_jit_config": "encoded",
+                        })),
+                    );
+                }
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(serde_json::json!({
+                        "message": message,
+                        "documentation_url": "https://docs.github.com/rest/actions/self-hosted-runners",
+                    })),
+                )
+            }),
+        )
+        .route(
+            "/repos/owner/repo/actions/runners",
+            get(|Query(query): Query<HashMap<String, String>>| async move {
+                let runners = match query.get("name").map(String::as_str) {
+                    Some("gha-11-1-7") => {
+                        serde_json::json!([{"id": STALE_RUNNER_ID, "name": "gha-11-1-7"}])
+                    }
+                    _ => serde_json::json!([]),
+                };
+                Json(serde_json::json!({"total_count": 1, "runners": runners}))
+            }),
+        )
+        .route(
+            "/repos/owner/repo/actions/runners/{runner_id}",
+            delete(move |Path(runner_id): Path<u64>| async move {
+                deletions.lock().unwrap().push(runner_id);
+                StatusCode::NO_CONTENT
+            }),
+        );
+    (common::serve(app).await, deleted)
+}
+
+const NAME_EXISTS: &str = "Already exists - A runner with the name gha-11-1-7 already exists.";
+const INVALID_LABELS: &str = "Invalid label name: 'bad label'";
+/// Mentions "already exists" without being GitHub's duplicate name answer
+const LABEL_EXISTS: &str = "Invalid labels: label 'linux' already exists";
+
+async fn handle_queued_against(
+    message: &'static str,
+) -> (
+    Arc<MockCompute>,
+    Arc<Mutex<Vec<u64>>>,
+    Result<axum::response::Response, axum::response::ErrorResponse>,
+) {
+    let (api, deleted) = mock_github(message).await;
+    let compute = Arc::new(MockCompute::default());
+    let mut state = test_state(
+        compute.clone(),
+        Arc::default(),
+        spotted_arms::config::Config {
+            github_api_url: Some(api.clone()),
+            ..Default::default()
+        },
+    );
+    state.github_client = Arc::new(GithubClient::with_base_url(api));
+
+    let res = spotted_arms::webhook::handle_workflow_job_event(
+        workflow_job_headers(),
+        axum::extract::State(state),
+        axum_github_webhook_extract::GithubEvent(queued_event(&["self-hosted", "linux", "ARM64"])),
+    )
+    .await;
+    (compute, deleted, res)
+}
+
+#[tokio::test]
+async fn unprocessable_jit_requests_are_told_apart() {
+    let repo_url = |api: &reqwest::Url| api.join("repos/owner/repo").unwrap();
+
+    let (api, _) = mock_github(NAME_EXISTS).await;
+    let exists = GithubClient::with_base_url(api.clone())
+        .generate_jit_config(&repo_url(&api), "token", "gha-11-1-7", &[])
+        .await;
+    assert!(matches!(exists, Err(GithubError::RunnerExists(name)) if name == "gha-11-1-7"));
+
+    for message in [INVALID_LABELS, LABEL_EXISTS] {
+        let (api, _) = mock_github(message).await;
+        let invalid = GithubClient::with_base_url(api.clone())
+            .generate_jit_config(&repo_url(&api), "token", "gha-11-1-7", &[])
+            .await;
+        assert!(
+            matches!(invalid, Err(GithubError::Unprocessable(m)) if m == message),
+            "{message}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn stale_runner_is_replaced_before_the_instance_boots() {
+    let (compute, deleted, res) = handle_queued_against(NAME_EXISTS).await;
+
+    assert!(res.is_ok());
+    assert_eq!(*deleted.lock().unwrap(), [STALE_RUNNER_ID]);
+    let inserts = compute.inserts.lock().unwrap();
+    assert_eq!(inserts.len(), 1);
+    // the instance boots with the fresh registration's credentials
+    let metadata = inserts[0]
+        .instance
+        .as_ref()
+        .and_then(|i| i.metadata.as_ref())
+        .and_then(|m| m.items.as_ref())
+        .unwrap();
+    assert!(metadata.iter().any(|item| {
+        item.key.as_deref() == Some("JIT_CONFIG") && item.value.as_deref() == Some("encoded")
+    }));
+}
+
+#[tokio::test]
+async fn invalid_runner_labels_are_rejected_with_422() {
+    let (compute, _, res) = handle_queued_against(INVALID_LABELS).await;
+
+    let res = res.into_response();
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(body, format!("jit config rejected: {INVALID_LABELS}"));
+    assert!(compute.inserts.lock().unwrap().is_empty());
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use axum::http::StatusCode;
+use axum::routing::get;
+use spotted_arms::metadata::metadata_get;
+
+/// Serves `statuses` in turn, then `200`, counting requests
+async fn mock_metadata(statuses: Vec<u16>) -> (String, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counted = requests.clone();
+    let app = axum::Router::new().route(
+        "/project-id",
+        get(move || {
+            let n = counted.fetch_add(1, Ordering::Relaxed);
+            let status = statuses.get(n).copied().unwrap_or(200);
+            async move { (StatusCode::from_u16(status).unwrap(), "my-project") }
+        }),
+    );
+
+    let url = common::serve(app).await.join("project-id").unwrap();
+    (url.to_string(), requests)
+}
+
+#[tokio::test]
+async fn metadata_requests_retry_server_errors_only() {
+    let client = reqwest::Client::new();
+
+    let (url, requests) = mock_metadata(vec![503, 500]).await;
+    let response = metadata_get(&client, &url).await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(requests.load(Ordering::Relaxed), 3);
+
+    let (url, requests) = mock_metadata(vec![503, 503, 503]).await;
+    let response = metadata_get(&client, &url).await.unwrap();
+    assert_eq!(response.status(), 503);
+    assert_eq!(requests.load(Ordering::Relaxed), 3);
+
+    let (url, requests) = mock_metadata(vec![404]).await;
+    let response = metadata_get(&client, &url).await.unwrap();
+    assert_eq!(response.status(), 404);
+    assert_eq!(requests.load(Ordering::Relaxed), 1);
+}
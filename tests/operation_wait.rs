@@ -0,0 +1,120 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{MockCompute, operation};
+use gcloud_sdk::google_rest_apis::compute_v1;
+use gcloud_sdk::google_rest_apis::compute_v1::operation::Status;
+use spotted_arms::compute::{ComputeApi, ComputeError};
+
+#[tokio::test(start_paused = true)]
+async fn wait_for_operation_polls_until_done() {
+    let compute = MockCompute::default();
+    compute
+        .operations
+        .lock()
+        .unwrap()
+        .extend([Ok(operation(Status::Running)), Ok(operation(Status::Done))]);
+
+    let done = compute
+        .wait_for_operation(
+            "test-project",
+            operation(Status::Pending),
+            Duration::from_secs(30),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(done.status, Some(Status::Done));
+    let polls = compute.operation_polls.lock().unwrap();
+    assert_eq!(polls.len(), 2);
+    assert_eq!(polls[0].zone, "us-central1-a");
+    assert_eq!(polls[0].operation, "operation-1");
+}
+
+#[tokio::test(start_paused = true)]
+async fn wait_for_operation_times_out_while_still_running() {
+    let compute = MockCompute::default();
+    compute
+        .operations
+        .lock()
+        .unwrap()
+        .extend((0..100).map(|_| Ok(operation(Status::Running))));
+
+    let err = compute
+        .wait_for_operation(
+            "test-project",
+            operation(Status::Pending),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(err, ComputeError::OperationTimeout { ref operation } if operation == "operation-1"),
+        "{err:?}"
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn wait_for_operation_reports_operation_errors() {
+    let compute = MockCompute::default();
+    compute
+        .operations
+        .lock()
+        .unwrap()
+        .push_back(Ok(compute_v1::Operation {
+            error: Some(Box::new(compute_v1::OperationError {
+                errors: Some(vec![
+                    compute_v1::ManagedInstanceLastAttemptErrorsErrorsInner {
+                        code: Some("ZONE_RESOURCE_POOL_EXHAUSTED".to_string()),
+                        message: Some("out of capacity".to_string()),
+                        ..Default::default()
+                    },
+                ]),
+            })),
+            ..operation(Status::Done)
+        }));
+
+    let err = compute
+        .wait_for_operation(
+            "test-project",
+            operation(Status::Running),
+            Duration::from_secs(30),
+        )
+        .await
+        .unwrap_err();
+
+    // stockouts are told apart so creation can fall back to another zone
+    match err {
+        ComputeError::ResourceExhausted(message) => {
+            assert_eq!(message, "ZONE_RESOURCE_POOL_EXHAUSTED: out of capacity");
+        }
+        other => panic!("unexpected error: {other:?}"),
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn wait_for_operation_backs_off_when_rate_limited() {
+    let compute = MockCompute::default();
+    compute.operations.lock().unwrap().extend([
+        Err(ComputeError::RateLimited { retry_after: None }),
+        Err(ComputeError::RateLimited { retry_after: None }),
+        Ok(operation(Status::Done)),
+    ]);
+
+    let started = tokio::time::Instant::now();
+    let done = compute
+        .wait_for_operation(
+            "test-project",
+            operation(Status::Running),
+            Duration::from_secs(30),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(done.status, Some(Status::Done));
+    assert_eq!(compute.operation_polls.lock().unwrap().len(), 3);
+    // intervals double: 1s, 2s, then 4s
+    assert_eq!(started.elapsed(), Duration::from_secs(7));
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::MockCompute;
+use gcloud_sdk::google_rest_apis::compute_v1;
+use spotted_arms::instance::{CREATED_BY, CREATED_BY_LABEL};
+
+fn instance(name: &str, zone: &str, created: &str) -> compute_v1::Instance {
+    compute_v1::Instance {
+        name: Some(name.to_string()),
+        zone: Some(zone.to_string()),
+        creation_timestamp: Some(created.to_string()),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn instances_older_than_the_ttl_are_deleted() {
+    let compute = Arc::new(MockCompute::default());
+    compute.instances.lock().unwrap().extend([
+        instance("gha-1-1", "us-central1-a", "2024-05-01T02:00:00.000-07:00"),
+        instance("gha-2-2", "us-central1-b", "2024-05-01T11:30:00.000+02:00"),
+        instance("gha-3-3", "us-central1-c", "2024-05-01T14:00:00.000Z"),
+    ]);
+    let now = humantime::parse_rfc3339("2024-05-01T16:00:00Z").unwrap();
+
+    let reaped = spotted_arms::reaper::reap(
+        compute.as_ref(),
+        &["test-project".to_string()],
+        &["us-central1-a", "us-central1-b", "us-central1-c"],
+        Duration::from_secs(6 * 60 * 60),
+        now,
+    )
+    .await;
+
+    // created 7h and 6h30m ago; the other is 2h old
+    assert_eq!(reaped, vec!["gha-1-1", "gha-2-2"]);
+    let deletes = compute.deletes.lock().unwrap();
+    let deleted = deletes
+        .iter()
+        .map(|d| (d.zone.as_str(), d.instance.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        deleted,
+        vec![("us-central1-a", "gha-1-1"), ("us-central1-b", "gha-2-2")]
+    );
+
+    let lists = compute.lists.lock().unwrap();
+    assert_eq!(
+        lists[0].filter.as_deref(),
+        Some(format!("labels.{CREATED_BY_LABEL}={CREATED_BY}").as_str())
+    );
+}
+
+#[tokio::test]
+async fn preview_lists_candidates_without_deleting_them() {
+    use spotted_arms::clock::FakeClock;
+    use tower::ServiceExt;
+
+    let compute = Arc::new(MockCompute::default());
+    let mut old = instance(
+        "gha-1-1-1",
+        "us-central1-a",
+        "2024-05-01T02:00:00.000-07:00",
+    );
+    old.labels = Some(
+        [
+            (CREATED_BY_LABEL.to_string(), CREATED_BY.to_string()),
+            ("repository".to_string(), "owner-repo".to_string()),
+        ]
+        .into(),
+    );
+    compute.instances.lock().unwrap().extend([
+        old,
+        instance("gha-2-1-2", "us-central1-b", "2024-05-01T14:00:00.000Z"),
+    ]);
+    let mut state = common::test_state(compute.clone(), Arc::default(), Default::default());
+    state.clock = Arc::new(FakeClock::new(
+        humantime::parse_rfc3339("2024-05-01T16:00:00Z").unwrap(),
+    ));
+    let app = spotted_arms::server::create_app(state);
+
+    let res = app
+        .oneshot(
+            axum::http::Request::get("/reaper/preview?ttl=6h")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), axum::http::StatusCode::OK);
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let preview: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        preview["candidates"],
+        serde_json::json!([{
+            "project": "test-project",
+            "zone": "us-central1-a",
+            "instance": "gha-1-1-1",
+            "created": "2024-05-01T02:00:00.000-07:00",
+            "age_seconds": 7 * 60 * 60,
+            "labels": {"created-by": "spotted-arms", "repository": "owner-repo"},
+        }])
+    );
+    assert!(compute.deletes.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn preview_needs_a_ttl() {
+    use tower::ServiceExt;
+
+    let state = common::test_state(Arc::default(), Arc::default(), Default::default());
+    let res = spotted_arms::server::create_app(state)
+        .oneshot(
+            axum::http::Request::get("/reaper/preview")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.status(), axum::http::StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,132 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use spotted_arms::github::{
+    DeadLetter, DeadLetters, GithubApi, GithubError, JitConfig, RetryingGithub,
+};
+
+/// GitHub mock whose deregistrations fail a set number of times before succeeding
+struct FlakyGithub {
+    failures: Mutex<usize>,
+    attempts: Mutex<usize>,
+    /// Fails with bad credentials rather than a 5xx
+    permanent: bool,
+}
+
+impl FlakyGithub {
+    fn failing(failures: usize) -> Arc<Self> {
+        Arc::new(Self {
+            failures: Mutex::new(failures),
+            attempts: Mutex::new(0),
+            permanent: false,
+        })
+    }
+
+    fn rejecting() -> Arc<Self> {
+        Arc::new(Self {
+            failures: Mutex::new(usize::MAX),
+            attempts: Mutex::new(0),
+            permanent: true,
+        })
+    }
+}
+
+impl GithubApi for FlakyGithub {
+    fn generate_jit_config(
+        &self,
+        _repo_url: &reqwest::Url,
+        _github_token: &str,
+        _runner_name: &str,
+        _labels: &[String],
+    ) -> Pin<Box<dyn Future<Output = Result<JitConfig, GithubError>> + Send>> {
+        Box::pin(async { Err(GithubError::Other("unused".into())) })
+    }
+
+    fn delete_runner(
+        &self,
+        _repo_url: &reqwest::Url,
+        _github_token: &str,
+        _runner_id: u64,
+    ) -> Pin<Box<dyn Future<Output = Result<(), GithubError>> + Send>> {
+        *self.attempts.lock().unwrap() += 1;
+        let mut failures = self.failures.lock().unwrap();
+        let result = if *failures > 0 {
+            *failures -= 1;
+            Err(if self.permanent {
+                GithubError::Other("401 Unauthorized".to_string())
+            } else {
+                GithubError::Unavailable("502 Bad Gateway".to_string())
+            })
+        } else {
+            Ok(())
+        };
+        Box::pin(async move { result })
+    }
+
+    fn find_runner(
+        &self,
+        _repo_url: &reqwest::Url,
+        _github_token: &str,
+        _runner_name: &str,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<u64>, GithubError>> + Send>> {
+        Box::pin(async { Err(GithubError::Other("unused".into())) })
+    }
+}
+
+fn repo_url() -> reqwest::Url {
+    "https://api.github.com/repos/owner/repo".parse().unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn deregistration_retries_transient_failures() {
+    let inner = FlakyGithub::failing(2);
+    let dead_letters = Arc::new(DeadLetters::default());
+    let github = RetryingGithub::new(inner.clone(), 2, dead_letters.clone());
+
+    // the first failure is reported without waiting on the retries
+    let started = tokio::time::Instant::now();
+    assert!(github.delete_runner(&repo_url(), "token", 7).await.is_err());
+    assert_eq!(started.elapsed(), Duration::ZERO);
+    assert_eq!(*inner.attempts.lock().unwrap(), 1);
+
+    // backoff doubles: 1s, then 2s
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(*inner.attempts.lock().unwrap(), 2);
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(*inner.attempts.lock().unwrap(), 3);
+    assert!(dead_letters.runners().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn exhausted_deregistration_is_dead_lettered() {
+    let inner = FlakyGithub::failing(3);
+    let dead_letters = Arc::new(DeadLetters::default());
+    let github = RetryingGithub::new(inner.clone(), 2, dead_letters.clone());
+
+    assert!(github.delete_runner(&repo_url(), "token", 7).await.is_err());
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    assert_eq!(*inner.attempts.lock().unwrap(), 3);
+    assert_eq!(
+        dead_letters.runners(),
+        [DeadLetter {
+            repository: repo_url(),
+            runner_id: 7
+        }]
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn permanent_deregistration_failures_are_not_retried() {
+    let inner = FlakyGithub::rejecting();
+    let dead_letters = Arc::new(DeadLetters::default());
+    let github = RetryingGithub::new(inner.clone(), 2, dead_letters.clone());
+
+    assert!(github.delete_runner(&repo_url(), "token", 7).await.is_err());
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    assert_eq!(*inner.attempts.lock().unwrap(), 1);
+    assert_eq!(dead_letters.runners().len(), 1);
+}
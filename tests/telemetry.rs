@@ -0,0 +1,36 @@
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::{SpanData, SpanExporter};
+use std::sync::{Arc, Mutex};
+
+/// Keeps every exported span, including across shutdown, unlike `InMemorySpanExporter`
+#[derive(Clone, Debug, Default)]
+struct RecordingExporter {
+    spans: Arc<Mutex<Vec<SpanData>>>,
+}
+
+impl SpanExporter for RecordingExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        self.spans.lock().unwrap().extend(batch);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn tracing_guard_flushes_batched_spans_on_shutdown() {
+    use opentelemetry::trace::{Tracer, TracerProvider};
+    use opentelemetry_sdk::trace::{BatchSpanProcessor, SdkTracerProvider};
+    use spotted_arms::telemetry::TracingGuard;
+
+    let exporter = RecordingExporter::default();
+    let provider = SdkTracerProvider::builder()
+        .with_span_processor(BatchSpanProcessor::builder(exporter.clone()).build())
+        .build();
+    provider.tracer("test").in_span("last-webhook", |_| {});
+    assert!(exporter.spans.lock().unwrap().is_empty());
+
+    TracingGuard::new(provider).shutdown().await;
+
+    let spans = exporter.spans.lock().unwrap();
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0].name, "last-webhook");
+}
@@ -17,3 +17,11 @@ fn parse_queued_payload() {
         serde_json::from_str(payload_str).expect("valid webhook payload");
     assert_eq!(payload.action, WorkflowJobWebhookEventAction::Queued);
 }
+
+#[test]
+fn parse_org_scoped_queued_payload() {
+    let payload_str = include_str!("fixtures/org-queued-payload.json");
+    let payload: WorkflowJobWebhookEventPayload =
+        serde_json::from_str(payload_str).expect("valid webhook payload");
+    assert_eq!(payload.action, WorkflowJobWebhookEventAction::Queued);
+}